@@ -1,33 +1,112 @@
-use std::{any::TypeId, collections::HashMap};
+use std::{
+    any::TypeId,
+    collections::{BTreeMap, HashSet},
+    path::Path,
+    time::{Duration, Instant},
+};
 
-use katabatic_ecs::world::World;
-use katabatic_scene::scene::Scene;
-use katabatic_util::{error::KResult, lock::SharedLock};
+use katabatic_ecs::{
+    reflect::{Reflect, TypeRegistry},
+    world::World,
+};
+use katabatic_scene::{
+    hierarchy::Name,
+    relationship::{register_relationship, ChildOf, Relationship},
+    scene::Scene,
+    transform::Transform,
+};
+use katabatic_util::{
+    error::KResult,
+    kbail, kensure, kerror,
+    lock::{Lock, SharedLock},
+};
 
 use crate::{
-    plugin::Plugin,
+    event::{Event, Events},
+    executor::Executor,
+    plugin::{sort_plugins, Plugin, PluginGroup},
     runner::{Hook, NoOpRunner, Runner},
+    schedule::{CoreStage, IntoSystemConfig, Label, Schedule},
+    time::{FixedTime, Time},
 };
 
+/// Sent to ask the app to shut down. The runner stops at the end of the frame, and cleans the
+/// app up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AppExit;
+
+/// What an [`App`] does with an error returned by a hook or a system.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ErrorHandler {
+    /// Logs the error with [`log::error!`] and carries on with the rest of the frame.
+    Log,
+    /// Carries on with the rest of the frame as if nothing happened.
+    Ignore,
+    /// Stops the app, which is then cleaned up. [`App::run`] returns the error.
+    #[default]
+    Exit,
+}
+
+/// Identifies one of the scenes loaded into an [`App`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SceneId(u64);
+
+impl SceneId {
+    /// The scene every app starts with, returned by [`App::root_scene`].
+    pub const ROOT: Self = Self(0);
+}
+
 pub struct App {
     world: SharedLock<World>,
-    root_scene: SharedLock<Scene>,
-    plugins: HashMap<TypeId, Box<dyn Plugin>>,
+    scenes: BTreeMap<SceneId, SharedLock<Scene>>,
+    next_scene: u64,
+    // built plugins, in the order they were built
+    plugins: Vec<Box<dyn Plugin>>,
+    pending_plugins: Vec<Box<dyn Plugin>>,
+    finished_plugins: usize,
+    // the plugin that failed to set up, after which the app can't run
+    failed_plugin: Option<&'static str>,
+    ready_timeout: Duration,
+    // when setup started waiting for plugins to be ready
+    waiting_since: Option<Instant>,
+    started: bool,
+    cleaned_up: bool,
     runner: Option<Box<dyn Runner>>,
+    error_handler: ErrorHandler,
     hooks: Vec<Box<dyn Hook>>,
+    schedule: Lock<Schedule>,
 }
 
 impl Default for App {
     fn default() -> Self {
-        let world = SharedLock::new(World::new());
+        let mut registry = TypeRegistry::new();
+        registry.register::<Name>();
+        registry.register::<Transform>();
+        register_relationship::<ChildOf>(&mut registry);
+        let mut world = World::new();
+        world.insert_resource(registry);
+        let world = SharedLock::new(world);
         let root_scene = Scene::new(world.clone());
-        Self {
+        let mut app = Self {
             world,
-            root_scene: SharedLock::new(root_scene),
-            plugins: HashMap::new(),
+            scenes: BTreeMap::from([(SceneId::ROOT, SharedLock::new(root_scene))]),
+            next_scene: 1,
+            plugins: Vec::new(),
+            pending_plugins: Vec::new(),
+            finished_plugins: 0,
+            failed_plugin: None,
+            ready_timeout: Duration::from_secs(30),
+            waiting_since: None,
+            started: false,
+            cleaned_up: false,
             runner: Some(Box::<NoOpRunner>::default()),
+            error_handler: ErrorHandler::default(),
             hooks: Vec::new(),
-        }
+            schedule: Lock::new(Schedule::new()),
+        };
+        app.add_event::<AppExit>()
+            .expect("App::default(): CoreStage::First is missing");
+        app
     }
 }
 
@@ -41,59 +120,482 @@ impl App {
     }
 
     pub fn root_scene(&self) -> &SharedLock<Scene> {
-        &self.root_scene
+        &self.scenes[&SceneId::ROOT]
+    }
+
+    pub fn scene(&self, id: SceneId) -> Option<&SharedLock<Scene>> {
+        self.scenes.get(&id)
+    }
+
+    /// Every scene in the app, starting with the root scene.
+    pub fn scenes(&self) -> impl Iterator<Item = (SceneId, &SharedLock<Scene>)> + '_ {
+        self.scenes.iter().map(|(&id, scene)| (id, scene))
+    }
+
+    /// Adds a scene alongside the root scene. It must live in the app's world.
+    pub fn add_scene(&mut self, scene: Scene) -> KResult<SceneId> {
+        kensure!(
+            std::ptr::eq(&**scene.world(), &*self.world),
+            "App::add_scene(): the scene belongs to a different world"
+        );
+        let id = SceneId(self.next_scene);
+        self.next_scene += 1;
+        self.scenes.insert(id, SharedLock::new(scene));
+        Ok(id)
+    }
+
+    /// Loads a scene saved with [`Scene::save`] into the app's world, alongside the scenes
+    /// already loaded.
+    pub fn load_scene(&mut self, text: &str) -> KResult<SceneId> {
+        let scene = Scene::load(self.world.clone(), text)?;
+        self.add_scene(scene)
+    }
+
+    pub fn load_scene_from_file(&mut self, path: impl AsRef<Path>) -> KResult<SceneId> {
+        let scene = Scene::load_from_file(self.world.clone(), path)?;
+        self.add_scene(scene)
+    }
+
+    /// Removes a scene and destroys the entities of all of its nodes.
+    ///
+    /// Fails for the root scene, and for scenes still shared elsewhere.
+    pub fn unload_scene(&mut self, id: SceneId) -> KResult<()> {
+        kensure!(
+            id != SceneId::ROOT,
+            "App::unload_scene(): cannot unload the root scene"
+        );
+        let scene = self
+            .scenes
+            .get(&id)
+            .ok_or_else(|| kerror!(format!("App::unload_scene(): no scene {:?}", id)))?;
+        kensure!(
+            scene.strong_count() == 1,
+            format!("App::unload_scene(): scene {:?} is still in use", id)
+        );
+        let scene = self.scenes.remove(&id).unwrap();
+        scene.into_inner().unwrap().unload()
+    }
+
+    pub fn schedule(&self) -> &Lock<Schedule> {
+        &self.schedule
     }
 
     pub fn add_hook<T: Hook>(&mut self, hook: T) {
         self.hooks.push(Box::new(hook));
     }
 
+    /// Adds a system to the given stage of the app's [`Schedule`].
+    pub fn add_system<Marker>(
+        &mut self,
+        stage: impl Into<Label>,
+        system: impl IntoSystemConfig<Marker>,
+    ) -> KResult<()> {
+        self.schedule.get_mut().add_system(stage, system)
+    }
+
+    /// Adds the [`Events`] resource for `E`, along with a system in [`CoreStage::First`] that
+    /// drops events once they're two frames old. Does nothing if `E` was already added.
+    pub fn add_event<E: Event>(&mut self) -> KResult<()> {
+        if self.world.read().contains_resource::<Events<E>>() {
+            return Ok(());
+        }
+
+        self.world.write().insert_resource(Events::<E>::new());
+        self.add_system(
+            CoreStage::First,
+            |mut events: crate::system::ResMut<Events<E>>| events.update(),
+        )
+    }
+
+    /// Sends an event from outside of any system, e.g. from a [`Runner`].
+    ///
+    /// Fails if `E` wasn't added with [`add_event`](Self::add_event).
+    pub fn send_event<E: Event>(&self, event: E) -> KResult<()> {
+        self.world
+            .read()
+            .resource_mut::<Events<E>>()
+            .ok_or_else(|| {
+                kerror!(format!(
+                    "App::send_event(): event {} was not added to the app",
+                    std::any::type_name::<E>()
+                ))
+            })?
+            .send(event);
+        Ok(())
+    }
+
+    /// Registers `T` in the world's [`TypeRegistry`], so its fields can be read and written by
+    /// path with [`World::reflect_path`] and [`World::set_reflect_path`], and so scenes can save
+    /// and load it.
+    pub fn register_type<T: Reflect>(&mut self) {
+        self.with_type_registry(|registry| registry.register::<T>());
+    }
+
+    /// Registers `T` in the world's [`TypeRegistry`] as a relationship, so scenes can save and
+    /// load it.
+    pub fn register_relationship<T: Relationship + Reflect>(&mut self) {
+        self.with_type_registry(register_relationship::<T>);
+    }
+
+    fn with_type_registry(&mut self, f: impl FnOnce(&mut TypeRegistry)) {
+        let mut world = self.world.write();
+        if !world.contains_resource::<TypeRegistry>() {
+            world.insert_resource(TypeRegistry::new());
+        }
+        f(&mut world.resource_mut::<TypeRegistry>().unwrap());
+    }
+
+    /// Replaces the executor that runs the app's systems, e.g. with
+    /// [`Executor::single_threaded`] while debugging.
+    pub fn set_executor(&mut self, executor: Executor) {
+        self.schedule.get_mut().set_executor(executor);
+    }
+
+    pub fn add_stage_before(
+        &mut self,
+        existing: impl Into<Label>,
+        label: impl Into<Label>,
+    ) -> KResult<()> {
+        self.schedule.get_mut().add_stage_before(existing, label)
+    }
+
+    pub fn add_stage_after(
+        &mut self,
+        existing: impl Into<Label>,
+        label: impl Into<Label>,
+    ) -> KResult<()> {
+        self.schedule.get_mut().add_stage_after(existing, label)
+    }
+
+    /// What to do with errors from hooks and systems. See [`ErrorHandler`].
+    pub fn error_handler(&self) -> ErrorHandler {
+        self.error_handler
+    }
+
+    pub fn set_error_handler(&mut self, handler: ErrorHandler) {
+        self.error_handler = handler;
+    }
+
+    /// Passes an error from a hook or a system to the [error handler](Self::error_handler),
+    /// and returns it back only if it's fatal.
+    pub fn handle_error(&self, result: KResult<()>) -> KResult<()> {
+        let Err(error) = result else {
+            return Ok(());
+        };
+        match self.error_handler {
+            ErrorHandler::Log => {
+                log::error!("{}", error);
+                Ok(())
+            }
+            ErrorHandler::Ignore => Ok(()),
+            ErrorHandler::Exit => Err(error),
+        }
+    }
+
+    /// Whether anything has sent an [`AppExit`] event. Runners stop at the end of the frame it
+    /// was sent in.
+    pub fn exit_requested(&self) -> bool {
+        self.world
+            .read()
+            .resource::<Events<AppExit>>()
+            .is_some_and(|events| events.next_id() > 0)
+    }
+
+    /// Runs the init hooks, then the [`CoreStage::Startup`] stage.
     pub fn run_init_hooks(&self) -> KResult<()> {
         for hook in &self.hooks {
-            hook.init(self)?;
+            self.handle_error(hook.init(self))?;
         }
 
-        Ok(())
+        let result = self
+            .schedule
+            .write()
+            .run_stage(CoreStage::Startup, &self.world);
+        self.handle_error(result)
     }
 
+    /// Runs the update hooks, then every stage from [`CoreStage::First`] to [`CoreStage::Last`],
+    /// then ends the frame for change detection with [`World::clear_trackers`].
+    ///
+    /// [`CoreStage::FixedUpdate`] runs once for every step the [`FixedTime`] resource has
+    /// accumulated since the last frame, or just once if there is no `FixedTime`.
     pub fn run_update_hooks(&self) -> KResult<()> {
         for hook in &self.hooks {
-            hook.update(self)?;
+            self.handle_error(hook.update(self))?;
         }
 
+        let mut schedule = self.schedule.write();
+        let fixed_update = Label::from(CoreStage::FixedUpdate);
+        for label in schedule.stage_labels(CoreStage::First, CoreStage::Last)? {
+            if label == fixed_update {
+                self.run_fixed_update(&mut schedule)?;
+            } else {
+                let result = schedule.run_stage(label, &self.world);
+                self.handle_error(result)?;
+            }
+        }
+        drop(schedule);
+
+        self.world.write().clear_trackers();
         Ok(())
     }
 
+    fn run_fixed_update(&self, schedule: &mut Schedule) -> KResult<()> {
+        {
+            let world = self.world.read();
+            let Some(mut fixed_time) = world.resource_mut::<FixedTime>() else {
+                drop(world);
+                let result = schedule.run_stage(CoreStage::FixedUpdate, &self.world);
+                return self.handle_error(result);
+            };
+            let delta = world
+                .resource::<Time>()
+                .map(|time| time.delta())
+                .unwrap_or_default();
+            fixed_time.accumulate(delta);
+        }
+
+        while self.expend_fixed_step() {
+            let result = schedule.run_stage(CoreStage::FixedUpdate, &self.world);
+            self.handle_error(result)?;
+        }
+        Ok(())
+    }
+
+    fn expend_fixed_step(&self) -> bool {
+        self.world
+            .read()
+            .resource_mut::<FixedTime>()
+            .is_some_and(|mut fixed_time| fixed_time.expend())
+    }
+
+    /// Runs the render hooks, then the [`CoreStage::Render`] stage.
     pub fn run_render_hooks(&self) -> KResult<()> {
         for hook in &self.hooks {
-            hook.render(self)?;
+            self.handle_error(hook.render(self))?;
         }
 
-        Ok(())
+        let result = self
+            .schedule
+            .write()
+            .run_stage(CoreStage::Render, &self.world);
+        self.handle_error(result)
     }
 
+    /// Runs the cleanup hooks, then the [`CoreStage::Cleanup`] stage. All of them run even if
+    /// one fails, and the first fatal error is returned.
     pub fn run_cleanup_hooks(&self) -> KResult<()> {
+        let mut result = Ok(());
         for hook in &self.hooks {
-            hook.cleanup(self)?;
+            let hook = self.handle_error(hook.cleanup(self));
+            result = result.and(hook);
         }
 
-        Ok(())
+        let stage = self
+            .schedule
+            .write()
+            .run_stage(CoreStage::Cleanup, &self.world);
+        result.and(self.handle_error(stage))
     }
 
-    pub fn add_plugin<T: Plugin>(mut self, mut plugin: T) -> KResult<Self> {
-        plugin.build(&mut self)?;
-
-        self.plugins.insert(TypeId::of::<T>(), Box::new(plugin));
+    /// Adds a plugin, to be built when the app is [set up](Self::setup).
+    ///
+    /// Fails if a plugin of the same type was already added.
+    pub fn add_plugin<T: Plugin>(mut self, plugin: T) -> KResult<Self> {
+        self.insert_plugin(plugin)?;
+        Ok(self)
+    }
 
+    /// Adds every enabled plugin of a group, in the group's order.
+    ///
+    /// Fails if the group's builder was misused, or if one of its plugins was already added.
+    pub fn add_plugins(mut self, group: impl PluginGroup) -> KResult<Self> {
+        for plugin in group.build().finish()? {
+            self.insert_boxed_plugin(plugin)?;
+        }
         Ok(self)
     }
 
+    /// Adds a plugin from within another plugin's [`build`](Plugin::build). It's built after
+    /// the plugins that are already being built.
+    pub fn insert_plugin<T: Plugin>(&mut self, plugin: T) -> KResult<()> {
+        self.insert_boxed_plugin(Box::new(plugin))
+    }
+
+    fn insert_boxed_plugin(&mut self, plugin: Box<dyn Plugin>) -> KResult<()> {
+        let type_id = (*plugin).as_any().type_id();
+        kensure!(
+            self.plugins()
+                .all(|added| added.as_any().type_id() != type_id),
+            format!("App::add_plugin(): {} was already added", plugin.name())
+        );
+        self.pending_plugins.push(plugin);
+        Ok(())
+    }
+
     pub fn get_plugin<T: Plugin>(&self) -> Option<&T> {
-        if let Some(plugin) = self.plugins.get(&TypeId::of::<T>()) {
-            (**plugin).downcast_ref()
-        } else {
-            None
+        self.plugins().find_map(|plugin| plugin.downcast_ref())
+    }
+
+    fn plugins(&self) -> impl Iterator<Item = &dyn Plugin> + '_ {
+        self.plugins
+            .iter()
+            .chain(&self.pending_plugins)
+            .map(|plugin| &**plugin)
+    }
+
+    /// How long [`setup`](Self::setup) waits for plugins to be [`ready`](Plugin::ready) before
+    /// it fails. 30 seconds by default.
+    pub fn ready_timeout(&self) -> Duration {
+        self.ready_timeout
+    }
+
+    pub fn set_ready_timeout(&mut self, timeout: Duration) {
+        self.ready_timeout = timeout;
+    }
+
+    /// Whether every plugin added so far has been built and [finished](Plugin::finish).
+    pub fn is_set_up(&self) -> bool {
+        self.pending_plugins.is_empty() && self.finished_plugins == self.plugins.len()
+    }
+
+    /// Builds the plugins added so far, each after the plugins it depends on, then
+    /// [finishes](Plugin::finish) them if they're all [`ready`](Plugin::ready). Called by
+    /// [`run`](Self::run) and by every [`update`](Self::update) until
+    /// [`is_set_up`](Self::is_set_up).
+    ///
+    /// Doesn't wait for plugins that aren't ready yet: it's called again until they are.
+    ///
+    /// Fails if a plugin depends on one that was never added, if plugins depend on each
+    /// other in a cycle, or if some plugins still aren't ready after the
+    /// [timeout](Self::ready_timeout). Nothing is built in the first two cases, so adding the
+    /// missing plugins and calling it again works. Once a plugin fails to build or finish,
+    /// every later call fails too.
+    pub fn setup(&mut self) -> KResult<()> {
+        if let Some(name) = self.failed_plugin {
+            kbail!(format!(
+                "App::setup(): {} failed to set up, so the app can't run",
+                name
+            ));
         }
+
+        // plugins can add more plugins while they're built
+        while !self.pending_plugins.is_empty() {
+            let built = self
+                .plugins
+                .iter()
+                .map(|plugin| (**plugin).as_any().type_id())
+                .collect::<HashSet<TypeId>>();
+            let order = sort_plugins(&self.pending_plugins, &built)?;
+            let mut pending = std::mem::take(&mut self.pending_plugins)
+                .into_iter()
+                .map(Some)
+                .collect::<Vec<_>>();
+            for index in order {
+                let mut plugin = pending[index].take().unwrap();
+                if let Err(error) = plugin.build(self) {
+                    self.failed_plugin = Some(plugin.name());
+                    self.pending_plugins.extend(pending.into_iter().flatten());
+                    return Err(error);
+                }
+                self.plugins.push(plugin);
+            }
+        }
+        if self.is_set_up() {
+            return Ok(());
+        }
+
+        let not_ready = self.plugins[self.finished_plugins..]
+            .iter()
+            .filter(|plugin| !plugin.ready(self))
+            .map(|plugin| plugin.name())
+            .collect::<Vec<_>>();
+        if !not_ready.is_empty() {
+            let since = *self.waiting_since.get_or_insert_with(Instant::now);
+            kensure!(
+                since.elapsed() < self.ready_timeout,
+                format!(
+                    "App::setup(): plugins {:?} were still not ready after {:?}",
+                    not_ready, self.ready_timeout
+                )
+            );
+            return Ok(());
+        }
+        self.waiting_since = None;
+
+        let plugins = std::mem::take(&mut self.plugins);
+        let mut result = Ok(());
+        for plugin in &plugins[self.finished_plugins..] {
+            result = plugin.finish(self);
+            if result.is_err() {
+                self.failed_plugin = Some(plugin.name());
+                break;
+            }
+            self.finished_plugins += 1;
+        }
+        self.plugins = plugins;
+        result
+    }
+
+    /// [Sets up](Self::setup) the app, then runs the init hooks once it
+    /// [is set up](Self::is_set_up). Runners call this before the first frame. Calling it again
+    /// sets up plugins added since.
+    pub fn startup(&mut self) -> KResult<()> {
+        self.setup()?;
+        if !self.started && self.is_set_up() {
+            self.started = true;
+            self.run_init_hooks()?;
+        }
+        Ok(())
+    }
+
+    /// Whether the init hooks have run, which happens once every plugin is ready.
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Runs a single frame: the update hooks, then the render hooks. The first call also
+    /// [starts the app up](Self::startup), so tests can drive an app frame by frame without a
+    /// runner.
+    ///
+    /// Until every plugin is [`ready`](Plugin::ready), frames only poll the plugins and run
+    /// nothing else.
+    pub fn update(&mut self) -> KResult<()> {
+        self.startup()?;
+        if !self.started {
+            return Ok(());
+        }
+        self.run_update_hooks()?;
+        self.run_render_hooks()
+    }
+
+    /// Runs the cleanup hooks, then [cleans up](Plugin::cleanup) the plugins in the reverse
+    /// of the order they were built. Runners call this when the app shuts down; it does nothing
+    /// after the first call.
+    ///
+    /// The cleanup hooks and the [`CoreStage::Cleanup`] stage only run if the app was
+    /// [started](Self::is_started); otherwise only the plugins that were built clean up.
+    ///
+    /// Errors go through the [error handler](Self::error_handler), and the first fatal one is
+    /// returned once everything has been cleaned up.
+    pub fn cleanup(&mut self) -> KResult<()> {
+        if self.cleaned_up {
+            return Ok(());
+        }
+        self.cleaned_up = true;
+
+        let mut result = match self.started {
+            true => self.run_cleanup_hooks(),
+            false => Ok(()),
+        };
+        let plugins = std::mem::take(&mut self.plugins);
+        // every plugin gets to clean up, even after one fails
+        for plugin in plugins.iter().rev() {
+            let cleanup = plugin.cleanup(self);
+            result = result.and(self.handle_error(cleanup));
+        }
+        self.plugins = plugins;
+        result
     }
 
     pub fn set_runner<T>(&mut self, runner: T)
@@ -103,14 +605,162 @@ impl App {
         self.runner = Some(Box::new(runner));
     }
 
+    /// [Sets up](Self::setup) the app, then hands it over to its runner.
+    ///
+    /// Returns the first fatal error, once the app has been [cleaned up](Self::cleanup).
     pub fn run(mut self) -> KResult<()> {
-        let mut runner = self
-            .runner
-            .take()
-            .expect("App:run(): Runner not initialized");
+        if let Err(error) = self.setup() {
+            // still let the plugins that were built clean up after themselves
+            let _ = self.cleanup();
+            return Err(error);
+        }
 
-        runner.run(self)?;
+        let Some(mut runner) = self.runner.take() else {
+            let _ = self.cleanup();
+            kbail!("App::run(): no runner was set");
+        };
+        runner.run(self)
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::system::ResMut;
+
+    #[test]
+    fn test_load_and_unload_scenes() {
+        let mut app = App::new();
+        let text = {
+            let mut scene = app.root_scene().write();
+            let root = *scene.root();
+            let child = scene.create_node();
+            scene.add_child(root, child).unwrap();
+            scene.save().unwrap()
+        };
+
+        let a = app.load_scene(&text).unwrap();
+        let b = app.load_scene(&text).unwrap();
+        assert_eq!(app.scenes().count(), 3);
+        let entities = |app: &App, id| {
+            let scene = app.scene(id).unwrap().read();
+            scene
+                .graph()
+                .node_weights()
+                .map(|node| node.entity)
+                .collect::<Vec<_>>()
+        };
+        let a_entities = entities(&app, a);
+        assert_eq!(a_entities.len(), 2);
+
+        app.unload_scene(a).unwrap();
+        assert!(app.scene(a).is_none());
+        assert!(a_entities.iter().all(|&e| !app.world().read().is_alive(e)));
+        assert!(entities(&app, b)
+            .iter()
+            .all(|&e| app.world().read().is_alive(e)));
+
+        assert!(app.unload_scene(a).is_err());
+        assert!(app.unload_scene(SceneId::ROOT).is_err());
+        let other = Scene::new(SharedLock::new(World::new()));
+        assert!(app.add_scene(other).is_err());
+    }
+
+    #[derive(Debug, Default)]
+    struct Steps(u32);
+
+    #[test]
+    fn test_fixed_update() {
+        let mut app = App::new();
+        let startup = Instant::now();
+        {
+            let mut world = app.world().write();
+            world.insert_resource(Time::new(startup));
+            world.insert_resource(FixedTime::new(Duration::from_millis(10)).with_max_steps(4));
+            world.insert_resource(Steps::default());
+        }
+        app.add_system(CoreStage::FixedUpdate, |mut steps: ResMut<Steps>| {
+            steps.0 += 1
+        })
+        .unwrap();
+
+        let frame = |millis| {
+            app.world()
+                .read()
+                .resource_mut::<Time>()
+                .unwrap()
+                .update_with_instant(startup + Duration::from_millis(millis));
+            app.run_update_hooks().unwrap();
+            let world = app.world().read();
+            let steps = std::mem::take(&mut world.resource_mut::<Steps>().unwrap().0);
+            let alpha = world.resource::<FixedTime>().unwrap().alpha();
+            (steps, alpha)
+        };
+
+        assert_eq!(frame(5), (0, 0.5));
+        assert_eq!(frame(25), (2, 0.5));
+        // a long frame only catches up on `max_steps`
+        assert_eq!(frame(1025), (4, 0.5));
+        assert_eq!(frame(1030), (1, 0.0));
+    }
+
+    /// Counts its own cleanups in `.0`, and cleanup systems in `.1`.
+    struct Cleanups(Arc<[AtomicUsize; 2]>);
+
+    impl Plugin for Cleanups {
+        fn build(&mut self, app: &mut App) -> KResult<()> {
+            let counts = self.0.clone();
+            app.add_system(CoreStage::Cleanup, move || {
+                counts[1].fetch_add(1, Ordering::SeqCst);
+            })
+        }
+
+        fn cleanup(&self, _app: &mut App) -> KResult<()> {
+            self.0[0].fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct FailingBuild;
+
+    impl Plugin for FailingBuild {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            kbail!("build failed")
+        }
+    }
+
+    #[test]
+    fn test_run_cleans_up_built_plugins() {
+        let load = |counts: &[AtomicUsize; 2]| counts.each_ref().map(|c| c.load(Ordering::SeqCst));
+
+        let counts = Arc::new(<[AtomicUsize; 2]>::default());
+        let mut app = App::new().add_plugin(Cleanups(counts.clone())).unwrap();
+        app.runner = None;
+        assert!(app.run().is_err());
+        // never started, so only the plugin cleans up
+        assert_eq!(load(&counts), [1, 0]);
+
+        let counts = Arc::new(<[AtomicUsize; 2]>::default());
+        let app = App::new()
+            .add_plugin(Cleanups(counts.clone()))
+            .unwrap()
+            .add_plugin(FailingBuild)
+            .unwrap();
+        assert!(app.run().is_err());
+        assert_eq!(load(&counts), [1, 0]);
+
+        let counts = Arc::new(<[AtomicUsize; 2]>::default());
+        let mut app = App::new().add_plugin(Cleanups(counts.clone())).unwrap();
+        app.update().unwrap();
+        app.cleanup().unwrap();
+        assert_eq!(load(&counts), [1, 1]);
     }
 }
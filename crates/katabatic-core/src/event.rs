@@ -0,0 +1,292 @@
+use std::any::TypeId;
+
+use katabatic_ecs::{access::Access, world::World};
+use katabatic_util::lock::{MapRead, MapWrite};
+
+use crate::system::{SystemMeta, SystemParam};
+
+/// A message that systems can send to each other through [`Events`].
+pub trait Event: Send + Sync + 'static {}
+
+impl<T: Send + Sync + 'static> Event for T {}
+
+/// A resource holding the events of type `E` sent during the current and previous frame.
+///
+/// Events are double buffered: [`update`](Self::update), which runs at the start of every frame
+/// for events added with [`App::add_event`](crate::app::App::add_event), drops the events sent
+/// two frames ago. A reader that reads at least once a frame never misses an event, and never
+/// sees one twice.
+///
+/// Every event gets an id, counting up from the first event ever sent, so readers only need to
+/// remember the id of the next event they haven't read.
+pub struct Events<E: Event> {
+    previous: Vec<E>,
+    current: Vec<E>,
+    // the id of `previous[0]`
+    previous_start: usize,
+}
+
+impl<E: Event> Default for Events<E> {
+    fn default() -> Self {
+        Self {
+            previous: Vec::new(),
+            current: Vec::new(),
+            previous_start: 0,
+        }
+    }
+}
+
+impl<E: Event> Events<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn send(&mut self, event: E) {
+        self.current.push(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = E>) {
+        self.current.extend(events);
+    }
+
+    /// Drops the previous frame's events, and makes the current frame's the previous ones.
+    pub fn update(&mut self) {
+        self.previous_start += self.previous.len();
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    /// Drops every event, read or not.
+    pub fn clear(&mut self) {
+        self.update();
+        self.update();
+    }
+
+    /// The id the next event sent will get.
+    pub fn next_id(&self) -> usize {
+        self.previous_start + self.previous.len() + self.current.len()
+    }
+
+    /// The number of events held, from both frames.
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns every event with an id of at least `cursor`, oldest first, and moves `cursor`
+    /// past them.
+    pub fn read_from<'a>(&'a self, cursor: &mut usize) -> impl Iterator<Item = &'a E> + 'a {
+        let skip = cursor.saturating_sub(self.previous_start);
+        *cursor = self.next_id();
+        self.previous.iter().chain(&self.current).skip(skip)
+    }
+
+    /// The number of events with an id of at least `cursor`.
+    pub fn len_from(&self, cursor: usize) -> usize {
+        self.len()
+            .saturating_sub(cursor.saturating_sub(self.previous_start))
+    }
+}
+
+/// Sends events of type `E`.
+///
+/// # Panics
+/// A system with this parameter panics if `E` wasn't added with
+/// [`App::add_event`](crate::app::App::add_event).
+pub struct EventWriter<'w, E: Event> {
+    events: MapWrite<'w, Events<E>>,
+}
+
+impl<'w, E: Event> EventWriter<'w, E> {
+    pub fn send(&mut self, event: E) {
+        self.events.send(event);
+    }
+
+    pub fn send_batch(&mut self, events: impl IntoIterator<Item = E>) {
+        self.events.send_batch(events);
+    }
+}
+
+impl<'a, E: Event> SystemParam for EventWriter<'a, E> {
+    type State = ();
+    type Item<'w, 's> = EventWriter<'w, E>;
+
+    fn init_state(_world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let mut access = Access::new();
+        access.add_resource_write(TypeId::of::<Events<E>>());
+        meta.add_access(&access);
+    }
+
+    fn get_param<'w, 's>(
+        _state: &'s mut Self::State,
+        meta: &SystemMeta,
+        world: &'w World,
+    ) -> Self::Item<'w, 's> {
+        EventWriter {
+            events: world
+                .resource_mut::<Events<E>>()
+                .unwrap_or_else(|| panic_missing::<E>(meta)),
+        }
+    }
+}
+
+/// Reads events of type `E`. Each reader keeps track of which events it has already read.
+///
+/// # Panics
+/// A system with this parameter panics if `E` wasn't added with
+/// [`App::add_event`](crate::app::App::add_event).
+pub struct EventReader<'w, 's, E: Event> {
+    events: MapRead<'w, Events<E>>,
+    cursor: &'s mut usize,
+}
+
+impl<'w, 's, E: Event> EventReader<'w, 's, E> {
+    /// Returns the events sent since the last read, oldest first, and marks them as read.
+    pub fn read(&mut self) -> impl Iterator<Item = &E> + '_ {
+        self.events.read_from(self.cursor)
+    }
+
+    /// The number of unread events.
+    pub fn len(&self) -> usize {
+        self.events.len_from(*self.cursor)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Marks every event so far as read.
+    pub fn clear(&mut self) {
+        *self.cursor = self.events.next_id();
+    }
+}
+
+impl<'a, 'b, E: Event> SystemParam for EventReader<'a, 'b, E> {
+    type State = usize;
+    type Item<'w, 's> = EventReader<'w, 's, E>;
+
+    fn init_state(_world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let mut access = Access::new();
+        access.add_resource_read(TypeId::of::<Events<E>>());
+        meta.add_access(&access);
+        0
+    }
+
+    fn get_param<'w, 's>(
+        state: &'s mut Self::State,
+        meta: &SystemMeta,
+        world: &'w World,
+    ) -> Self::Item<'w, 's> {
+        EventReader {
+            events: world
+                .resource::<Events<E>>()
+                .unwrap_or_else(|| panic_missing::<E>(meta)),
+            cursor: state,
+        }
+    }
+}
+
+fn panic_missing<E: Event>(meta: &SystemMeta) -> ! {
+    panic!(
+        "System {}: event {} was not added to the app",
+        meta.name(),
+        std::any::type_name::<E>()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_util::lock::Lock;
+
+    use super::*;
+    use crate::{
+        schedule::{CoreStage, Schedule},
+        system::{IntoSystem, ResMut, System},
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct Ping(u32);
+
+    #[test]
+    fn test_events_double_buffering() {
+        let mut events = Events::new();
+        let mut cursor = 0;
+        let mut late_cursor = 0;
+
+        events.send(Ping(0));
+        events.send(Ping(1));
+        assert_eq!(
+            events.read_from(&mut cursor).collect::<Vec<_>>(),
+            vec![&Ping(0), &Ping(1)]
+        );
+
+        events.update();
+        events.send(Ping(2));
+        assert_eq!(events.len_from(cursor), 1);
+        assert_eq!(
+            events.read_from(&mut cursor).collect::<Vec<_>>(),
+            vec![&Ping(2)]
+        );
+
+        // a reader that fell two frames behind only sees what's left
+        events.update();
+        assert_eq!(
+            events.read_from(&mut late_cursor).collect::<Vec<_>>(),
+            vec![&Ping(2)]
+        );
+        events.update();
+        assert!(events.is_empty());
+        assert_eq!(events.read_from(&mut cursor).count(), 0);
+    }
+
+    #[test]
+    fn test_event_systems() {
+        let world = Lock::new(World::new());
+        world.write().insert_resource(Events::<Ping>::new());
+        world.write().insert_resource(Vec::<u32>::new());
+
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(CoreStage::First, |mut events: ResMut<Events<Ping>>| {
+                events.update()
+            })
+            .unwrap();
+        schedule
+            .add_system(CoreStage::Update, |mut writer: EventWriter<Ping>| {
+                writer.send(Ping(1));
+                writer.send(Ping(2));
+            })
+            .unwrap();
+        schedule
+            .add_system(
+                CoreStage::PostUpdate,
+                |mut reader: EventReader<Ping>, mut seen: ResMut<Vec<u32>>| {
+                    seen.extend(reader.read().map(|ping| ping.0));
+                },
+            )
+            .unwrap();
+
+        for _ in 0..3 {
+            schedule
+                .run_range(CoreStage::First, CoreStage::Last, &world)
+                .unwrap();
+        }
+
+        assert_eq!(
+            *world.read().resource::<Vec<u32>>().unwrap(),
+            vec![1, 2, 1, 2, 1, 2]
+        );
+        assert_eq!(world.read().resource::<Events<Ping>>().unwrap().len(), 4);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_missing_events() {
+        let mut world = World::new();
+        let mut system = (|_reader: EventReader<Ping>| {}).into_system();
+        system.initialize(&mut world);
+        let _ = system.run(&world);
+    }
+}
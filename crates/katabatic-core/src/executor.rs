@@ -0,0 +1,391 @@
+use std::{
+    any::Any,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, Sender},
+        Mutex,
+    },
+};
+
+use katabatic_ecs::world::World;
+use katabatic_util::{
+    error::{KError, KResult},
+    lock::Lock,
+};
+use rayon::{Scope, ThreadPool, ThreadPoolBuilder};
+
+use crate::schedule::SystemConfig;
+
+/// How an [`Executor`] runs the systems of a stage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExecutorKind {
+    /// Runs systems whose data access doesn't conflict at the same time, on a thread pool.
+    #[default]
+    MultiThreaded,
+    /// Runs every system on the calling thread, one after another. Useful for debugging.
+    SingleThreaded,
+}
+
+/// Runs the systems of a [`Stage`](crate::schedule::Stage).
+///
+/// Whatever the kind, a system never starts before the systems it's ordered after have
+/// finished, and two systems with conflicting access always run in the stage's order, so
+/// both kinds give the same results. Systems that aren't [`Send`](crate::system::System::is_send)
+/// always run on the thread that called the executor.
+pub struct Executor {
+    kind: ExecutorKind,
+    pool: Option<ThreadPool>,
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new(ExecutorKind::default())
+    }
+}
+
+impl Executor {
+    /// Creates an executor of the given kind. A multi-threaded executor gets one thread per core.
+    pub fn new(kind: ExecutorKind) -> Self {
+        match kind {
+            ExecutorKind::MultiThreaded => Self::multi_threaded(0),
+            ExecutorKind::SingleThreaded => Self::single_threaded(),
+        }
+    }
+
+    /// Creates a multi-threaded executor with `num_threads` threads, or one per core if `0`.
+    pub fn multi_threaded(num_threads: usize) -> Self {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(num_threads)
+            .thread_name(|index| format!("katabatic-worker-{}", index))
+            .build()
+            .expect("Executor::multi_threaded(): Error creating thread pool");
+
+        Self {
+            kind: ExecutorKind::MultiThreaded,
+            pool: Some(pool),
+        }
+    }
+
+    pub fn single_threaded() -> Self {
+        Self {
+            kind: ExecutorKind::SingleThreaded,
+            pool: None,
+        }
+    }
+
+    pub fn kind(&self) -> ExecutorKind {
+        self.kind
+    }
+
+    pub fn num_threads(&self) -> usize {
+        self.pool
+            .as_ref()
+            .map_or(1, |pool| pool.current_num_threads())
+    }
+
+    /// Runs `systems` once each.
+    ///
+    /// `order` must be a topological order of `dependencies`, which holds the indices of the
+    /// systems each system has to wait for. Skips every system that hasn't started yet after the
+    /// first error.
+    pub(crate) fn run(
+        &self,
+        systems: &mut [SystemConfig],
+        order: &[usize],
+        dependencies: &[Vec<usize>],
+        world: &World,
+    ) -> KResult<()> {
+        let Some(pool) = &self.pool else {
+            for &index in order {
+                systems[index].system_mut().run(world)?;
+            }
+            return Ok(());
+        };
+
+        let mut dependents = vec![Vec::new(); systems.len()];
+        for (index, dependencies) in dependencies.iter().enumerate() {
+            for &dependency in dependencies {
+                dependents[dependency].push(index);
+            }
+        }
+
+        // decide what's ready up front, since spawned systems start counting down right away
+        let ready = order
+            .iter()
+            .copied()
+            .filter(|&index| dependencies[index].is_empty())
+            .collect::<Vec<_>>();
+
+        let is_send = systems
+            .iter()
+            .map(|config| config.system().is_send())
+            .collect::<Vec<_>>();
+        let non_send_count = is_send.iter().filter(|is_send| !**is_send).count();
+        let (main_thread, main_thread_queue) = mpsc::channel();
+
+        let context = RunContext {
+            world,
+            systems: systems.iter_mut().map(Lock::new).collect(),
+            is_send,
+            dependents,
+            remaining: dependencies
+                .iter()
+                .map(|dependencies| AtomicUsize::new(dependencies.len()))
+                .collect(),
+            main_thread,
+            error: Lock::new(None),
+            panic: Mutex::new(None),
+        };
+
+        pool.in_place_scope(|scope| {
+            for index in ready {
+                context.dispatch(scope, index);
+            }
+
+            // every system is dispatched exactly once, even after a failure, so this can't hang
+            for _ in 0..non_send_count {
+                let index = main_thread_queue
+                    .recv()
+                    .expect("Executor::run(): Main thread queue disconnected");
+                context.run_system(index);
+                context.finish(scope, index);
+            }
+        });
+
+        if let Some(panic) = context
+            .panic
+            .into_inner()
+            .unwrap_or_else(|e| e.into_inner())
+        {
+            std::panic::resume_unwind(panic);
+        }
+
+        match context.error.into_inner() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Executor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Executor")
+            .field("kind", &self.kind)
+            .field("num_threads", &self.num_threads())
+            .finish()
+    }
+}
+
+struct RunContext<'a> {
+    world: &'a World,
+    // each lock is only ever taken by the one task running that system
+    systems: Vec<Lock<&'a mut SystemConfig>>,
+    is_send: Vec<bool>,
+    dependents: Vec<Vec<usize>>,
+    remaining: Vec<AtomicUsize>,
+    main_thread: Sender<usize>,
+    error: Lock<Option<KError>>,
+    // panic payloads aren't `Sync`, so they need a mutex rather than a `Lock`
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl<'a> RunContext<'a> {
+    /// Runs the system on the thread pool, or hands it to the main thread if it isn't `Send`.
+    fn dispatch<'scope>(&'scope self, scope: &Scope<'scope>, index: usize) {
+        if self.is_send[index] {
+            scope.spawn(move |scope| {
+                self.run_system(index);
+                self.finish(scope, index);
+            });
+        } else {
+            self.main_thread
+                .send(index)
+                .expect("Executor::run(): Main thread queue disconnected");
+        }
+    }
+
+    fn run_system(&self, index: usize) {
+        if self.error.read().is_some() || self.has_panicked() {
+            return;
+        }
+
+        let mut system = self.systems[index].write();
+        match std::panic::catch_unwind(AssertUnwindSafe(|| system.system_mut().run(self.world))) {
+            Ok(Ok(())) => {}
+            Ok(Err(error)) => {
+                let mut first = self.error.write();
+                if first.is_none() {
+                    *first = Some(error);
+                }
+            }
+            Err(panic) => {
+                let mut first = self.panic.lock().unwrap_or_else(|e| e.into_inner());
+                if first.is_none() {
+                    *first = Some(panic);
+                }
+            }
+        }
+    }
+
+    fn has_panicked(&self) -> bool {
+        self.panic.lock().map_or(true, |panic| panic.is_some())
+    }
+
+    /// Dispatches every system that was only waiting on the system at `index`.
+    fn finish<'scope>(&'scope self, scope: &Scope<'scope>, index: usize) {
+        for &dependent in &self.dependents[index] {
+            if self.remaining[dependent].fetch_sub(1, Ordering::AcqRel) == 1 {
+                self.dispatch(scope, dependent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicUsize, Arc},
+        thread,
+        time::Duration,
+    };
+
+    use katabatic_ecs::query::Query;
+
+    use crate::schedule::{CoreStage, IntoSystemConfig, Schedule};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Counter(u32);
+
+    #[derive(Debug, Default)]
+    struct Log(Vec<u32>);
+
+    #[test]
+    fn test_parallel_systems() {
+        let world = Lock::new(World::new());
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+
+        let mut schedule = Schedule::with_executor(Executor::multi_threaded(2));
+        for _ in 0..2 {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            schedule
+                .add_system(CoreStage::Update, move || {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_running.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(50));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                })
+                .unwrap();
+        }
+
+        schedule.run_stage(CoreStage::Update, &world).unwrap();
+
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_conflicting_systems_keep_order() {
+        fn run(executor: Executor) -> Vec<u32> {
+            let world = Lock::new(World::new());
+            world.write().spawn((Counter::default(), Log::default()));
+
+            let mut schedule = Schedule::with_executor(executor);
+            for step in 0..8 {
+                schedule
+                    .add_system(CoreStage::Update, move |query: Query<&mut Log>| {
+                        for mut log in query.iter() {
+                            log.0.push(step);
+                        }
+                    })
+                    .unwrap();
+            }
+            schedule
+                .add_system(
+                    CoreStage::Update,
+                    (|query: Query<&mut Counter>| {
+                        for mut counter in query.iter() {
+                            counter.0 += 1;
+                        }
+                    })
+                    .before("first"),
+                )
+                .unwrap();
+            schedule
+                .add_system(CoreStage::Update, (|| {}).label("first"))
+                .unwrap();
+
+            schedule.run_stage(CoreStage::Update, &world).unwrap();
+
+            let world = world.read();
+            let query = world.query::<(&Counter, &Log)>();
+            let (counter, log) = query.iter().next().unwrap();
+            assert_eq!(counter.0, 1);
+            log.0.clone()
+        }
+
+        let expected = (0..8).collect::<Vec<_>>();
+        assert_eq!(run(Executor::single_threaded()), expected);
+        for _ in 0..10 {
+            assert_eq!(run(Executor::multi_threaded(4)), expected);
+        }
+    }
+
+    #[test]
+    fn test_non_send_on_main_thread() {
+        use std::{rc::Rc, thread::ThreadId};
+
+        use crate::system::NonSendMut;
+
+        let world = Lock::new(World::new());
+        world
+            .write()
+            .insert_non_send_resource(Rc::new(Lock::new(None::<ThreadId>)));
+
+        let mut schedule = Schedule::with_executor(Executor::multi_threaded(2));
+        schedule
+            .add_system(CoreStage::Update, |thread: NonSendMut<Rc<Lock<_>>>| {
+                *thread.write() = Some(thread::current().id());
+            })
+            .unwrap();
+        schedule.add_system(CoreStage::Update, || {}).unwrap();
+
+        schedule.run_stage(CoreStage::Update, &world).unwrap();
+
+        let world = world.read();
+        let thread = world
+            .non_send_resource::<Rc<Lock<Option<ThreadId>>>>()
+            .unwrap();
+        assert_eq!(*thread.read(), Some(thread::current().id()));
+    }
+
+    #[test]
+    fn test_error_stops_dependents() {
+        let world = Lock::new(World::new());
+        let ran = Arc::new(AtomicUsize::new(0));
+
+        let mut schedule = Schedule::with_executor(Executor::multi_threaded(2));
+        schedule
+            .add_system(
+                CoreStage::Update,
+                (|| -> KResult<()> { katabatic_util::kbail!("failed") }).label("failing"),
+            )
+            .unwrap();
+        let ran_clone = ran.clone();
+        schedule
+            .add_system(
+                CoreStage::Update,
+                (move || {
+                    ran_clone.fetch_add(1, Ordering::SeqCst);
+                })
+                .after("failing"),
+            )
+            .unwrap();
+
+        assert!(schedule.run_stage(CoreStage::Update, &world).is_err());
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{collections::HashSet, hash::Hash};
+
+/// The state of a set of buttons, e.g. the keys of a keyboard: which are held down, and which
+/// were pressed or released this frame.
+///
+/// Kept up to date by an input plugin, which calls [`clear`](Self::clear) at the start of every
+/// frame.
+#[derive(Debug, Clone)]
+pub struct Input<T: Copy + Eq + Hash + Send + Sync + 'static> {
+    pressed: HashSet<T>,
+    just_pressed: HashSet<T>,
+    just_released: HashSet<T>,
+}
+
+impl<T: Copy + Eq + Hash + Send + Sync + 'static> Default for Input<T> {
+    fn default() -> Self {
+        Self {
+            pressed: HashSet::new(),
+            just_pressed: HashSet::new(),
+            just_released: HashSet::new(),
+        }
+    }
+}
+
+impl<T: Copy + Eq + Hash + Send + Sync + 'static> Input<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, button: T) {
+        // held keys repeat, but were only just pressed the first time
+        if self.pressed.insert(button) {
+            self.just_pressed.insert(button);
+        }
+    }
+
+    pub fn release(&mut self, button: T) {
+        if self.pressed.remove(&button) {
+            self.just_released.insert(button);
+        }
+    }
+
+    pub fn pressed(&self, button: T) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    pub fn just_pressed(&self, button: T) -> bool {
+        self.just_pressed.contains(&button)
+    }
+
+    pub fn just_released(&self, button: T) -> bool {
+        self.just_released.contains(&button)
+    }
+
+    pub fn get_pressed(&self) -> impl Iterator<Item = &T> + '_ {
+        self.pressed.iter()
+    }
+
+    /// Forgets which buttons were just pressed or released, for a new frame.
+    pub fn clear(&mut self) {
+        self.just_pressed.clear();
+        self.just_released.clear();
+    }
+
+    /// Releases every button without recording it, e.g. when the window loses focus.
+    pub fn reset_all(&mut self) {
+        self.pressed.clear();
+        self.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input() {
+        let mut input = Input::new();
+        input.press('a');
+        input.press('a');
+        assert!(input.pressed('a') && input.just_pressed('a'));
+
+        input.clear();
+        input.press('a');
+        assert!(input.pressed('a') && !input.just_pressed('a'));
+
+        input.release('a');
+        assert!(!input.pressed('a') && input.just_released('a'));
+        input.clear();
+        assert!(!input.just_released('a'));
+    }
+}
@@ -1,3 +1,11 @@
 pub mod app;
+pub mod event;
+pub mod executor;
+pub mod input;
+pub mod logging;
 pub mod plugin;
 pub mod runner;
+pub mod schedule;
+pub mod system;
+pub mod time;
+pub mod transform;
@@ -0,0 +1,49 @@
+use katabatic_util::error::KResult;
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{app::App, plugin::Plugin};
+
+/// Prints [`log`] records to stderr.
+///
+/// Does nothing if another logger was installed first, except for setting the maximum level.
+pub struct LogPlugin {
+    pub level: LevelFilter,
+}
+
+impl Default for LogPlugin {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+        }
+    }
+}
+
+impl Plugin for LogPlugin {
+    fn build(&mut self, _app: &mut App) -> KResult<()> {
+        // fails if a logger is already installed, which is fine
+        let _ = log::set_logger(&StderrLogger);
+        log::set_max_level(self.level);
+        Ok(())
+    }
+}
+
+struct StderrLogger;
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "[{:<5} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
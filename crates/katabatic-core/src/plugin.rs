@@ -1,21 +1,49 @@
+use std::{
+    any::{type_name, TypeId},
+    collections::HashSet,
+};
+
 use downcast_rs::{impl_downcast, Downcast};
-use katabatic_util::error::KResult;
+use katabatic_util::{
+    error::{KError, KResult},
+    kbail, kensure, kerror,
+};
 
-use crate::app::App;
+use crate::{app::App, logging::LogPlugin, time::TimePlugin, transform::TransformPlugin};
 
+/// Adds functionality to an [`App`].
+///
+/// When the app is [set up](App::setup), plugins are built after the plugins they depend on,
+/// then polled once a frame until they're all [`ready`](Plugin::ready), then
+/// [finished](Plugin::finish) in the same order. They're cleaned up in reverse order when the
+/// app shuts down.
 pub trait Plugin: Downcast {
     fn build(&mut self, app: &mut App) -> KResult<()>;
 
+    /// The plugins that have to be built before this one.
+    fn dependencies(&self) -> Vec<Dependency> {
+        Vec::new()
+    }
+
+    /// Used in errors about the plugin.
+    fn name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+
+    /// Whether the plugin is done with any work started in [`build`](Plugin::build), e.g. on
+    /// another thread. Must not block: it's polled again on the next frame.
     #[allow(unused)]
     fn ready(&self, app: &App) -> bool {
         true
     }
 
+    /// Called once every plugin has been built and is ready.
     #[allow(unused)]
     fn finish(&self, app: &mut App) -> KResult<()> {
         Ok(())
     }
 
+    /// Called when the app shuts down.
     #[allow(unused)]
     fn cleanup(&self, app: &mut App) -> KResult<()> {
         Ok(())
@@ -23,3 +51,588 @@ pub trait Plugin: Downcast {
 }
 
 impl_downcast!(Plugin);
+
+/// A plugin that another plugin depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dependency {
+    type_id: TypeId,
+    name: &'static str,
+}
+
+impl Dependency {
+    pub fn on<T: Plugin>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            name: type_name::<T>(),
+        }
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+/// A set of plugins added together with [`App::add_plugins`].
+pub trait PluginGroup: Sized {
+    fn build(self) -> PluginGroupBuilder;
+}
+
+struct GroupMember {
+    type_id: TypeId,
+    plugin: Box<dyn Plugin>,
+    enabled: bool,
+}
+
+/// The plugins of a [`PluginGroup`], in the order they'll be added to the app.
+///
+/// Members can be disabled, replaced or moved before the group is added. Mistakes like naming a
+/// plugin that isn't in the group are reported by [`App::add_plugins`].
+pub struct PluginGroupBuilder {
+    group: &'static str,
+    members: Vec<GroupMember>,
+    error: Option<KError>,
+}
+
+impl PluginGroupBuilder {
+    pub fn start<G: PluginGroup>() -> Self {
+        Self {
+            group: type_name::<G>(),
+            members: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Adds a plugin at the end of the group, replacing any plugin of the same type.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<T: Plugin>(mut self, plugin: T) -> Self {
+        self.remove::<T>();
+        self.members.push(GroupMember::new(plugin));
+        self
+    }
+
+    /// Adds a plugin right before `Target`, replacing any plugin of the same type.
+    pub fn add_before<Target: Plugin, T: Plugin>(mut self, plugin: T) -> Self {
+        self.remove::<T>();
+        if let Some(index) = self.find::<Target>("add_before") {
+            self.members.insert(index, GroupMember::new(plugin));
+        }
+        self
+    }
+
+    /// Adds a plugin right after `Target`, replacing any plugin of the same type.
+    pub fn add_after<Target: Plugin, T: Plugin>(mut self, plugin: T) -> Self {
+        self.remove::<T>();
+        if let Some(index) = self.find::<Target>("add_after") {
+            self.members.insert(index + 1, GroupMember::new(plugin));
+        }
+        self
+    }
+
+    /// Replaces the group's plugin of type `T`, keeping its place.
+    pub fn set<T: Plugin>(mut self, plugin: T) -> Self {
+        if let Some(index) = self.find::<T>("set") {
+            self.members[index].plugin = Box::new(plugin);
+        }
+        self
+    }
+
+    /// Leaves the group's plugin of type `T` out when the group is added.
+    pub fn disable<T: Plugin>(mut self) -> Self {
+        if let Some(index) = self.find::<T>("disable") {
+            self.members[index].enabled = false;
+        }
+        self
+    }
+
+    pub fn enable<T: Plugin>(mut self) -> Self {
+        if let Some(index) = self.find::<T>("enable") {
+            self.members[index].enabled = true;
+        }
+        self
+    }
+
+    pub fn contains<T: Plugin>(&self) -> bool {
+        self.index_of::<T>().is_some()
+    }
+
+    pub fn is_enabled<T: Plugin>(&self) -> bool {
+        self.index_of::<T>()
+            .is_some_and(|index| self.members[index].enabled)
+    }
+
+    /// The enabled plugins, in order.
+    pub(crate) fn finish(self) -> KResult<Vec<Box<dyn Plugin>>> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        Ok(self
+            .members
+            .into_iter()
+            .filter(|member| member.enabled)
+            .map(|member| member.plugin)
+            .collect())
+    }
+
+    fn index_of<T: Plugin>(&self) -> Option<usize> {
+        self.members
+            .iter()
+            .position(|member| member.type_id == TypeId::of::<T>())
+    }
+
+    fn remove<T: Plugin>(&mut self) {
+        if let Some(index) = self.index_of::<T>() {
+            self.members.remove(index);
+        }
+    }
+
+    /// Like `index_of`, but remembers an error if `T` isn't in the group.
+    fn find<T: Plugin>(&mut self, method: &str) -> Option<usize> {
+        let index = self.index_of::<T>();
+        if index.is_none() && self.error.is_none() {
+            self.error = Some(kerror!(format!(
+                "PluginGroupBuilder::{}(): {} is not in {}",
+                method,
+                type_name::<T>(),
+                self.group
+            )));
+        }
+        index
+    }
+}
+
+impl GroupMember {
+    fn new<T: Plugin>(plugin: T) -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            plugin: Box::new(plugin),
+            enabled: true,
+        }
+    }
+}
+
+impl PluginGroup for PluginGroupBuilder {
+    fn build(self) -> PluginGroupBuilder {
+        self
+    }
+}
+
+/// The plugins for an app without a window: logging, [time](TimePlugin) and transform
+/// propagation.
+pub struct MinimalPlugins;
+
+impl PluginGroup for MinimalPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(LogPlugin::default())
+            .add(TimePlugin)
+            .add(TransformPlugin)
+    }
+}
+
+/// Orders plugins so that each comes after its dependencies, and otherwise in the order they
+/// were added, returning their indices in that order. `built` are the plugins that were built
+/// earlier.
+pub(crate) fn sort_plugins(
+    plugins: &[Box<dyn Plugin>],
+    built: &HashSet<TypeId>,
+) -> KResult<Vec<usize>> {
+    let ids = plugins
+        .iter()
+        .map(|plugin| (**plugin).as_any().type_id())
+        .collect::<Vec<_>>();
+    for plugin in plugins {
+        for dependency in plugin.dependencies() {
+            kensure!(
+                built.contains(&dependency.type_id) || ids.contains(&dependency.type_id),
+                format!(
+                    "App::setup(): {} depends on {}, which was not added",
+                    plugin.name(),
+                    dependency.name
+                )
+            );
+        }
+    }
+
+    let mut done = built.clone();
+    let mut sorted = Vec::new();
+    while sorted.len() < plugins.len() {
+        let next = (0..plugins.len()).find(|index| {
+            !sorted.contains(index)
+                && plugins[*index]
+                    .dependencies()
+                    .iter()
+                    .all(|dependency| done.contains(&dependency.type_id))
+        });
+        let Some(next) = next else {
+            let names = (0..plugins.len())
+                .filter(|index| !sorted.contains(index))
+                .map(|index| plugins[index].name())
+                .collect::<Vec<_>>();
+            kbail!(format!(
+                "App::setup(): plugins {:?} have cyclic dependencies",
+                names
+            ));
+        };
+        done.insert(ids[next]);
+        sorted.push(next);
+    }
+    Ok(sorted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::app::ErrorHandler;
+
+    #[derive(Default)]
+    struct Log(Vec<&'static str>);
+
+    fn log(app: &App, entry: &'static str) {
+        let mut world = app.world().write();
+        if !world.contains_resource::<Log>() {
+            world.insert_resource(Log::default());
+        }
+        world.resource_mut::<Log>().unwrap().0.push(entry);
+    }
+
+    fn entries(app: &App) -> Vec<&'static str> {
+        app.world().read().resource::<Log>().unwrap().0.clone()
+    }
+
+    struct A;
+
+    impl Plugin for A {
+        fn build(&mut self, app: &mut App) -> KResult<()> {
+            log(app, "build A");
+            Ok(())
+        }
+
+        fn dependencies(&self) -> Vec<Dependency> {
+            vec![Dependency::on::<B>()]
+        }
+
+        fn finish(&self, app: &mut App) -> KResult<()> {
+            log(app, "finish A");
+            Ok(())
+        }
+
+        fn cleanup(&self, app: &mut App) -> KResult<()> {
+            log(app, "cleanup A");
+            Ok(())
+        }
+    }
+
+    // ready on the third poll
+    #[derive(Default)]
+    struct B(AtomicUsize);
+
+    impl Plugin for B {
+        fn build(&mut self, app: &mut App) -> KResult<()> {
+            log(app, "build B");
+            app.insert_plugin(C)
+        }
+
+        fn ready(&self, _app: &App) -> bool {
+            self.0.fetch_add(1, Ordering::Relaxed) >= 2
+        }
+
+        fn finish(&self, app: &mut App) -> KResult<()> {
+            log(app, "finish B");
+            Ok(())
+        }
+
+        fn cleanup(&self, app: &mut App) -> KResult<()> {
+            log(app, "cleanup B");
+            Ok(())
+        }
+    }
+
+    struct C;
+
+    impl Plugin for C {
+        fn build(&mut self, app: &mut App) -> KResult<()> {
+            log(app, "build C");
+            Ok(())
+        }
+
+        fn dependencies(&self) -> Vec<Dependency> {
+            vec![Dependency::on::<A>()]
+        }
+    }
+
+    struct Loop;
+
+    impl Plugin for Loop {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            Ok(())
+        }
+
+        fn dependencies(&self) -> Vec<Dependency> {
+            vec![Dependency::on::<LoopBack>()]
+        }
+    }
+
+    struct LoopBack;
+
+    impl Plugin for LoopBack {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            Ok(())
+        }
+
+        fn dependencies(&self) -> Vec<Dependency> {
+            vec![Dependency::on::<Loop>()]
+        }
+    }
+
+    #[test]
+    fn test_plugin_order() {
+        let mut app = App::new()
+            .add_plugin(A)
+            .unwrap()
+            .add_plugin(B::default())
+            .unwrap();
+        assert!(app.insert_plugin(A).is_err());
+
+        app.setup().unwrap();
+        // `B` isn't ready until the third poll, so nothing is finished yet
+        assert!(!app.is_set_up());
+        assert_eq!(entries(&app), vec!["build B", "build A", "build C"]);
+        while !app.is_set_up() {
+            app.setup().unwrap();
+        }
+        assert_eq!(app.get_plugin::<B>().unwrap().0.load(Ordering::Relaxed), 3);
+        app.cleanup().unwrap();
+        app.cleanup().unwrap();
+        assert_eq!(
+            entries(&app),
+            vec![
+                "build B",
+                "build A",
+                "build C",
+                "finish B",
+                "finish A",
+                "cleanup A",
+                "cleanup B",
+            ]
+        );
+    }
+
+    struct Never;
+
+    impl Plugin for Never {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            Ok(())
+        }
+
+        fn ready(&self, _app: &App) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn test_ready_timeout() {
+        let mut app = App::new().add_plugin(Never).unwrap();
+        app.add_system(crate::schedule::CoreStage::Update, || -> KResult<()> {
+            kbail!("no frame should run before every plugin is ready")
+        })
+        .unwrap();
+        for _ in 0..3 {
+            app.update().unwrap();
+        }
+        assert!(!app.is_started());
+
+        app.set_ready_timeout(std::time::Duration::ZERO);
+        let error = app.update().unwrap_err();
+        assert!(error.to_string().contains("Never"));
+    }
+
+    struct FailingCleanup(&'static str);
+
+    impl Plugin for FailingCleanup {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            Ok(())
+        }
+
+        fn cleanup(&self, app: &mut App) -> KResult<()> {
+            log(app, self.0);
+            kbail!(format!("{} failed", self.0))
+        }
+    }
+
+    #[test]
+    fn test_plugin_cleanup_errors() {
+        for (handler, fatal) in [
+            (ErrorHandler::Exit, true),
+            (ErrorHandler::Log, false),
+            (ErrorHandler::Ignore, false),
+        ] {
+            let mut app = App::new()
+                .add_plugin(A)
+                .unwrap()
+                .add_plugin(B::default())
+                .unwrap()
+                .add_plugin(FailingCleanup("cleanup failing"))
+                .unwrap();
+            app.set_error_handler(handler);
+            while !app.is_set_up() {
+                app.setup().unwrap();
+            }
+
+            let result = app.cleanup();
+            assert_eq!(result.is_err(), fatal);
+            if let Err(error) = result {
+                assert!(error.to_string().contains("cleanup failing failed"));
+            }
+            // the plugins built before it still clean up
+            assert_eq!(
+                entries(&app)[5..],
+                ["cleanup failing", "cleanup A", "cleanup B"]
+            );
+        }
+    }
+
+    struct FailingBuild;
+
+    impl Plugin for FailingBuild {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            kbail!("build failed")
+        }
+    }
+
+    #[test]
+    fn test_plugin_build_error() {
+        let mut app = App::new()
+            .add_plugin(FailingBuild)
+            .unwrap()
+            .add_plugin(Named("after"))
+            .unwrap();
+        assert!(app.setup().is_err());
+        assert!(app.get_plugin::<Named>().is_some());
+        // the app stays failed rather than running without its plugins
+        assert!(app.setup().is_err());
+        assert!(app.update().is_err());
+        assert!(app.world().read().resource::<Log>().is_none());
+    }
+
+    struct FailingFinish;
+
+    impl Plugin for FailingFinish {
+        fn build(&mut self, _app: &mut App) -> KResult<()> {
+            Ok(())
+        }
+
+        fn finish(&self, _app: &mut App) -> KResult<()> {
+            kbail!("finish failed")
+        }
+    }
+
+    #[test]
+    fn test_plugin_finish_error() {
+        let mut app = App::new()
+            .add_plugin(A)
+            .unwrap()
+            .add_plugin(B(AtomicUsize::new(2)))
+            .unwrap()
+            .add_plugin(FailingFinish)
+            .unwrap();
+        assert!(app.setup().is_err());
+        // `C` was built after the failing plugin, so it was never finished
+        assert!(!app.is_set_up());
+        assert!(app.setup().is_err());
+        assert_eq!(
+            entries(&app),
+            vec!["build B", "build A", "build C", "finish B", "finish A"]
+        );
+    }
+
+    #[test]
+    fn test_plugin_dependency_errors() {
+        // `B` is missing, which can still be fixed
+        let mut app = App::new().add_plugin(A).unwrap();
+        assert!(app.setup().is_err());
+        assert!(app.get_plugin::<A>().is_some());
+        app.insert_plugin(B::default()).unwrap();
+        while !app.is_set_up() {
+            app.setup().unwrap();
+        }
+        assert_eq!(entries(&app)[..3], ["build B", "build A", "build C"]);
+
+        let mut app = App::new()
+            .add_plugin(Loop)
+            .unwrap()
+            .add_plugin(LoopBack)
+            .unwrap();
+        assert!(app.setup().is_err());
+    }
+
+    struct Named(&'static str);
+
+    impl Plugin for Named {
+        fn build(&mut self, app: &mut App) -> KResult<()> {
+            log(app, self.0);
+            Ok(())
+        }
+    }
+
+    struct Other;
+
+    impl Plugin for Other {
+        fn build(&mut self, app: &mut App) -> KResult<()> {
+            log(app, "other");
+            Ok(())
+        }
+    }
+
+    struct Group;
+
+    impl PluginGroup for Group {
+        fn build(self) -> PluginGroupBuilder {
+            PluginGroupBuilder::start::<Self>()
+                .add(Named("named"))
+                .add(Other)
+        }
+    }
+
+    #[test]
+    fn test_plugin_group() {
+        let mut app = App::new().add_plugins(Group).unwrap();
+        app.setup().unwrap();
+        assert_eq!(entries(&app), vec!["named", "other"]);
+
+        let group = Group
+            .build()
+            .set::<Named>(Named("replaced"))
+            .add_before::<Named, _>(Other);
+        let mut app = App::new().add_plugins(group).unwrap();
+        app.setup().unwrap();
+        assert_eq!(entries(&app), vec!["other", "replaced"]);
+
+        let group = Group.build().disable::<Named>();
+        assert!(group.contains::<Named>() && !group.is_enabled::<Named>());
+        let mut app = App::new().add_plugins(group).unwrap();
+        app.setup().unwrap();
+        assert_eq!(entries(&app), vec!["other"]);
+
+        assert!(App::new()
+            .add_plugins(Group.build().disable::<A>())
+            .is_err());
+        assert!(App::new()
+            .add_plugins(Group)
+            .unwrap()
+            .add_plugins(Group)
+            .is_err());
+    }
+
+    #[test]
+    fn test_minimal_plugins() {
+        let mut app = App::new().add_plugins(MinimalPlugins).unwrap();
+        app.setup().unwrap();
+        assert!(app.world().read().contains_resource::<crate::time::Time>());
+    }
+}
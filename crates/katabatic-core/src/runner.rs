@@ -1,3 +1,5 @@
+use std::time::{Duration, Instant};
+
 use katabatic_util::error::KResult;
 
 use crate::app::App;
@@ -10,8 +12,114 @@ pub trait Runner {
 pub struct NoOpRunner;
 
 impl Runner for NoOpRunner {
-    fn run(&mut self, _app: App) -> KResult<()> {
-        Ok(())
+    fn run(&mut self, mut app: App) -> KResult<()> {
+        app.cleanup()
+    }
+}
+
+type ExitCondition = Box<dyn FnMut(&App) -> bool>;
+
+/// Runs frames in a loop without a window, for servers and tests.
+///
+/// The loop ends once an [`AppExit`](crate::app::AppExit) is sent, the
+/// [exit condition](Self::run_until) is met, or a frame fails with a fatal error. The app is
+/// cleaned up either way.
+#[derive(Default)]
+pub struct HeadlessRunner {
+    frame_time: Option<Duration>,
+    exit_condition: Option<ExitCondition>,
+}
+
+impl HeadlessRunner {
+    /// Creates a runner that runs frames as fast as it can, until an
+    /// [`AppExit`](crate::app::AppExit) is sent.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sleeps after every frame that took less than `1 / fps` seconds, so that no more than
+    /// `fps` frames run per second.
+    ///
+    /// Panics if `fps` isn't positive.
+    pub fn with_target_fps(self, fps: f64) -> Self {
+        assert!(
+            fps > 0.0,
+            "HeadlessRunner::with_target_fps(): fps must be positive"
+        );
+        self.with_frame_time(Duration::from_secs_f64(1.0 / fps))
+    }
+
+    /// Sleeps after every frame that took less than `frame_time`.
+    pub fn with_frame_time(mut self, frame_time: Duration) -> Self {
+        self.frame_time = Some(frame_time);
+        self
+    }
+
+    /// Stops after the first frame at the end of which `condition` returns `true`.
+    pub fn run_until(mut self, condition: impl FnMut(&App) -> bool + 'static) -> Self {
+        self.exit_condition = Some(Box::new(condition));
+        self
+    }
+
+    fn run_frames(&mut self, app: &mut App) -> KResult<()> {
+        loop {
+            let start = Instant::now();
+            app.update()?;
+            if app.exit_requested() {
+                return Ok(());
+            }
+
+            if let Some(exit_condition) = &mut self.exit_condition {
+                if exit_condition(app) {
+                    return Ok(());
+                }
+            }
+
+            if let Some(frame_time) = self.frame_time {
+                if let Some(remaining) = frame_time.checked_sub(start.elapsed()) {
+                    std::thread::sleep(remaining);
+                }
+            }
+        }
+    }
+}
+
+impl Runner for HeadlessRunner {
+    fn run(&mut self, mut app: App) -> KResult<()> {
+        let result = self.run_frames(&mut app);
+        let cleanup = app.cleanup();
+        result.and(cleanup)
+    }
+}
+
+/// Runs a fixed number of frames back to back with [`App::update`], or fewer if an
+/// [`AppExit`](crate::app::AppExit) is sent, then cleans the app up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StepRunner {
+    steps: u64,
+}
+
+impl StepRunner {
+    pub fn new(steps: u64) -> Self {
+        Self { steps }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+impl Runner for StepRunner {
+    fn run(&mut self, mut app: App) -> KResult<()> {
+        let mut result = Ok(());
+        for _ in 0..self.steps {
+            result = app.update();
+            if result.is_err() || app.exit_requested() {
+                break;
+            }
+        }
+        let cleanup = app.cleanup();
+        result.and(cleanup)
     }
 }
 
@@ -33,3 +141,153 @@ pub trait Hook: 'static {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    };
+
+    use katabatic_util::kbail;
+
+    use super::*;
+    use crate::{
+        app::{AppExit, ErrorHandler},
+        event::EventWriter,
+        schedule::CoreStage,
+        system::{Res, ResMut},
+    };
+
+    #[derive(Debug, Default)]
+    struct Counts {
+        startup: u64,
+        update: u64,
+        cleanup: u64,
+    }
+
+    /// Counts the frames it runs, and publishes the counts when it's cleaned up.
+    fn counting_app(counts: Arc<[AtomicU64; 3]>) -> App {
+        let mut app = App::new();
+        app.world().write().insert_resource(Counts::default());
+        app.add_system(CoreStage::Startup, |mut counts: ResMut<Counts>| {
+            counts.startup += 1
+        })
+        .unwrap();
+        app.add_system(CoreStage::Update, |mut counts: ResMut<Counts>| {
+            counts.update += 1
+        })
+        .unwrap();
+        app.add_system(CoreStage::Cleanup, move |mut c: ResMut<Counts>| {
+            c.cleanup += 1;
+            counts[0].store(c.startup, Ordering::SeqCst);
+            counts[1].store(c.update, Ordering::SeqCst);
+            counts[2].store(c.cleanup, Ordering::SeqCst);
+        })
+        .unwrap();
+        app
+    }
+
+    fn load(counts: &[AtomicU64; 3]) -> [u64; 3] {
+        counts.each_ref().map(|count| count.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_update() {
+        let mut app = App::new();
+        app.world().write().insert_resource(Counts::default());
+        app.add_system(CoreStage::Startup, |mut counts: ResMut<Counts>| {
+            counts.startup += 1
+        })
+        .unwrap();
+        app.add_system(CoreStage::Update, |mut counts: ResMut<Counts>| {
+            counts.update += 1
+        })
+        .unwrap();
+
+        for _ in 0..3 {
+            app.update().unwrap();
+        }
+        let world = app.world().read();
+        let counts = world.resource::<Counts>().unwrap();
+        assert_eq!((counts.startup, counts.update), (1, 3));
+    }
+
+    #[test]
+    fn test_step_runner() {
+        let counts = Arc::new(<[AtomicU64; 3]>::default());
+        let mut app = counting_app(counts.clone());
+        app.set_runner(StepRunner::new(5));
+        app.run().unwrap();
+        assert_eq!(load(&counts), [1, 5, 1]);
+    }
+
+    #[test]
+    fn test_headless_runner() {
+        let counts = Arc::new(<[AtomicU64; 3]>::default());
+        let mut app = counting_app(counts.clone());
+        app.set_runner(
+            HeadlessRunner::new()
+                .with_frame_time(Duration::from_millis(5))
+                .run_until(|app| app.world().read().resource::<Counts>().unwrap().update >= 4),
+        );
+        let start = Instant::now();
+        app.run().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(15));
+        assert_eq!(load(&counts), [1, 4, 1]);
+    }
+
+    #[test]
+    fn test_headless_runner_error() {
+        let counts = Arc::new(<[AtomicU64; 3]>::default());
+        let mut app = counting_app(counts.clone());
+        app.add_system(CoreStage::Update, |counts: Res<Counts>| {
+            if counts.update >= 2 {
+                kbail!("too many frames");
+            }
+            Ok(())
+        })
+        .unwrap();
+        app.set_runner(HeadlessRunner::new());
+        assert!(app.run().is_err());
+        // cleaned up even though a frame failed
+        assert_eq!(load(&counts)[2], 1);
+    }
+
+    #[test]
+    fn test_app_exit() {
+        let counts = Arc::new(<[AtomicU64; 3]>::default());
+        let mut app = counting_app(counts.clone());
+        app.add_system(
+            CoreStage::PostUpdate,
+            |counts: Res<Counts>, mut exit: EventWriter<AppExit>| {
+                if counts.update == 3 {
+                    exit.send(AppExit);
+                }
+            },
+        )
+        .unwrap();
+        app.set_runner(HeadlessRunner::new());
+        app.run().unwrap();
+        assert_eq!(load(&counts), [1, 3, 1]);
+    }
+
+    #[test]
+    fn test_error_handler() {
+        for (handler, fatal, updates) in [
+            (ErrorHandler::Exit, true, 0),
+            (ErrorHandler::Log, false, 4),
+            (ErrorHandler::Ignore, false, 4),
+        ] {
+            let counts = Arc::new(<[AtomicU64; 3]>::default());
+            let mut app = counting_app(counts.clone());
+            app.set_error_handler(handler);
+            app.add_system(CoreStage::PreUpdate, || -> KResult<()> { kbail!("failed") })
+                .unwrap();
+            app.set_runner(StepRunner::new(4));
+            let result = app.run();
+            assert_eq!(result.is_err(), fatal);
+            assert_eq!(load(&counts), [1, updates, 1]);
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::BinaryHeap,
+    fmt::{Debug, Display},
+};
+
+use katabatic_ecs::world::World;
+use katabatic_util::{error::KResult, kbail, kerror, lock::Lock};
+
+use crate::{
+    executor::Executor,
+    system::{IntoSystem, System},
+};
+
+/// A name for a stage, or for a group of systems that others can be ordered against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Label(Cow<'static, str>);
+
+impl Label {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Label {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<&'static str> for Label {
+    fn from(value: &'static str) -> Self {
+        Self(Cow::Borrowed(value))
+    }
+}
+
+impl From<String> for Label {
+    fn from(value: String) -> Self {
+        Self(Cow::Owned(value))
+    }
+}
+
+/// The stages every [`Schedule`] starts with, in the order they run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoreStage {
+    /// Runs once, before the first update.
+    Startup,
+    First,
+    PreUpdate,
+    /// Runs zero or more times per frame, once per fixed timestep that has passed. See
+    /// [`FixedTime`](crate::time::FixedTime).
+    FixedUpdate,
+    Update,
+    PostUpdate,
+    Last,
+    Render,
+    /// Runs once, when the app shuts down.
+    Cleanup,
+}
+
+impl CoreStage {
+    pub const ALL: [CoreStage; 9] = [
+        CoreStage::Startup,
+        CoreStage::First,
+        CoreStage::PreUpdate,
+        CoreStage::FixedUpdate,
+        CoreStage::Update,
+        CoreStage::PostUpdate,
+        CoreStage::Last,
+        CoreStage::Render,
+        CoreStage::Cleanup,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            CoreStage::Startup => "Startup",
+            CoreStage::First => "First",
+            CoreStage::PreUpdate => "PreUpdate",
+            CoreStage::FixedUpdate => "FixedUpdate",
+            CoreStage::Update => "Update",
+            CoreStage::PostUpdate => "PostUpdate",
+            CoreStage::Last => "Last",
+            CoreStage::Render => "Render",
+            CoreStage::Cleanup => "Cleanup",
+        }
+    }
+}
+
+impl From<CoreStage> for Label {
+    fn from(value: CoreStage) -> Self {
+        Self(Cow::Borrowed(value.as_str()))
+    }
+}
+
+/// A system along with its labels and ordering constraints.
+pub struct SystemConfig {
+    system: Box<dyn System>,
+    labels: Vec<Label>,
+    before: Vec<Label>,
+    after: Vec<Label>,
+}
+
+impl SystemConfig {
+    pub fn new<S: System>(system: S) -> Self {
+        // every system can be referred to by its own name
+        let labels = vec![Label::from(system.name().into_owned())];
+        Self {
+            system: Box::new(system),
+            labels,
+            before: Vec::new(),
+            after: Vec::new(),
+        }
+    }
+
+    pub fn system(&self) -> &dyn System {
+        &*self.system
+    }
+
+    pub fn system_mut(&mut self) -> &mut dyn System {
+        &mut *self.system
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn label(mut self, label: impl Into<Label>) -> Self {
+        self.labels.push(label.into());
+        self
+    }
+
+    /// Runs this system before every system in the same stage with the given label.
+    pub fn before(mut self, label: impl Into<Label>) -> Self {
+        self.before.push(label.into());
+        self
+    }
+
+    /// Runs this system after every system in the same stage with the given label.
+    pub fn after(mut self, label: impl Into<Label>) -> Self {
+        self.after.push(label.into());
+        self
+    }
+
+    fn has_label(&self, label: &Label) -> bool {
+        self.labels.contains(label)
+    }
+}
+
+/// Conversion into a [`SystemConfig`], with shortcuts for adding labels and constraints.
+pub trait IntoSystemConfig<Marker>: Sized {
+    fn into_config(self) -> SystemConfig;
+
+    fn label(self, label: impl Into<Label>) -> SystemConfig {
+        self.into_config().label(label)
+    }
+
+    fn before(self, label: impl Into<Label>) -> SystemConfig {
+        self.into_config().before(label)
+    }
+
+    fn after(self, label: impl Into<Label>) -> SystemConfig {
+        self.into_config().after(label)
+    }
+}
+
+impl<Marker, S: IntoSystem<Marker>> IntoSystemConfig<Marker> for S {
+    fn into_config(self) -> SystemConfig {
+        SystemConfig::new(self.into_system())
+    }
+}
+
+impl IntoSystemConfig<()> for SystemConfig {
+    fn into_config(self) -> SystemConfig {
+        self
+    }
+}
+
+/// A named group of systems that run together, in an order satisfying their constraints.
+pub struct Stage {
+    label: Label,
+    systems: Vec<SystemConfig>,
+    order: Vec<usize>,
+    dirty: bool,
+    // what each system waits for, including earlier systems it conflicts with
+    graph: Option<Vec<Vec<usize>>>,
+}
+
+impl Stage {
+    pub fn new(label: impl Into<Label>) -> Self {
+        Self {
+            label: label.into(),
+            systems: Vec::new(),
+            order: Vec::new(),
+            dirty: false,
+            graph: None,
+        }
+    }
+
+    pub fn label(&self) -> &Label {
+        &self.label
+    }
+
+    pub fn add_system<Marker>(&mut self, system: impl IntoSystemConfig<Marker>) {
+        self.systems.push(system.into_config());
+        self.dirty = true;
+        self.graph = None;
+    }
+
+    pub fn systems(&self) -> impl Iterator<Item = &SystemConfig> + '_ {
+        self.systems.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.systems.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.systems.is_empty()
+    }
+
+    /// The indices of the systems that must finish before system `index` starts.
+    pub fn dependencies_of(&self, index: usize) -> Vec<usize> {
+        let system = &self.systems[index];
+        (0..self.systems.len())
+            .filter(|&other| other != index)
+            .filter(|&other| {
+                let other = &self.systems[other];
+                system.after.iter().any(|label| other.has_label(label))
+                    || other.before.iter().any(|label| system.has_label(label))
+            })
+            .collect()
+    }
+
+    /// Returns the order the systems run in, sorting them again if any were added.
+    ///
+    /// Every ordering constraint is respected. Whenever more than one system has all of its
+    /// constraints met, the one added first goes next. So a system that is held back by a
+    /// constraint can end up after systems added later, even ones it has no constraint with.
+    pub fn order(&mut self) -> KResult<&[usize]> {
+        if self.dirty {
+            self.order = self.sort()?;
+            self.dirty = false;
+        }
+        Ok(&self.order)
+    }
+
+    fn sort(&self) -> KResult<Vec<usize>> {
+        let count = self.systems.len();
+        let mut dependents = vec![Vec::new(); count];
+        let mut remaining = vec![0usize; count];
+
+        for (index, remaining) in remaining.iter_mut().enumerate() {
+            for dependency in self.dependencies_of(index) {
+                dependents[dependency].push(index);
+                *remaining += 1;
+            }
+        }
+
+        let mut ready = (0..count)
+            .filter(|&index| remaining[index] == 0)
+            .map(Reverse)
+            .collect::<BinaryHeap<_>>();
+        let mut order = Vec::with_capacity(count);
+
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for &dependent in &dependents[index] {
+                remaining[dependent] -= 1;
+                if remaining[dependent] == 0 {
+                    ready.push(Reverse(dependent));
+                }
+            }
+        }
+
+        if order.len() != count {
+            let cyclic = (0..count)
+                .filter(|index| !order.contains(index))
+                .map(|index| self.systems[index].system.name())
+                .collect::<Vec<_>>();
+            kbail!(format!(
+                "Stage {}: systems have cyclic ordering constraints: {}",
+                self.label,
+                cyclic.join(", ")
+            ));
+        }
+
+        Ok(order)
+    }
+
+    /// For each system, the systems that have to finish before it starts: those it's explicitly
+    /// ordered after, and those earlier in the stage's order whose access conflicts with its own.
+    fn build_graph(&self) -> Vec<Vec<usize>> {
+        let mut position = vec![0; self.systems.len()];
+        for (i, &index) in self.order.iter().enumerate() {
+            position[index] = i;
+        }
+
+        (0..self.systems.len())
+            .map(|index| {
+                let mut dependencies = self.dependencies_of(index);
+                let access = self.systems[index].system.access();
+                for (other, config) in self.systems.iter().enumerate() {
+                    if position[other] < position[index]
+                        && !dependencies.contains(&other)
+                        && !access.is_compatible(config.system.access())
+                    {
+                        dependencies.push(other);
+                    }
+                }
+                dependencies
+            })
+            .collect()
+    }
+
+    /// Initializes any new systems, then runs every system once with `executor` and applies
+    /// their deferred work.
+    pub fn run(&mut self, world: &Lock<World>, executor: &Executor) -> KResult<()> {
+        if self
+            .systems
+            .iter()
+            .any(|config| !config.system.is_initialized())
+        {
+            let mut world = world.write();
+            for config in &mut self.systems {
+                config.system.initialize(&mut world);
+            }
+        }
+
+        self.order()?;
+        // access is only known once every system is initialized
+        let graph = match self.graph.take() {
+            Some(graph) => graph,
+            None => self.build_graph(),
+        };
+
+        let mut result = executor.run(&mut self.systems, &self.order, &graph, &world.read());
+        self.graph = Some(graph);
+
+        // the stage's sync point: apply whatever the systems deferred, in order, and then
+        // anything else deferred on the world, even if a system failed
+        let mut world = world.write();
+        for &index in &self.order {
+            let applied = self.systems[index].system.apply_deferred(&mut world);
+            if result.is_ok() {
+                result = applied;
+            }
+        }
+        let applied = world.apply_deferred();
+        if result.is_ok() {
+            result = applied;
+        }
+
+        result
+    }
+}
+
+/// An ordered list of [`Stage`]s.
+pub struct Schedule {
+    stages: Vec<Stage>,
+    executor: Executor,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::with_executor(Executor::default())
+    }
+}
+
+impl Schedule {
+    /// Creates a schedule with all of the [`CoreStage`]s.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a schedule with all of the [`CoreStage`]s, run by `executor`.
+    pub fn with_executor(executor: Executor) -> Self {
+        Self {
+            stages: CoreStage::ALL.into_iter().map(Stage::new).collect(),
+            executor,
+        }
+    }
+
+    /// Creates a schedule with no stages at all.
+    pub fn empty() -> Self {
+        Self {
+            stages: Vec::new(),
+            executor: Executor::default(),
+        }
+    }
+
+    pub fn executor(&self) -> &Executor {
+        &self.executor
+    }
+
+    pub fn set_executor(&mut self, executor: Executor) {
+        self.executor = executor;
+    }
+
+    pub fn stages(&self) -> impl Iterator<Item = &Stage> + '_ {
+        self.stages.iter()
+    }
+
+    pub fn stage(&self, label: impl Into<Label>) -> Option<&Stage> {
+        let label = label.into();
+        self.stages.iter().find(|stage| stage.label == label)
+    }
+
+    pub fn stage_mut(&mut self, label: impl Into<Label>) -> Option<&mut Stage> {
+        let label = label.into();
+        self.stages.iter_mut().find(|stage| stage.label == label)
+    }
+
+    fn position(&self, label: &Label) -> KResult<usize> {
+        self.stages
+            .iter()
+            .position(|stage| stage.label == *label)
+            .ok_or_else(|| kerror!(format!("Schedule: no stage named {}", label)))
+    }
+
+    /// Adds a stage that runs after every existing stage.
+    pub fn add_stage(&mut self, label: impl Into<Label>) -> KResult<()> {
+        let label = label.into();
+        self.ensure_new(&label)?;
+        self.stages.push(Stage::new(label));
+        Ok(())
+    }
+
+    pub fn add_stage_before(
+        &mut self,
+        existing: impl Into<Label>,
+        label: impl Into<Label>,
+    ) -> KResult<()> {
+        let label = label.into();
+        self.ensure_new(&label)?;
+        let index = self.position(&existing.into())?;
+        self.stages.insert(index, Stage::new(label));
+        Ok(())
+    }
+
+    pub fn add_stage_after(
+        &mut self,
+        existing: impl Into<Label>,
+        label: impl Into<Label>,
+    ) -> KResult<()> {
+        let label = label.into();
+        self.ensure_new(&label)?;
+        let index = self.position(&existing.into())?;
+        self.stages.insert(index + 1, Stage::new(label));
+        Ok(())
+    }
+
+    fn ensure_new(&self, label: &Label) -> KResult<()> {
+        if self.stages.iter().any(|stage| stage.label == *label) {
+            kbail!(format!("Schedule: stage {} already exists", label));
+        }
+        Ok(())
+    }
+
+    pub fn add_system<Marker>(
+        &mut self,
+        stage: impl Into<Label>,
+        system: impl IntoSystemConfig<Marker>,
+    ) -> KResult<()> {
+        let label = stage.into();
+        let index = self.position(&label)?;
+        self.stages[index].add_system(system);
+        Ok(())
+    }
+
+    pub fn run_stage(&mut self, label: impl Into<Label>, world: &Lock<World>) -> KResult<()> {
+        let index = self.position(&label.into())?;
+        self.stages[index].run(world, &self.executor)
+    }
+
+    /// The labels of every stage from `first` to `last`, inclusive, in the order they run.
+    pub fn stage_labels(
+        &self,
+        first: impl Into<Label>,
+        last: impl Into<Label>,
+    ) -> KResult<Vec<Label>> {
+        let first = self.position(&first.into())?;
+        let last = self.position(&last.into())?;
+        Ok(self.stages[first..=last]
+            .iter()
+            .map(|stage| stage.label.clone())
+            .collect())
+    }
+
+    /// Runs every stage from `first` to `last`, inclusive, including any added in between.
+    pub fn run_range(
+        &mut self,
+        first: impl Into<Label>,
+        last: impl Into<Label>,
+        world: &Lock<World>,
+    ) -> KResult<()> {
+        let first = self.position(&first.into())?;
+        let last = self.position(&last.into())?;
+        for stage in &mut self.stages[first..=last] {
+            stage.run(world, &self.executor)?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list()
+            .entries(self.stages.iter().map(|stage| &stage.label))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_ecs::query::Query;
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Log(Vec<&'static str>);
+
+    fn push(name: &'static str) -> impl FnMut(Query<&mut Log>) {
+        move |query: Query<&mut Log>| {
+            for mut log in query.iter() {
+                log.0.push(name);
+            }
+        }
+    }
+
+    fn log_of(world: &Lock<World>) -> Vec<&'static str> {
+        let world = world.read();
+        let query = world.query::<&Log>();
+        let log = query.iter().next().unwrap();
+        log.0.clone()
+    }
+
+    #[test]
+    fn test_stage_order() {
+        let world = Lock::new(World::new());
+        world.write().spawn((Log::default(),));
+
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(CoreStage::Update, push("update"))
+            .unwrap();
+        schedule
+            .add_system(CoreStage::PreUpdate, push("pre_update"))
+            .unwrap();
+        schedule
+            .add_stage_after(CoreStage::Update, "physics")
+            .unwrap();
+        schedule.add_system("physics", push("physics")).unwrap();
+        schedule
+            .add_system(CoreStage::Render, push("render"))
+            .unwrap();
+
+        schedule
+            .run_range(CoreStage::First, CoreStage::Last, &world)
+            .unwrap();
+
+        assert_eq!(log_of(&world), vec!["pre_update", "update", "physics"]);
+
+        assert!(schedule.add_system("missing", push("missing")).is_err());
+        assert!(schedule.add_stage("physics").is_err());
+    }
+
+    #[test]
+    fn test_system_order() {
+        let world = Lock::new(World::new());
+        world.write().spawn((Log::default(),));
+
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(CoreStage::Update, push("c").label("c").after("b"))
+            .unwrap();
+        schedule
+            .add_system(CoreStage::Update, push("a").label("a"))
+            .unwrap();
+        schedule
+            .add_system(CoreStage::Update, push("b").label("b").before("c"))
+            .unwrap();
+        schedule
+            .add_system(CoreStage::Update, push("first").before("a"))
+            .unwrap();
+
+        schedule.run_stage(CoreStage::Update, &world).unwrap();
+
+        // constrained systems are placed as early as their dependencies allow
+        assert_eq!(log_of(&world), vec!["b", "c", "first", "a"]);
+    }
+
+    #[test]
+    fn test_cyclic_order() {
+        let world = Lock::new(World::new());
+
+        let mut schedule = Schedule::new();
+        schedule
+            .add_system(CoreStage::Update, push("a").label("a").after("b"))
+            .unwrap();
+        schedule
+            .add_system(CoreStage::Update, push("b").label("b").after("a"))
+            .unwrap();
+
+        assert!(schedule.run_stage(CoreStage::Update, &world).is_err());
+    }
+}
@@ -0,0 +1,680 @@
+use std::{
+    any::{type_name, TypeId},
+    borrow::Cow,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use katabatic_ecs::{
+    access::Access,
+    command::{CommandQueue, Commands},
+    component::Component,
+    lifecycle::RemovedComponents,
+    query::{Query, QueryData, QueryFilter},
+    resource::Resource,
+    tick::Tick,
+    world::World,
+};
+use katabatic_util::{
+    error::KResult,
+    lock::{MapRead, MapWrite},
+};
+
+/// A unit of work run by a [`Schedule`](crate::schedule::Schedule) every time its stage runs.
+///
+/// Most systems are plain functions whose arguments are [`SystemParam`]s, turned into a
+/// `System` with [`IntoSystem`].
+pub trait System: Send + Sync + 'static {
+    fn name(&self) -> Cow<'static, str>;
+
+    /// Sets up the system's state. Called once before the system first runs.
+    fn initialize(&mut self, world: &mut World);
+
+    fn is_initialized(&self) -> bool;
+
+    /// Everything the system reads and writes. Only valid once the system is initialized.
+    fn access(&self) -> &Access;
+
+    /// Returns `false` if the system uses non-`Send` data, so has to run on the main thread.
+    /// Only valid once the system is initialized.
+    fn is_send(&self) -> bool {
+        true
+    }
+
+    fn run(&mut self, world: &World) -> KResult<()>;
+
+    /// Applies any work the system deferred until it has exclusive access to the world.
+    fn apply_deferred(&mut self, world: &mut World) -> KResult<()>;
+}
+
+/// Information about a system that its parameters can read and contribute to.
+#[derive(Debug, Clone)]
+pub struct SystemMeta {
+    name: Cow<'static, str>,
+    access: Access,
+    is_send: bool,
+    last_run: Tick,
+    this_run: Tick,
+}
+
+impl SystemMeta {
+    pub fn new(name: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            name: name.into(),
+            access: Access::new(),
+            is_send: true,
+            last_run: Tick::default(),
+            this_run: Tick::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn access(&self) -> &Access {
+        &self.access
+    }
+
+    pub fn is_send(&self) -> bool {
+        self.is_send
+    }
+
+    /// The change tick of the system's previous run. Changes made since then are detected by
+    /// the system's queries.
+    pub fn last_run(&self) -> Tick {
+        self.last_run
+    }
+
+    /// The change tick of the system's current run.
+    pub fn this_run(&self) -> Tick {
+        self.this_run
+    }
+
+    /// Marks the system as using non-`Send` data, so it only ever runs on the main thread.
+    pub fn set_non_send(&mut self) {
+        self.is_send = false;
+    }
+
+    /// Adds a parameter's access to the system's.
+    ///
+    /// # Panics
+    /// Panics if the parameter conflicts with another parameter of the same system.
+    pub fn add_access(&mut self, access: &Access) {
+        self.access.extend(access);
+        assert!(
+            !self.access.is_self_conflicting(),
+            "System {} has parameters that access the same data mutably more than once",
+            self.name
+        );
+    }
+}
+
+/// A value that can be passed to a function system as one of its arguments.
+pub trait SystemParam: Sized {
+    /// Data kept by the system between runs on behalf of this parameter.
+    type State: Send + Sync + 'static;
+
+    /// The parameter type, with the lifetimes of one particular run.
+    type Item<'w, 's>: SystemParam<State = Self::State>;
+
+    /// Creates the parameter's state and registers its access with `meta`.
+    fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State;
+
+    fn get_param<'w, 's>(
+        state: &'s mut Self::State,
+        meta: &SystemMeta,
+        world: &'w World,
+    ) -> Self::Item<'w, 's>;
+
+    /// Applies work deferred by the parameter, such as [`Commands`].
+    #[allow(unused)]
+    fn apply(state: &mut Self::State, world: &mut World) -> KResult<()> {
+        Ok(())
+    }
+}
+
+pub type SystemParamItem<'w, 's, P> = <P as SystemParam>::Item<'w, 's>;
+
+impl<'a, Q: QueryData + 'static, F: QueryFilter + 'static> SystemParam for Query<'a, Q, F> {
+    type State = ();
+    type Item<'w, 's> = Query<'w, Q, F>;
+
+    fn init_state(_world: &mut World, meta: &mut SystemMeta) -> Self::State {
+        let mut access = Access::new();
+        Q::access(&mut access);
+        F::access(&mut access);
+        meta.add_access(&access);
+    }
+
+    fn get_param<'w, 's>(
+        _state: &'s mut Self::State,
+        meta: &SystemMeta,
+        world: &'w World,
+    ) -> Self::Item<'w, 's> {
+        Query::with_ticks(world, meta.last_run(), meta.this_run())
+    }
+}
+
+/// A value owned by a single system, kept between runs.
+#[derive(Debug)]
+pub struct Local<'s, T: Default + Send + Sync + 'static>(&'s mut T);
+
+impl<'s, T: Default + Send + Sync + 'static> Deref for Local<'s, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<'s, T: Default + Send + Sync + 'static> DerefMut for Local<'s, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+    }
+}
+
+impl<'a, T: Default + Send + Sync + 'static> SystemParam for Local<'a, T> {
+    type State = T;
+    type Item<'w, 's> = Local<'s, T>;
+
+    fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+        T::default()
+    }
+
+    fn get_param<'w, 's>(
+        state: &'s mut Self::State,
+        _meta: &SystemMeta,
+        _world: &'w World,
+    ) -> Self::Item<'w, 's> {
+        Local(state)
+    }
+}
+
+impl<'a, 'b> SystemParam for Commands<'a, 'b> {
+    type State = CommandQueue;
+    type Item<'w, 's> = Commands<'w, 's>;
+
+    fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+        CommandQueue::new()
+    }
+
+    fn get_param<'w, 's>(
+        state: &'s mut Self::State,
+        _meta: &SystemMeta,
+        world: &'w World,
+    ) -> Self::Item<'w, 's> {
+        Commands::new(state, world)
+    }
+
+    fn apply(state: &mut Self::State, world: &mut World) -> KResult<()> {
+        state.apply(world)
+    }
+}
+
+impl<'a, 'b, T: Component> SystemParam for RemovedComponents<'a, 'b, T> {
+    type State = usize;
+    type Item<'w, 's> = RemovedComponents<'w, 's, T>;
+
+    fn init_state(_world: &mut World, _meta: &mut SystemMeta) -> Self::State {
+        0
+    }
+
+    fn get_param<'w, 's>(
+        state: &'s mut Self::State,
+        _meta: &SystemMeta,
+        world: &'w World,
+    ) -> Self::Item<'w, 's> {
+        RemovedComponents::new(world, state)
+    }
+}
+
+/// Shared access to a resource of type `R`.
+///
+/// # Panics
+/// A system with this parameter panics if the resource doesn't exist. Use `Option<Res<R>>`
+/// for resources that might not.
+pub struct Res<'w, R: Resource>(MapRead<'w, R>);
+
+/// Exclusive access to a resource of type `R`.
+///
+/// # Panics
+/// A system with this parameter panics if the resource doesn't exist. Use `Option<ResMut<R>>`
+/// for resources that might not.
+pub struct ResMut<'w, R: Resource>(MapWrite<'w, R>);
+
+/// Shared access to a non-`Send` resource. Systems using it run on the main thread.
+pub struct NonSend<'w, R: 'static>(MapRead<'w, R>);
+
+/// Exclusive access to a non-`Send` resource. Systems using it run on the main thread.
+pub struct NonSendMut<'w, R: 'static>(MapWrite<'w, R>);
+
+macro_rules! impl_resource_param {
+    ($param:ident, $bound:tt, $add_access:ident, $get:ident, $non_send:literal) => {
+        impl<'w, R: $bound> Deref for $param<'w, R> {
+            type Target = R;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl<'a, R: $bound> SystemParam for Option<$param<'a, R>> {
+            type State = ();
+            type Item<'w, 's> = Option<$param<'w, R>>;
+
+            fn init_state(_world: &mut World, meta: &mut SystemMeta) -> Self::State {
+                let mut access = Access::new();
+                access.$add_access(TypeId::of::<R>());
+                meta.add_access(&access);
+                if $non_send {
+                    meta.set_non_send();
+                }
+            }
+
+            fn get_param<'w, 's>(
+                _state: &'s mut Self::State,
+                _meta: &SystemMeta,
+                world: &'w World,
+            ) -> Self::Item<'w, 's> {
+                world.$get::<R>().map($param)
+            }
+        }
+
+        impl<'a, R: $bound> SystemParam for $param<'a, R> {
+            type State = ();
+            type Item<'w, 's> = $param<'w, R>;
+
+            fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+                Option::<Self>::init_state(world, meta)
+            }
+
+            fn get_param<'w, 's>(
+                state: &'s mut Self::State,
+                meta: &SystemMeta,
+                world: &'w World,
+            ) -> Self::Item<'w, 's> {
+                Option::<Self>::get_param(state, meta, world).unwrap_or_else(|| {
+                    panic!(
+                        "System {}: resource {} does not exist",
+                        meta.name(),
+                        type_name::<R>()
+                    )
+                })
+            }
+        }
+    };
+}
+
+impl_resource_param!(Res, Resource, add_resource_read, resource, false);
+impl_resource_param!(ResMut, Resource, add_resource_write, resource_mut, false);
+impl_resource_param!(NonSend, 'static, add_resource_read, non_send_resource, true);
+impl_resource_param!(NonSendMut, 'static, add_resource_write, non_send_resource_mut, true);
+
+impl<'w, R: Resource> DerefMut for ResMut<'w, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<'w, R: 'static> DerefMut for NonSendMut<'w, R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+macro_rules! impl_system_param_tuple {
+    ($($param:ident),*) => {
+        impl<$($param: SystemParam),*> SystemParam for ($($param,)*) {
+            type State = ($($param::State,)*);
+            type Item<'w, 's> = ($($param::Item<'w, 's>,)*);
+
+            #[allow(unused_variables, clippy::unused_unit)]
+            fn init_state(world: &mut World, meta: &mut SystemMeta) -> Self::State {
+                ($($param::init_state(world, meta),)*)
+            }
+
+            #[allow(unused_variables, non_snake_case, clippy::unused_unit)]
+            fn get_param<'w, 's>(
+                state: &'s mut Self::State,
+                meta: &SystemMeta,
+                world: &'w World,
+            ) -> Self::Item<'w, 's> {
+                let ($($param,)*) = state;
+                ($($param::get_param($param, meta, world),)*)
+            }
+
+            #[allow(unused_variables, non_snake_case)]
+            fn apply(state: &mut Self::State, world: &mut World) -> KResult<()> {
+                let ($($param,)*) = state;
+                $($param::apply($param, world)?;)*
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_system_param_tuple!();
+impl_system_param_tuple!(P0);
+impl_system_param_tuple!(P0, P1);
+impl_system_param_tuple!(P0, P1, P2);
+impl_system_param_tuple!(P0, P1, P2, P3);
+impl_system_param_tuple!(P0, P1, P2, P3, P4);
+impl_system_param_tuple!(P0, P1, P2, P3, P4, P5);
+impl_system_param_tuple!(P0, P1, P2, P3, P4, P5, P6);
+impl_system_param_tuple!(P0, P1, P2, P3, P4, P5, P6, P7);
+
+/// The return value of a function system: either nothing, or a [`KResult`].
+pub trait IntoSystemResult {
+    fn into_result(self) -> KResult<()>;
+}
+
+impl IntoSystemResult for () {
+    fn into_result(self) -> KResult<()> {
+        Ok(())
+    }
+}
+
+impl IntoSystemResult for KResult<()> {
+    fn into_result(self) -> KResult<()> {
+        self
+    }
+}
+
+/// A function that can be run as a system. `Marker` only serves to tell implementations apart.
+pub trait SystemParamFunction<Marker>: Send + Sync + 'static {
+    type Param: SystemParam;
+
+    fn run(&mut self, param: SystemParamItem<Self::Param>) -> KResult<()>;
+}
+
+macro_rules! impl_system_param_function {
+    ($($param:ident),*) => {
+        impl<Out, Func, $($param: SystemParam),*> SystemParamFunction<fn($($param,)*) -> Out> for Func
+        where
+            Func: Send + Sync + 'static,
+            for<'a> &'a mut Func:
+                FnMut($($param),*) -> Out + FnMut($(SystemParamItem<$param>),*) -> Out,
+            Out: IntoSystemResult,
+        {
+            type Param = ($($param,)*);
+
+            #[allow(non_snake_case)]
+            fn run(&mut self, param: SystemParamItem<($($param,)*)>) -> KResult<()> {
+                // calling through a generic function pins down which `FnMut` impl to use
+                #[allow(clippy::too_many_arguments)]
+                fn call_inner<Out, $($param),*>(
+                    mut f: impl FnMut($($param),*) -> Out,
+                    $($param: $param),*
+                ) -> Out {
+                    f($($param),*)
+                }
+
+                let ($($param,)*) = param;
+                call_inner(self, $($param),*).into_result()
+            }
+        }
+    };
+}
+
+impl_system_param_function!();
+impl_system_param_function!(P0);
+impl_system_param_function!(P0, P1);
+impl_system_param_function!(P0, P1, P2);
+impl_system_param_function!(P0, P1, P2, P3);
+impl_system_param_function!(P0, P1, P2, P3, P4);
+impl_system_param_function!(P0, P1, P2, P3, P4, P5);
+impl_system_param_function!(P0, P1, P2, P3, P4, P5, P6);
+impl_system_param_function!(P0, P1, P2, P3, P4, P5, P6, P7);
+
+/// A [`System`] built from a function whose arguments are all [`SystemParam`]s.
+pub struct FunctionSystem<Marker, F: SystemParamFunction<Marker>> {
+    func: F,
+    state: Option<<F::Param as SystemParam>::State>,
+    meta: SystemMeta,
+    _marker: PhantomData<fn() -> Marker>,
+}
+
+impl<Marker: 'static, F: SystemParamFunction<Marker>> System for FunctionSystem<Marker, F> {
+    fn name(&self) -> Cow<'static, str> {
+        self.meta.name.clone()
+    }
+
+    fn initialize(&mut self, world: &mut World) {
+        if self.state.is_none() {
+            self.state = Some(F::Param::init_state(world, &mut self.meta));
+        }
+    }
+
+    fn is_initialized(&self) -> bool {
+        self.state.is_some()
+    }
+
+    fn access(&self) -> &Access {
+        &self.meta.access
+    }
+
+    fn is_send(&self) -> bool {
+        self.meta.is_send
+    }
+
+    fn run(&mut self, world: &World) -> KResult<()> {
+        let state = self
+            .state
+            .as_mut()
+            .expect("FunctionSystem::run(): System not initialized");
+        self.meta.this_run = world.increment_change_tick();
+        let param = F::Param::get_param(state, &self.meta, world);
+        let result = self.func.run(param);
+        self.meta.last_run = self.meta.this_run;
+        result
+    }
+
+    fn apply_deferred(&mut self, world: &mut World) -> KResult<()> {
+        match &mut self.state {
+            Some(state) => F::Param::apply(state, world),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Conversion into a [`System`].
+pub trait IntoSystem<Marker> {
+    type System: System;
+
+    fn into_system(self) -> Self::System;
+}
+
+#[doc(hidden)]
+pub struct IsFunctionSystem;
+
+impl<Marker: 'static, F: SystemParamFunction<Marker>> IntoSystem<(IsFunctionSystem, Marker)> for F {
+    type System = FunctionSystem<Marker, F>;
+
+    fn into_system(self) -> Self::System {
+        FunctionSystem {
+            func: self,
+            state: None,
+            meta: SystemMeta::new(type_name::<F>()),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: System> IntoSystem<()> for T {
+    type System = T;
+
+    fn into_system(self) -> Self::System {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_ecs::{
+        entity::Entity,
+        query::{Changed, With},
+    };
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position {
+        x: f32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity {
+        dx: f32,
+    }
+
+    fn movement(query: Query<(&mut Position, &Velocity)>) {
+        for (mut position, velocity) in query.iter() {
+            position.x += velocity.dx;
+        }
+    }
+
+    #[test]
+    fn test_function_system() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0 }, Velocity { dx: 2.0 }));
+
+        let mut system = movement.into_system();
+        system.initialize(&mut world);
+        system.run(&world).unwrap();
+        system.run(&world).unwrap();
+
+        assert_eq!(world.get_component::<Position>(entity).unwrap().x, 4.0);
+        assert!(system
+            .access()
+            .has_write(std::any::TypeId::of::<Position>()));
+        assert!(system.access().has_read(std::any::TypeId::of::<Velocity>()));
+    }
+
+    #[test]
+    fn test_local() {
+        let mut world = World::new();
+
+        let mut system = (|mut count: Local<u32>| -> KResult<()> {
+            *count += 1;
+            katabatic_util::kensure!(*count < 3, "ran too often");
+            Ok(())
+        })
+        .into_system();
+        system.initialize(&mut world);
+
+        assert!(system.run(&world).is_ok());
+        assert!(system.run(&world).is_ok());
+        assert!(system.run(&world).is_err());
+    }
+
+    #[test]
+    fn test_resources() {
+        #[derive(Debug, PartialEq)]
+        struct Score {
+            points: u32,
+        }
+
+        let mut world = World::new();
+        world.insert_resource(Score { points: 0 });
+
+        let mut system = (|mut score: ResMut<Score>, missing: Option<Res<Velocity>>| {
+            assert!(missing.is_none());
+            score.points += 1;
+        })
+        .into_system();
+        system.initialize(&mut world);
+        system.run(&world).unwrap();
+        system.run(&world).unwrap();
+
+        assert_eq!(world.resource::<Score>().unwrap().points, 2);
+        assert!(system.access().has_resource_write(TypeId::of::<Score>()));
+        assert!(system.is_send());
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut world = World::new();
+        world.spawn((Position { x: 0.0 },));
+
+        let mut system = (|mut commands: Commands, query: Query<Entity, With<Position>>| {
+            for entity in query.entity_iter() {
+                commands.entity(entity).despawn();
+                commands.spawn((Velocity { dx: 1.0 },));
+            }
+        })
+        .into_system();
+        system.initialize(&mut world);
+        system.run(&world).unwrap();
+
+        // nothing changes until the commands are applied
+        assert_eq!(world.query::<&Position>().len(), 1);
+        assert_eq!(world.query::<&Velocity>().len(), 0);
+
+        system.apply_deferred(&mut world).unwrap();
+        assert_eq!(world.query::<&Position>().len(), 0);
+        assert_eq!(world.query::<&Velocity>().len(), 1);
+    }
+
+    #[test]
+    fn test_change_detection() {
+        #[derive(Debug, Default)]
+        struct Seen {
+            changed: Vec<usize>,
+        }
+
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0 }, Velocity { dx: 1.0 }));
+        world.insert_resource(Seen::default());
+
+        let mut system = (|query: Query<Entity, Changed<Position>>, mut seen: ResMut<Seen>| {
+            seen.changed.push(query.len());
+        })
+        .into_system();
+        let mut movement = movement.into_system();
+        system.initialize(&mut world);
+        movement.initialize(&mut world);
+
+        // added, then nothing, then changed by another system, then changed outside of any
+        system.run(&world).unwrap();
+        system.run(&world).unwrap();
+        movement.run(&world).unwrap();
+        system.run(&world).unwrap();
+        world.get_component_mut::<Position>(entity).unwrap().x = 0.0;
+        system.run(&world).unwrap();
+
+        assert_eq!(world.resource::<Seen>().unwrap().changed, vec![1, 0, 1, 1]);
+        assert!(system.access().has_read(TypeId::of::<Position>()));
+    }
+
+    #[test]
+    fn test_removed_components() {
+        let mut world = World::new();
+        let entity = world.spawn((Position { x: 0.0 },));
+        world.insert_resource(Vec::<Entity>::new());
+
+        let mut system = (|mut removed: RemovedComponents<Position>,
+                           mut seen: ResMut<Vec<Entity>>| {
+            seen.extend(removed.read());
+        })
+        .into_system();
+        system.initialize(&mut world);
+
+        system.run(&world).unwrap();
+        world.remove_component::<Position>(entity);
+        system.run(&world).unwrap();
+        system.run(&world).unwrap();
+
+        assert_eq!(*world.resource::<Vec<Entity>>().unwrap(), vec![entity]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_conflicting_params() {
+        fn conflicting(_a: Query<&mut Position>, _b: Query<&Position>) {}
+
+        let mut world = World::new();
+        let mut system = conflicting.into_system();
+        system.initialize(&mut world);
+    }
+}
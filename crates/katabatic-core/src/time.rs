@@ -0,0 +1,281 @@
+use std::time::{Duration, Instant};
+
+use katabatic_util::error::KResult;
+
+use crate::{app::App, plugin::Plugin, schedule::CoreStage, system::ResMut};
+
+/// How much time has passed since startup and since the last frame.
+///
+/// Added as a resource by [`TimePlugin`], which updates it at the start of every frame.
+#[derive(Debug, Clone)]
+pub struct Time {
+    startup: Instant,
+    last_update: Option<Instant>,
+    delta: Duration,
+    elapsed: Duration,
+    frame_count: u64,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl Time {
+    pub fn new(startup: Instant) -> Self {
+        Self {
+            startup,
+            last_update: None,
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            frame_count: 0,
+        }
+    }
+
+    /// Starts a new frame now.
+    pub fn update(&mut self) {
+        self.update_with_instant(Instant::now());
+    }
+
+    /// Starts a new frame at `now`. The first frame's delta is the time since startup.
+    pub fn update_with_instant(&mut self, now: Instant) {
+        let last = self.last_update.unwrap_or(self.startup);
+        self.delta = now.saturating_duration_since(last);
+        self.elapsed = now.saturating_duration_since(self.startup);
+        self.last_update = Some(now);
+        self.frame_count += 1;
+    }
+
+    pub fn startup(&self) -> Instant {
+        self.startup
+    }
+
+    pub fn last_update(&self) -> Option<Instant> {
+        self.last_update
+    }
+
+    /// The time between the start of the previous frame and this one.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    /// The time between startup and the start of this frame.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed.as_secs_f64()
+    }
+
+    /// The number of frames started so far.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+}
+
+/// The state of the fixed timestep that [`CoreStage::FixedUpdate`] runs at.
+///
+/// Every frame, [`Time::delta`] is added to an accumulator, and the stage runs once for every
+/// whole timestep in it. So that a slow frame doesn't make the next one slower still, at most
+/// [`max_steps`](Self::max_steps) are run per frame and the time beyond that is dropped.
+///
+/// What's left over is less than a timestep, and [`alpha`](Self::alpha) gives it as a fraction
+/// of one, for rendering to interpolate between the last two fixed steps.
+#[derive(Debug, Clone)]
+pub struct FixedTime {
+    timestep: Duration,
+    max_steps: u32,
+    accumulator: Duration,
+    elapsed: Duration,
+    step_count: u64,
+    steps_this_frame: u32,
+}
+
+impl Default for FixedTime {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TIMESTEP)
+    }
+}
+
+impl FixedTime {
+    /// 64 steps per second, which unlike 60 is exact in binary.
+    pub const DEFAULT_TIMESTEP: Duration = Duration::from_micros(15625);
+    pub const DEFAULT_MAX_STEPS: u32 = 8;
+
+    /// Panics if `timestep` is zero.
+    pub fn new(timestep: Duration) -> Self {
+        assert!(!timestep.is_zero(), "FixedTime::new(): timestep is zero");
+        Self {
+            timestep,
+            max_steps: Self::DEFAULT_MAX_STEPS,
+            accumulator: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            step_count: 0,
+            steps_this_frame: 0,
+        }
+    }
+
+    /// Runs `hz` steps per second.
+    pub fn from_hz(hz: f64) -> Self {
+        Self::new(Duration::from_secs_f64(1.0 / hz))
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// The time that passes in each step.
+    pub fn timestep(&self) -> Duration {
+        self.timestep
+    }
+
+    pub fn timestep_seconds(&self) -> f32 {
+        self.timestep.as_secs_f32()
+    }
+
+    /// Panics if `timestep` is zero.
+    pub fn set_timestep(&mut self, timestep: Duration) {
+        assert!(
+            !timestep.is_zero(),
+            "FixedTime::set_timestep(): timestep is zero"
+        );
+        self.timestep = timestep;
+    }
+
+    /// The most steps run in a single frame.
+    pub fn max_steps(&self) -> u32 {
+        self.max_steps
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.max_steps = max_steps;
+    }
+
+    /// The time that has passed but not been stepped through yet.
+    pub fn accumulator(&self) -> Duration {
+        self.accumulator
+    }
+
+    /// How far between the last step and the next one the current frame is, from 0 to 1.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.timestep.as_secs_f64()) as f32
+    }
+
+    /// The total time stepped through, which is the step count times the timestep if it was
+    /// never changed.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed.as_secs_f64()
+    }
+
+    /// The number of steps run so far.
+    pub fn step_count(&self) -> u64 {
+        self.step_count
+    }
+
+    /// The number of steps run in the current frame so far.
+    pub fn steps_this_frame(&self) -> u32 {
+        self.steps_this_frame
+    }
+
+    /// Starts a new frame, `delta` after the last one.
+    pub fn accumulate(&mut self, delta: Duration) {
+        self.accumulator += delta;
+        self.steps_this_frame = 0;
+    }
+
+    /// Takes a step out of the accumulator, if there's a whole one left and the frame hasn't
+    /// run [`max_steps`](Self::max_steps) yet.
+    ///
+    /// Once the frame is out of steps, whole timesteps still in the accumulator are dropped.
+    pub fn expend(&mut self) -> bool {
+        if self.accumulator < self.timestep {
+            return false;
+        }
+        if self.steps_this_frame >= self.max_steps {
+            let remainder = self.accumulator.as_nanos() % self.timestep.as_nanos();
+            self.accumulator = Duration::from_nanos(remainder as u64);
+            return false;
+        }
+        self.accumulator -= self.timestep;
+        self.elapsed += self.timestep;
+        self.step_count += 1;
+        self.steps_this_frame += 1;
+        true
+    }
+}
+
+/// Adds the [`Time`] resource, and updates it in [`CoreStage::First`]. Also adds a default
+/// [`FixedTime`], unless the app already has one.
+///
+/// `FixedTime` is stepped by the [`App`] itself when it runs [`CoreStage::FixedUpdate`].
+#[derive(Default)]
+pub struct TimePlugin;
+
+impl Plugin for TimePlugin {
+    fn build(&mut self, app: &mut App) -> KResult<()> {
+        let mut world = app.world().write();
+        world.insert_resource(Time::default());
+        if !world.contains_resource::<FixedTime>() {
+            world.insert_resource(FixedTime::default());
+        }
+        drop(world);
+        app.add_system(CoreStage::First, |mut time: ResMut<Time>| time.update())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time() {
+        let startup = Instant::now();
+        let mut time = Time::new(startup);
+        time.update_with_instant(startup + Duration::from_millis(10));
+        assert_eq!(time.delta(), Duration::from_millis(10));
+        time.update_with_instant(startup + Duration::from_millis(25));
+        assert_eq!(time.delta(), Duration::from_millis(15));
+        assert_eq!(time.elapsed(), Duration::from_millis(25));
+        assert_eq!(time.frame_count(), 2);
+    }
+
+    #[test]
+    fn test_fixed_time() {
+        let mut fixed = FixedTime::new(Duration::from_millis(10)).with_max_steps(3);
+        let steps = |fixed: &mut FixedTime, delta| {
+            fixed.accumulate(Duration::from_millis(delta));
+            let mut steps = 0;
+            while fixed.expend() {
+                steps += 1;
+            }
+            steps
+        };
+
+        assert_eq!(steps(&mut fixed, 4), 0);
+        assert_eq!(steps(&mut fixed, 4), 0);
+        assert!((fixed.alpha() - 0.8).abs() < 1e-6);
+        assert_eq!(steps(&mut fixed, 4), 1);
+        assert_eq!(fixed.accumulator(), Duration::from_millis(2));
+        assert_eq!(steps(&mut fixed, 25), 2);
+        assert_eq!(fixed.accumulator(), Duration::from_millis(7));
+
+        // a long frame catches up three steps, then drops the rest but keeps the remainder
+        assert_eq!(steps(&mut fixed, 100), 3);
+        assert_eq!(fixed.steps_this_frame(), 3);
+        assert_eq!(fixed.accumulator(), Duration::from_millis(7));
+        assert_eq!(steps(&mut fixed, 0), 0);
+        assert_eq!(fixed.step_count(), 6);
+        assert_eq!(fixed.elapsed(), Duration::from_millis(60));
+    }
+}
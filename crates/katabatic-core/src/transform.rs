@@ -0,0 +1,28 @@
+use katabatic_util::error::KResult;
+
+use crate::{app::App, plugin::Plugin, runner::Hook};
+
+/// Propagates transforms through each of the app's scenes before every frame is rendered, with
+/// [`Scene::propagate_transforms`](katabatic_scene::scene::Scene::propagate_transforms).
+///
+/// Hooks run in the order they were added, so add this before any rendering plugins.
+#[derive(Default)]
+pub struct TransformPlugin;
+
+impl Plugin for TransformPlugin {
+    fn build(&mut self, app: &mut App) -> KResult<()> {
+        app.add_hook(TransformPropagationHook);
+        Ok(())
+    }
+}
+
+pub struct TransformPropagationHook;
+
+impl Hook for TransformPropagationHook {
+    fn render(&self, app: &App) -> KResult<()> {
+        for (_, scene) in app.scenes() {
+            scene.write().propagate_transforms();
+        }
+        Ok(())
+    }
+}
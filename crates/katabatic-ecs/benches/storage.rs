@@ -0,0 +1,200 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use katabatic_ecs::{entity::Entity, world::World};
+
+const ENTITIES: u32 = 10_000;
+
+#[derive(Clone, Copy)]
+struct Position {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Clone, Copy)]
+struct Velocity {
+    dx: f32,
+    dy: f32,
+}
+
+/// The previous storage layout: one sparse set of boxed components per type.
+mod boxed {
+    use std::any::{Any, TypeId};
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    pub struct SparseSet {
+        dense: Vec<Box<dyn Any>>,
+        sparse: Vec<Option<usize>>,
+        indices: Vec<usize>,
+    }
+
+    impl SparseSet {
+        pub fn insert(&mut self, id: usize, data: Box<dyn Any>) {
+            let index = self.dense.len();
+            self.dense.push(data);
+            if id >= self.sparse.len() {
+                self.sparse.resize(id + 1, None);
+            }
+            self.sparse[id] = Some(index);
+            self.indices.push(id);
+        }
+
+        pub fn get_mut(&mut self, id: usize) -> Option<&mut Box<dyn Any>> {
+            let index = self.sparse.get(id).copied().flatten()?;
+            self.dense.get_mut(index)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (usize, &Box<dyn Any>)> + '_ {
+            self.indices.iter().copied().zip(self.dense.iter())
+        }
+    }
+
+    #[derive(Default)]
+    pub struct Storage {
+        pub columns: HashMap<TypeId, SparseSet>,
+    }
+
+    impl Storage {
+        pub fn insert<T: 'static>(&mut self, id: usize, value: T) {
+            self.columns
+                .entry(TypeId::of::<T>())
+                .or_default()
+                .insert(id, Box::new(value));
+        }
+    }
+}
+
+fn spawn_world() -> World {
+    let mut world = World::new();
+    for i in 0..ENTITIES {
+        let entity = world.create_entity();
+        world
+            .insert_component(
+                entity,
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+            )
+            .unwrap();
+        world
+            .insert_component(entity, Velocity { dx: 1.0, dy: 1.0 })
+            .unwrap();
+    }
+    world
+}
+
+fn spawn_boxed() -> boxed::Storage {
+    let mut storage = boxed::Storage::default();
+    for i in 0..ENTITIES as usize {
+        storage.insert(
+            i,
+            Position {
+                x: i as f32,
+                y: 0.0,
+            },
+        );
+        storage.insert(i, Velocity { dx: 1.0, dy: 1.0 });
+    }
+    storage
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.bench_function("columnar", |b| b.iter(|| black_box(spawn_world())));
+    group.bench_function("boxed", |b| b.iter(|| black_box(spawn_boxed())));
+    group.finish();
+}
+
+fn iterate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("iterate");
+
+    group.bench_function("columnar", |b| {
+        b.iter_batched_ref(
+            spawn_world,
+            |world| {
+                for archetype in world.storage().archetypes() {
+                    let (Some(positions), Some(velocities)) = (
+                        archetype.column(std::any::TypeId::of::<Position>()),
+                        archetype.column(std::any::TypeId::of::<Velocity>()),
+                    ) else {
+                        continue;
+                    };
+                    let mut positions = positions.write();
+                    let velocities = velocities.read();
+                    for (position, velocity) in positions
+                        .as_mut_slice::<Position>()
+                        .iter_mut()
+                        .zip(velocities.as_slice::<Velocity>())
+                    {
+                        position.x += velocity.dx;
+                        position.y += velocity.dy;
+                    }
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("columnar_query", |b| {
+        b.iter_batched_ref(
+            spawn_world,
+            |world| {
+                for (mut position, velocity) in world.query::<(&mut Position, &Velocity)>().iter() {
+                    position.x += velocity.dx;
+                    position.y += velocity.dy;
+                }
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("boxed", |b| {
+        b.iter_batched_ref(
+            spawn_boxed,
+            |storage| {
+                let velocities = storage
+                    .columns
+                    .remove(&std::any::TypeId::of::<Velocity>())
+                    .unwrap();
+                let positions = storage
+                    .columns
+                    .get_mut(&std::any::TypeId::of::<Position>())
+                    .unwrap();
+                for (id, velocity) in velocities.iter() {
+                    let velocity = velocity.downcast_ref::<Velocity>().unwrap();
+                    let position = positions
+                        .get_mut(id)
+                        .unwrap()
+                        .downcast_mut::<Position>()
+                        .unwrap();
+                    position.x += velocity.dx;
+                    position.y += velocity.dy;
+                }
+                storage
+                    .columns
+                    .insert(std::any::TypeId::of::<Velocity>(), velocities);
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+fn lookup(c: &mut Criterion) {
+    let world = spawn_world();
+    c.bench_function("lookup/columnar", |b| {
+        b.iter(|| {
+            for id in 0..ENTITIES {
+                black_box(
+                    world
+                        .get_component::<Position>(Entity::new(id, 0))
+                        .map(|p| p.x),
+                );
+            }
+        })
+    });
+}
+
+criterion_group!(benches, insert, iterate, lookup);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+use std::{any::TypeId, collections::HashSet};
+
+/// The set of component and resource types a query or system reads and writes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    reads: HashSet<TypeId>,
+    writes: HashSet<TypeId>,
+    resource_reads: HashSet<TypeId>,
+    resource_writes: HashSet<TypeId>,
+    self_conflicting: bool,
+}
+
+impl Access {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_read(&mut self, type_id: TypeId) {
+        self.self_conflicting |= self.writes.contains(&type_id);
+        self.reads.insert(type_id);
+    }
+
+    pub fn add_write(&mut self, type_id: TypeId) {
+        self.self_conflicting |= self.reads.contains(&type_id) || self.writes.contains(&type_id);
+        self.writes.insert(type_id);
+    }
+
+    pub fn add_resource_read(&mut self, type_id: TypeId) {
+        self.self_conflicting |= self.resource_writes.contains(&type_id);
+        self.resource_reads.insert(type_id);
+    }
+
+    pub fn add_resource_write(&mut self, type_id: TypeId) {
+        self.self_conflicting |=
+            self.resource_reads.contains(&type_id) || self.resource_writes.contains(&type_id);
+        self.resource_writes.insert(type_id);
+    }
+
+    pub fn reads(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.reads.iter().copied()
+    }
+
+    pub fn writes(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.writes.iter().copied()
+    }
+
+    pub fn has_read(&self, type_id: TypeId) -> bool {
+        self.reads.contains(&type_id)
+    }
+
+    pub fn has_write(&self, type_id: TypeId) -> bool {
+        self.writes.contains(&type_id)
+    }
+
+    pub fn resource_reads(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.resource_reads.iter().copied()
+    }
+
+    pub fn resource_writes(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.resource_writes.iter().copied()
+    }
+
+    pub fn has_resource_read(&self, type_id: TypeId) -> bool {
+        self.resource_reads.contains(&type_id)
+    }
+
+    pub fn has_resource_write(&self, type_id: TypeId) -> bool {
+        self.resource_writes.contains(&type_id)
+    }
+
+    /// Returns `true` if neither side writes a type that the other one reads or writes.
+    pub fn is_compatible(&self, other: &Access) -> bool {
+        self.writes.is_disjoint(&other.writes)
+            && self.writes.is_disjoint(&other.reads)
+            && other.writes.is_disjoint(&self.reads)
+            && self.resource_writes.is_disjoint(&other.resource_writes)
+            && self.resource_writes.is_disjoint(&other.resource_reads)
+            && other.resource_writes.is_disjoint(&self.resource_reads)
+    }
+
+    /// Returns `true` if the same type was both read and written, or written twice.
+    pub fn is_self_conflicting(&self) -> bool {
+        self.self_conflicting
+    }
+
+    pub fn extend(&mut self, other: &Access) {
+        self.self_conflicting |= other.self_conflicting || !self.is_compatible(other);
+        self.reads.extend(other.reads.iter().copied());
+        self.writes.extend(other.writes.iter().copied());
+        self.resource_reads
+            .extend(other.resource_reads.iter().copied());
+        self.resource_writes
+            .extend(other.resource_writes.iter().copied());
+    }
+}
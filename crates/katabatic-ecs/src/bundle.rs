@@ -0,0 +1,183 @@
+use std::{any::TypeId, mem::ManuallyDrop};
+
+use crate::component::{Component, ComponentInfo};
+
+pub use katabatic_macros::Bundle;
+
+/// A set of components that are inserted into or removed from an entity together.
+///
+/// Implemented for tuples of up to eight components, and for structs with `#[derive(Bundle)]`.
+/// Moving an entity by a whole bundle only changes its archetype once.
+///
+/// # Safety
+/// `take_components` must call `f` exactly once for every type reported by `component_infos`,
+/// with a pointer to a valid value of that type whose ownership passes to `f`.
+pub unsafe trait Bundle: Sized + Send + Sync + 'static {
+    fn component_infos(infos: &mut Vec<ComponentInfo>);
+
+    /// Passes each component to `f`, which takes ownership of it.
+    fn take_components(self, f: &mut dyn FnMut(ComponentInfo, *mut u8));
+
+    /// Rebuilds the bundle, calling `take` once for each of its component types.
+    fn from_components(take: &mut dyn FnMut(TypeId) -> Box<dyn Component>) -> Self;
+}
+
+/// Passes a single component to `f`. Used by `#[derive(Bundle)]`.
+pub fn take_component<T: Component>(value: T, f: &mut dyn FnMut(ComponentInfo, *mut u8)) {
+    let mut value = ManuallyDrop::new(value);
+    f(ComponentInfo::of::<T>(), (&mut *value as *mut T).cast());
+}
+
+/// Takes a single component out of `take`. Used by `#[derive(Bundle)]`.
+pub fn from_component<T: Component>(take: &mut dyn FnMut(TypeId) -> Box<dyn Component>) -> T {
+    *take(TypeId::of::<T>())
+        .as_any_box()
+        .downcast::<T>()
+        .expect("Bundle::from_components(): wrong component type")
+}
+
+macro_rules! impl_bundle_tuple {
+    ($($name:ident),*) => {
+        unsafe impl<$($name: Component),*> Bundle for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn component_infos(infos: &mut Vec<ComponentInfo>) {
+                $(infos.push(ComponentInfo::of::<$name>());)*
+            }
+
+            #[allow(unused_variables, non_snake_case)]
+            fn take_components(self, f: &mut dyn FnMut(ComponentInfo, *mut u8)) {
+                let ($($name,)*) = self;
+                $(take_component($name, f);)*
+            }
+
+            #[allow(unused_variables, clippy::unused_unit)]
+            fn from_components(take: &mut dyn FnMut(TypeId) -> Box<dyn Component>) -> Self {
+                ($(from_component::<$name>(take),)*)
+            }
+        }
+    };
+}
+
+impl_bundle_tuple!();
+impl_bundle_tuple!(A);
+impl_bundle_tuple!(A, B);
+impl_bundle_tuple!(A, B, C);
+impl_bundle_tuple!(A, B, C, D);
+impl_bundle_tuple!(A, B, C, D, E);
+impl_bundle_tuple!(A, B, C, D, E, F);
+impl_bundle_tuple!(A, B, C, D, E, F, G);
+impl_bundle_tuple!(A, B, C, D, E, F, G, H);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::world::World;
+
+    #[derive(Debug, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity {
+        dx: f32,
+        dy: f32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Name(String);
+
+    #[derive(Debug, PartialEq, Bundle)]
+    struct Body {
+        position: Position,
+        velocity: Velocity,
+    }
+
+    #[derive(Debug, PartialEq, Bundle)]
+    struct Named {
+        name: Name,
+        #[bundle]
+        body: Body,
+    }
+
+    #[test]
+    fn test_spawn() {
+        let mut world = World::new();
+
+        let entity = world.spawn((Position { x: 1.0, y: 2.0 }, Velocity { dx: 3.0, dy: 4.0 }));
+
+        assert_eq!(world.get_component::<Position>(entity).unwrap().x, 1.0);
+        assert_eq!(world.get_component::<Velocity>(entity).unwrap().dy, 4.0);
+
+        // the entity moved straight from the empty archetype into its final one
+        assert_eq!(world.storage().archetypes().count(), 2);
+    }
+
+    #[test]
+    fn test_insert_bundle() {
+        let mut world = World::new();
+
+        let entity = world.spawn((Position { x: 1.0, y: 2.0 },));
+        world
+            .insert_bundle(
+                entity,
+                (Position { x: 5.0, y: 6.0 }, Name("player".to_string())),
+            )
+            .unwrap();
+
+        assert_eq!(world.get_component::<Position>(entity).unwrap().x, 5.0);
+        assert_eq!(world.get_component::<Name>(entity).unwrap().0, "player");
+        assert_eq!(world.storage().archetypes().count(), 3);
+    }
+
+    #[test]
+    fn test_remove_bundle() {
+        let mut world = World::new();
+
+        let entity = world.spawn((
+            Position { x: 1.0, y: 2.0 },
+            Velocity { dx: 3.0, dy: 4.0 },
+            Name("player".to_string()),
+        ));
+
+        assert_eq!(world.remove_bundle::<(Position, u32)>(entity), None);
+
+        let (name, position) = world.remove_bundle::<(Name, Position)>(entity).unwrap();
+        assert_eq!(name.0, "player");
+        assert_eq!(position, Position { x: 1.0, y: 2.0 });
+
+        assert!(!world.has_component::<Position>(entity));
+        assert!(!world.has_component::<Name>(entity));
+        assert_eq!(world.get_component::<Velocity>(entity).unwrap().dx, 3.0);
+    }
+
+    #[test]
+    fn test_derive_bundle() {
+        let mut world = World::new();
+
+        let entity = world.spawn(Named {
+            name: Name("player".to_string()),
+            body: Body {
+                position: Position { x: 1.0, y: 2.0 },
+                velocity: Velocity { dx: 3.0, dy: 4.0 },
+            },
+        });
+
+        assert!(world.has_component::<Name>(entity));
+        assert!(world.has_component::<Position>(entity));
+        assert!(world.has_component::<Velocity>(entity));
+
+        let body = world.remove_bundle::<Body>(entity).unwrap();
+        assert_eq!(body.velocity, Velocity { dx: 3.0, dy: 4.0 });
+        assert!(world.has_component::<Name>(entity));
+        assert!(!world.has_component::<Position>(entity));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_types() {
+        let mut world = World::new();
+        world.spawn((Position { x: 1.0, y: 2.0 }, Position { x: 3.0, y: 4.0 }));
+    }
+}
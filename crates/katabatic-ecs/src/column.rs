@@ -0,0 +1,336 @@
+use std::{
+    alloc::{self, Layout},
+    ptr::NonNull,
+};
+
+use crate::component::{Component, ComponentInfo};
+
+/// A type-erased, tightly packed array of one component type.
+///
+/// Each row is stored inline in a single aligned allocation, so iterating a column touches
+/// contiguous memory instead of chasing one heap pointer per component.
+pub struct Column {
+    info: ComponentInfo,
+    data: NonNull<u8>,
+    len: usize,
+    capacity: usize,
+}
+
+impl Column {
+    pub fn new(info: ComponentInfo) -> Self {
+        let capacity = if info.layout().size() == 0 {
+            usize::MAX
+        } else {
+            0
+        };
+
+        Self {
+            info,
+            data: dangling(info.layout()),
+            len: 0,
+            capacity,
+        }
+    }
+
+    pub fn info(&self) -> &ComponentInfo {
+        &self.info
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Moves `value` into a new row at the end of the column.
+    ///
+    /// # Panics
+    /// Panics if `T` isn't the column's component type.
+    pub fn push<T: Component>(&mut self, value: T) {
+        self.assert_type::<T>();
+        let mut value = std::mem::ManuallyDrop::new(value);
+        unsafe { self.push_raw((&mut *value as *mut T).cast()) }
+    }
+
+    /// Copies the value at `src` into a new row at the end of the column.
+    ///
+    /// # Safety
+    /// `src` must point to a valid value of the column's component type. Ownership of the value
+    /// moves into the column, so it must not be used or dropped afterwards.
+    pub unsafe fn push_raw(&mut self, src: *const u8) {
+        self.reserve(1);
+        let size = self.info.layout().size();
+        std::ptr::copy_nonoverlapping(src, self.get_ptr_unchecked(self.len), size);
+        self.len += 1;
+    }
+
+    /// Drops the value at `row` and moves `src` into its place.
+    ///
+    /// # Safety
+    /// `row` must be in bounds, and `src` must point to a valid value of the column's
+    /// component type, which must not be used or dropped afterwards.
+    pub unsafe fn replace_raw(&mut self, row: usize, src: *const u8) {
+        debug_assert!(row < self.len);
+        let dst = self.get_ptr_unchecked(row);
+        self.info.drop_in_place(dst);
+        std::ptr::copy_nonoverlapping(src, dst, self.info.layout().size());
+    }
+
+    /// Removes the value at `row` without dropping it, moving the last row into its place.
+    ///
+    /// # Safety
+    /// `row` must be in bounds, and the value at `row` must already have been moved out or dropped.
+    pub unsafe fn swap_remove_forget(&mut self, row: usize) {
+        debug_assert!(row < self.len);
+        let last = self.len - 1;
+        if row != last {
+            let size = self.info.layout().size();
+            std::ptr::copy_nonoverlapping(
+                self.get_ptr_unchecked(last),
+                self.get_ptr_unchecked(row),
+                size,
+            );
+        }
+        self.len -= 1;
+    }
+
+    /// Drops the value at `row`, moving the last row into its place.
+    ///
+    /// # Panics
+    /// Panics if `row` is out of bounds.
+    pub fn swap_remove_drop(&mut self, row: usize) {
+        assert!(
+            row < self.len,
+            "Column::swap_remove_drop(): row out of bounds"
+        );
+        unsafe {
+            self.info.drop_in_place(self.get_ptr_unchecked(row));
+            self.swap_remove_forget(row);
+        }
+    }
+
+    /// Moves the value at `row` out of the column, moving the last row into its place.
+    ///
+    /// # Panics
+    /// Panics if `row` is out of bounds or `T` isn't the column's component type.
+    pub fn swap_remove<T: Component>(&mut self, row: usize) -> T {
+        self.assert_type::<T>();
+        assert!(row < self.len, "Column::swap_remove(): row out of bounds");
+        unsafe {
+            let value = self.get_ptr_unchecked(row).cast::<T>().read();
+            self.swap_remove_forget(row);
+            value
+        }
+    }
+
+    pub fn get_ptr(&self, row: usize) -> Option<*mut u8> {
+        (row < self.len).then(|| unsafe { self.get_ptr_unchecked(row) })
+    }
+
+    /// # Safety
+    /// `row` must be less than or equal to the column's capacity.
+    pub unsafe fn get_ptr_unchecked(&self, row: usize) -> *mut u8 {
+        self.data.as_ptr().add(row * self.info.layout().size())
+    }
+
+    pub fn get<T: Component>(&self, row: usize) -> Option<&T> {
+        self.assert_type::<T>();
+        self.get_ptr(row).map(|ptr| unsafe { &*ptr.cast::<T>() })
+    }
+
+    pub fn get_mut<T: Component>(&mut self, row: usize) -> Option<&mut T> {
+        self.assert_type::<T>();
+        self.get_ptr(row)
+            .map(|ptr| unsafe { &mut *ptr.cast::<T>() })
+    }
+
+    pub fn get_dyn(&self, row: usize) -> Option<&dyn Component> {
+        self.get_ptr(row)
+            .map(|ptr| unsafe { &*self.info.as_dyn(ptr) })
+    }
+
+    pub fn get_dyn_mut(&mut self, row: usize) -> Option<&mut dyn Component> {
+        self.get_ptr(row)
+            .map(|ptr| unsafe { &mut *self.info.as_dyn(ptr) })
+    }
+
+    pub fn as_slice<T: Component>(&self) -> &[T] {
+        self.assert_type::<T>();
+        unsafe { std::slice::from_raw_parts(self.data.as_ptr().cast(), self.len) }
+    }
+
+    pub fn as_mut_slice<T: Component>(&mut self) -> &mut [T] {
+        self.assert_type::<T>();
+        unsafe { std::slice::from_raw_parts_mut(self.data.as_ptr().cast(), self.len) }
+    }
+
+    pub fn clear(&mut self) {
+        let len = self.len;
+        // set the length first so a panicking drop can't cause a double drop
+        self.len = 0;
+        for row in 0..len {
+            unsafe { self.info.drop_in_place(self.get_ptr_unchecked(row)) };
+        }
+    }
+
+    fn assert_type<T: Component>(&self) {
+        assert_eq!(
+            self.info.type_id(),
+            std::any::TypeId::of::<T>(),
+            "Column: expected {}, got {}",
+            self.info.type_name(),
+            std::any::type_name::<T>()
+        );
+    }
+
+    fn reserve(&mut self, additional: usize) {
+        let required = self
+            .len
+            .checked_add(additional)
+            .expect("Column: capacity overflow");
+        if required <= self.capacity {
+            return;
+        }
+
+        let new_capacity = required.max(self.capacity * 2).max(4);
+        let new_layout = array_layout(self.info.layout(), new_capacity);
+
+        let new_data = unsafe {
+            if self.capacity == 0 {
+                alloc::alloc(new_layout)
+            } else {
+                alloc::realloc(
+                    self.data.as_ptr(),
+                    array_layout(self.info.layout(), self.capacity),
+                    new_layout.size(),
+                )
+            }
+        };
+
+        self.data = NonNull::new(new_data).unwrap_or_else(|| alloc::handle_alloc_error(new_layout));
+        self.capacity = new_capacity;
+    }
+}
+
+// SAFETY: a column only ever holds values of its component type, and components are `Send + Sync`
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
+impl Drop for Column {
+    fn drop(&mut self) {
+        self.clear();
+        if self.info.layout().size() != 0 && self.capacity != 0 {
+            unsafe {
+                alloc::dealloc(
+                    self.data.as_ptr(),
+                    array_layout(self.info.layout(), self.capacity),
+                )
+            };
+        }
+    }
+}
+
+fn array_layout(layout: Layout, n: usize) -> Layout {
+    // a type's size is always a multiple of its alignment, so rows need no extra padding
+    let size = layout
+        .size()
+        .checked_mul(n)
+        .expect("Column: capacity overflow");
+    Layout::from_size_align(size, layout.align()).expect("Column: capacity overflow")
+}
+
+fn dangling(layout: Layout) -> NonNull<u8> {
+    // a well-aligned, non-null pointer that is never dereferenced for non-zero sizes
+    NonNull::new(layout.align() as *mut u8).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    use super::*;
+
+    #[test]
+    fn test_push_get() {
+        let mut column = Column::new(ComponentInfo::of::<u64>());
+
+        for i in 0..100u64 {
+            column.push(i);
+        }
+
+        assert_eq!(column.len(), 100);
+        assert_eq!(column.get::<u64>(42), Some(&42));
+        assert_eq!(column.get::<u64>(100), None);
+        assert_eq!(column.as_slice::<u64>().iter().sum::<u64>(), 4950);
+    }
+
+    #[test]
+    fn test_swap_remove() {
+        let mut column = Column::new(ComponentInfo::of::<String>());
+
+        column.push("a".to_string());
+        column.push("b".to_string());
+        column.push("c".to_string());
+
+        assert_eq!(column.swap_remove::<String>(0), "a");
+        assert_eq!(column.as_slice::<String>(), &["c", "b"]);
+    }
+
+    #[test]
+    fn test_drop() {
+        let arc = Arc::new(());
+        let mut column = Column::new(ComponentInfo::of::<Arc<()>>());
+
+        for _ in 0..10 {
+            column.push(arc.clone());
+        }
+        assert_eq!(Arc::strong_count(&arc), 11);
+
+        column.swap_remove_drop(3);
+        assert_eq!(Arc::strong_count(&arc), 10);
+
+        drop(column);
+        assert_eq!(Arc::strong_count(&arc), 1);
+    }
+
+    #[test]
+    fn test_zero_sized() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        struct Marker;
+
+        impl Drop for Marker {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+
+        let mut column = Column::new(ComponentInfo::of::<Marker>());
+        column.push(Marker);
+        column.push(Marker);
+        assert_eq!(column.len(), 2);
+        assert!(column.get::<Marker>(1).is_some());
+
+        drop(column);
+        assert_eq!(DROPS.load(std::sync::atomic::Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_alignment() {
+        #[repr(align(64))]
+        struct Aligned(u8);
+
+        let mut column = Column::new(ComponentInfo::of::<Aligned>());
+        for i in 0..10 {
+            column.push(Aligned(i));
+        }
+
+        for row in 0..10 {
+            let ptr = column.get_ptr(row).unwrap();
+            assert_eq!(ptr as usize % 64, 0);
+            assert_eq!(column.get::<Aligned>(row).unwrap().0, row as u8);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use katabatic_util::error::KResult;
+
+use crate::{
+    bundle::Bundle,
+    component::Component,
+    entity::{Entities, Entity},
+    resource::Resource,
+    world::World,
+};
+
+/// A change to a [`World`] that is recorded now and applied later, when the world can be
+/// borrowed mutably.
+pub trait Command: Send + Sync + 'static {
+    fn apply(self, world: &mut World) -> KResult<()>;
+}
+
+impl<F> Command for F
+where
+    F: FnOnce(&mut World) -> KResult<()> + Send + Sync + 'static,
+{
+    fn apply(self, world: &mut World) -> KResult<()> {
+        self(world)
+    }
+}
+
+type BoxedCommand = Box<dyn FnOnce(&mut World) -> KResult<()> + Send + Sync>;
+
+/// A list of [`Command`]s waiting to be applied, in the order they were pushed.
+#[derive(Default)]
+pub struct CommandQueue {
+    commands: Vec<BoxedCommand>,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<C: Command>(&mut self, command: C) {
+        self.commands
+            .push(Box::new(move |world: &mut World| command.apply(world)));
+    }
+
+    /// Moves every command in `other` to the end of this queue.
+    pub fn append(&mut self, other: &mut CommandQueue) {
+        self.commands.append(&mut other.commands);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Spawns any reserved entities, then applies and removes every command.
+    ///
+    /// A command failing doesn't stop the ones after it. The first error is returned.
+    pub fn apply(&mut self, world: &mut World) -> KResult<()> {
+        world.flush_entities();
+
+        let mut result = Ok(());
+        for command in self.commands.drain(..) {
+            let applied = command(world);
+            if result.is_ok() {
+                result = applied;
+            }
+        }
+        result
+    }
+}
+
+impl std::fmt::Debug for CommandQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommandQueue")
+            .field("len", &self.commands.len())
+            .finish()
+    }
+}
+
+/// Records structural changes to a [`World`] while only holding a shared reference to it.
+///
+/// Spawned entities are reserved right away, so their ids can be used immediately, but they
+/// aren't alive until the queue is applied.
+pub struct Commands<'w, 's> {
+    queue: &'s mut CommandQueue,
+    entities: &'w Entities,
+}
+
+impl<'w, 's> Commands<'w, 's> {
+    pub fn new(queue: &'s mut CommandQueue, world: &'w World) -> Self {
+        Self {
+            queue,
+            entities: world.entities(),
+        }
+    }
+
+    /// Spawns an entity with no components.
+    pub fn spawn_empty(&mut self) -> EntityCommands<'_> {
+        let entity = self.entities.reserve();
+        EntityCommands {
+            entity,
+            queue: self.queue,
+        }
+    }
+
+    /// Spawns an entity with every component in `bundle`.
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> EntityCommands<'_> {
+        let mut entity = self.spawn_empty();
+        entity.insert_bundle(bundle);
+        entity
+    }
+
+    /// Returns the commands for an existing entity.
+    pub fn entity(&mut self, entity: Entity) -> EntityCommands<'_> {
+        EntityCommands {
+            entity,
+            queue: self.queue,
+        }
+    }
+
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) {
+        self.queue.push(move |world: &mut World| {
+            world.insert_resource(resource);
+            Ok(())
+        });
+    }
+
+    pub fn remove_resource<R: Resource>(&mut self) {
+        self.queue.push(|world: &mut World| {
+            world.remove_resource::<R>();
+            Ok(())
+        });
+    }
+
+    pub fn add<C: Command>(&mut self, command: C) {
+        self.queue.push(command);
+    }
+}
+
+/// Records changes to a single entity. Created by [`Commands`].
+pub struct EntityCommands<'a> {
+    entity: Entity,
+    queue: &'a mut CommandQueue,
+}
+
+impl<'a> EntityCommands<'a> {
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    pub fn insert<T: Component>(&mut self, component: T) -> &mut Self {
+        let entity = self.entity;
+        self.queue
+            .push(move |world: &mut World| world.insert_component(entity, component));
+        self
+    }
+
+    pub fn insert_bundle<B: Bundle>(&mut self, bundle: B) -> &mut Self {
+        let entity = self.entity;
+        self.queue
+            .push(move |world: &mut World| world.insert_bundle(entity, bundle));
+        self
+    }
+
+    pub fn remove<T: Component>(&mut self) -> &mut Self {
+        let entity = self.entity;
+        self.queue.push(move |world: &mut World| {
+            world.remove_component::<T>(entity);
+            Ok(())
+        });
+        self
+    }
+
+    pub fn remove_bundle<B: Bundle>(&mut self) -> &mut Self {
+        let entity = self.entity;
+        self.queue.push(move |world: &mut World| {
+            world.remove_bundle::<B>(entity);
+            Ok(())
+        });
+        self
+    }
+
+    /// Destroys the entity. Applying this fails if the entity is already gone.
+    pub fn despawn(self) {
+        let entity = self.entity;
+        self.queue
+            .push(move |world: &mut World| world.destroy_entity(entity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity {
+        dx: f32,
+        dy: f32,
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut world = World::new();
+        let existing = world.spawn((Position { x: 0.0, y: 0.0 },));
+        let doomed = world.spawn((Position { x: 1.0, y: 1.0 },));
+
+        let mut queue = CommandQueue::new();
+        let spawned = {
+            let mut commands = Commands::new(&mut queue, &world);
+
+            // iterating a query while recording structural changes
+            for entity in world.query::<Entity>().entity_iter() {
+                if entity == doomed {
+                    commands.entity(entity).despawn();
+                } else {
+                    commands
+                        .entity(entity)
+                        .insert(Velocity { dx: 1.0, dy: 0.0 })
+                        .remove::<Position>();
+                }
+            }
+
+            commands
+                .spawn((Position { x: 2.0, y: 2.0 }, Velocity { dx: 0.0, dy: 1.0 }))
+                .id()
+        };
+
+        assert!(!world.is_alive(spawned));
+        assert_eq!(queue.len(), 4);
+
+        queue.apply(&mut world).unwrap();
+        assert!(queue.is_empty());
+
+        assert!(!world.is_alive(doomed));
+        assert!(!world.has_component::<Position>(existing));
+        assert!(world.has_component::<Velocity>(existing));
+        assert!(world.is_alive(spawned));
+        assert_eq!(world.get_component::<Position>(spawned).unwrap().x, 2.0);
+    }
+
+    #[test]
+    fn test_failing_command() {
+        let mut world = World::new();
+        let entity = world.create_entity();
+
+        let mut queue = CommandQueue::new();
+        let mut commands = Commands::new(&mut queue, &world);
+        commands.entity(entity).despawn();
+        commands.entity(entity).despawn();
+        commands.insert_resource(5u32);
+
+        // the second despawn fails, but the resource is still inserted
+        assert!(queue.apply(&mut world).is_err());
+        assert_eq!(*world.resource::<u32>().unwrap(), 5);
+    }
+}
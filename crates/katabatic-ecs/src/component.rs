@@ -1,10 +1,18 @@
-pub trait Component: 'static {
+use std::{
+    alloc::Layout,
+    any::{type_name, TypeId},
+};
+
+/// Data that can be attached to an entity.
+///
+/// Components must be `Send + Sync` so systems touching them can run on any thread.
+pub trait Component: Send + Sync + 'static {
     fn as_any(&self) -> &dyn std::any::Any;
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any;
     fn as_any_box(self: Box<Self>) -> Box<dyn std::any::Any>;
 }
 
-impl<T: 'static> Component for T {
+impl<T: Send + Sync + 'static> Component for T {
     fn as_any(&self) -> &dyn std::any::Any {
         self
     }
@@ -17,3 +25,76 @@ impl<T: 'static> Component for T {
         self
     }
 }
+
+/// Everything storage needs to know to handle a component type without knowing the type itself.
+#[derive(Clone, Copy, Debug)]
+pub struct ComponentInfo {
+    type_id: TypeId,
+    type_name: &'static str,
+    layout: Layout,
+    drop: Option<unsafe fn(*mut u8)>,
+    as_dyn: unsafe fn(*mut u8) -> *mut dyn Component,
+    into_box: unsafe fn(*mut u8) -> Box<dyn Component>,
+}
+
+impl ComponentInfo {
+    pub fn of<T: Component>() -> Self {
+        unsafe fn drop<T>(ptr: *mut u8) {
+            ptr.cast::<T>().drop_in_place()
+        }
+
+        unsafe fn as_dyn<T: Component>(ptr: *mut u8) -> *mut dyn Component {
+            ptr.cast::<T>() as *mut dyn Component
+        }
+
+        unsafe fn into_box<T: Component>(ptr: *mut u8) -> Box<dyn Component> {
+            Box::new(ptr.cast::<T>().read())
+        }
+
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            layout: Layout::new::<T>(),
+            drop: std::mem::needs_drop::<T>().then_some(drop::<T> as unsafe fn(*mut u8)),
+            as_dyn: as_dyn::<T>,
+            into_box: into_box::<T>,
+        }
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// Drops the value at `ptr` in place.
+    ///
+    /// # Safety
+    /// `ptr` must point to a valid, initialized value of this component type.
+    pub unsafe fn drop_in_place(&self, ptr: *mut u8) {
+        if let Some(drop) = self.drop {
+            drop(ptr)
+        }
+    }
+
+    /// # Safety
+    /// `ptr` must point to a valid, initialized value of this component type.
+    pub unsafe fn as_dyn(&self, ptr: *mut u8) -> *mut dyn Component {
+        (self.as_dyn)(ptr)
+    }
+
+    /// Moves the value at `ptr` into a new box.
+    ///
+    /// # Safety
+    /// `ptr` must point to a valid, initialized value of this component type, which must not be
+    /// used or dropped afterwards.
+    pub unsafe fn into_box(&self, ptr: *mut u8) -> Box<dyn Component> {
+        (self.into_box)(ptr)
+    }
+}
@@ -1,3 +1,7 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use katabatic_util::{error::KResult, kbail};
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[repr(C)]
 pub struct Entity {
@@ -18,3 +22,265 @@ impl Entity {
         self.generation
     }
 }
+
+#[derive(Debug, Clone, Copy, Default)]
+struct EntityMeta {
+    generation: u32,
+    alive: bool,
+}
+
+/// Hands out entity ids and records the current generation of each one.
+///
+/// Destroying an entity bumps its id's generation, so any [`Entity`] handle still pointing at
+/// the old generation is no longer alive, even once the id is reused.
+///
+/// Entities can also be [reserved](Self::reserve) through a shared reference, which is how
+/// deferred commands refer to entities they haven't spawned yet.
+#[derive(Debug, Default)]
+pub struct Entities {
+    meta: Vec<EntityMeta>,
+    free_entities: Vec<Entity>,
+    len: usize,
+    // reserved entities taken from the end of `free_entities`, possibly more than there are
+    reserved_free: AtomicUsize,
+    // reserved entities with brand new ids, past the end of `meta`
+    reserved_new: AtomicU32,
+}
+
+impl Entities {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn alloc(&mut self) -> Entity {
+        self.flush();
+        self.len += 1;
+
+        if let Some(entity) = self.free_entities.pop() {
+            self.meta[entity.id() as usize].alive = true;
+            entity
+        } else {
+            let id = u32::try_from(self.meta.len()).expect("Entities::alloc(): out of entity ids");
+            self.meta.push(EntityMeta {
+                generation: 0,
+                alive: true,
+            });
+            Entity::new(id, 0)
+        }
+    }
+
+    pub fn free(&mut self, entity: Entity) -> KResult<()> {
+        self.flush();
+        if !self.is_alive(entity) {
+            kbail!(format!(
+                "Entities::free(): {:?} is not alive (already destroyed, or never created)",
+                entity
+            ));
+        }
+
+        let meta = &mut self.meta[entity.id() as usize];
+        meta.alive = false;
+        meta.generation = meta.generation.wrapping_add(1);
+        self.free_entities
+            .push(Entity::new(entity.id(), meta.generation));
+        self.len -= 1;
+
+        Ok(())
+    }
+
+    /// Reserves an entity without needing exclusive access.
+    ///
+    /// The entity isn't alive until the next call to [`flush`](Self::flush), which happens
+    /// automatically before the next `alloc` or `free`.
+    pub fn reserve(&self) -> Entity {
+        let n = self.reserved_free.fetch_add(1, Ordering::Relaxed);
+        if n < self.free_entities.len() {
+            self.free_entities[self.free_entities.len() - 1 - n]
+        } else {
+            let n = self.reserved_new.fetch_add(1, Ordering::Relaxed);
+            let id = u32::try_from(self.meta.len() + n as usize)
+                .expect("Entities::reserve(): out of entity ids");
+            Entity::new(id, 0)
+        }
+    }
+
+    /// Makes every reserved entity alive.
+    pub fn flush(&mut self) {
+        let reserved_free =
+            std::mem::take(self.reserved_free.get_mut()).min(self.free_entities.len());
+        let start = self.free_entities.len() - reserved_free;
+        for entity in self.free_entities.drain(start..) {
+            self.meta[entity.id() as usize].alive = true;
+        }
+
+        let reserved_new = std::mem::take(self.reserved_new.get_mut()) as usize;
+        self.meta.resize(
+            self.meta.len() + reserved_new,
+            EntityMeta {
+                generation: 0,
+                alive: true,
+            },
+        );
+
+        self.len += reserved_free + reserved_new;
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.meta
+            .get(entity.id() as usize)
+            .is_some_and(|meta| meta.alive && meta.generation == entity.generation())
+    }
+
+    /// Returns the live entity with the given id, if there is one.
+    pub fn get(&self, id: u32) -> Option<Entity> {
+        let meta = self.meta.get(id as usize)?;
+        meta.alive.then_some(Entity::new(id, meta.generation))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.meta
+            .iter()
+            .enumerate()
+            .filter(|(_, meta)| meta.alive)
+            .map(|(id, meta)| Entity::new(id as u32, meta.generation))
+    }
+
+    /// The generation of every id ever handed out, and whether it's alive, in id order.
+    pub(crate) fn generations(&self) -> impl Iterator<Item = (u32, bool)> + '_ {
+        self.meta.iter().map(|meta| (meta.generation, meta.alive))
+    }
+
+    /// Replaces every id's generation and the free list, as saved by
+    /// [`generations`](Self::generations) and [`free_entities`](Self::free_entities).
+    ///
+    /// Fails, leaving everything as it was, if the free list doesn't hold exactly the dead ids
+    /// with their current generations.
+    pub(crate) fn restore(
+        &mut self,
+        generations: &[(u32, bool)],
+        free_entities: &[Entity],
+    ) -> KResult<()> {
+        let dead = generations.iter().filter(|(_, alive)| !alive).count();
+        if dead != free_entities.len() {
+            kbail!(format!(
+                "Entities::restore(): {} dead entities, but {} in the free list",
+                dead,
+                free_entities.len()
+            ));
+        }
+        let mut seen = vec![false; generations.len()];
+        for entity in free_entities {
+            let id = entity.id() as usize;
+            match generations.get(id) {
+                Some(&(generation, false)) if generation == entity.generation() && !seen[id] => {
+                    seen[id] = true;
+                }
+                _ => kbail!(format!(
+                    "Entities::restore(): {:?} in the free list is not a dead entity",
+                    entity
+                )),
+            }
+        }
+
+        self.meta = generations
+            .iter()
+            .map(|&(generation, alive)| EntityMeta { generation, alive })
+            .collect();
+        self.free_entities = free_entities.to_vec();
+        self.len = generations.len() - dead;
+        *self.reserved_free.get_mut() = 0;
+        *self.reserved_new.get_mut() = 0;
+        Ok(())
+    }
+
+    /// The entities waiting to be reused, with the generation they'll be handed out with.
+    ///
+    /// Includes any that have been reserved since the last [`flush`](Self::flush).
+    pub fn free_entities(&self) -> &[Entity] {
+        &self.free_entities
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alloc_free() {
+        let mut entities = Entities::new();
+
+        let a = entities.alloc();
+        let b = entities.alloc();
+        assert_ne!(a, b);
+        assert!(entities.is_alive(a));
+        assert!(entities.is_alive(b));
+        assert_eq!(entities.len(), 2);
+
+        entities.free(a).unwrap();
+        assert!(!entities.is_alive(a));
+        assert_eq!(entities.len(), 1);
+
+        let c = entities.alloc();
+        assert_eq!(c.id(), a.id());
+        assert_eq!(c.generation(), a.generation() + 1);
+        assert!(entities.is_alive(c));
+        assert!(!entities.is_alive(a));
+
+        assert_eq!(entities.iter().collect::<Vec<_>>(), vec![c, b]);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut entities = Entities::new();
+
+        let a = entities.alloc();
+        let b = entities.alloc();
+        entities.free(a).unwrap();
+
+        let reused = entities.reserve();
+        let new1 = entities.reserve();
+        let new2 = entities.reserve();
+        assert_eq!(reused.id(), a.id());
+        assert_eq!(new1.id(), 2);
+        assert_eq!(new2.id(), 3);
+        assert!(!entities.is_alive(reused));
+        assert_eq!(entities.len(), 1);
+
+        entities.flush();
+        assert!(entities.is_alive(reused));
+        assert!(entities.is_alive(new1));
+        assert!(entities.is_alive(new2));
+        assert!(entities.is_alive(b));
+        assert_eq!(entities.len(), 4);
+        assert!(entities.free_entities().is_empty());
+
+        // alloc flushes first, so it never hands out a reserved entity again
+        let reserved = entities.reserve();
+        let allocated = entities.alloc();
+        assert_ne!(reserved, allocated);
+        assert!(entities.is_alive(reserved));
+    }
+
+    #[test]
+    fn test_double_free() {
+        let mut entities = Entities::new();
+
+        let a = entities.alloc();
+        assert!(entities.free(a).is_ok());
+        assert!(entities.free(a).is_err());
+        assert!(entities.free(Entity::new(42, 0)).is_err());
+
+        // a stale handle can't free the entity that reused its id
+        let b = entities.alloc();
+        assert!(entities.free(a).is_err());
+        assert!(entities.is_alive(b));
+    }
+}
@@ -1,5 +1,16 @@
+extern crate self as katabatic_ecs;
+
+pub mod access;
+pub mod bundle;
+pub mod column;
+pub mod command;
 pub mod component;
 pub mod entity;
+pub mod lifecycle;
 pub mod query;
+pub mod reflect;
+pub mod resource;
+pub mod snapshot;
 pub mod storage;
+pub mod tick;
 pub mod world;
@@ -0,0 +1,297 @@
+use std::{any::TypeId, collections::HashMap, marker::PhantomData};
+
+use crate::{component::Component, entity::Entity, world::World};
+
+/// A function called when a component is added to, inserted on or removed from an entity.
+///
+/// Hooks only get shared access to the world, but can still change components and resources
+/// through it, or queue structural changes with [`World::defer`].
+pub type ComponentHook = Box<dyn Fn(&World, Entity) + Send + Sync>;
+
+/// The lifecycle hooks registered for one component type.
+#[derive(Default)]
+pub struct ComponentHooks {
+    on_add: Vec<ComponentHook>,
+    on_insert: Vec<ComponentHook>,
+    on_remove: Vec<ComponentHook>,
+}
+
+impl ComponentHooks {
+    /// Registers a hook called after the component is added to an entity that didn't have one.
+    pub fn on_add(&mut self, hook: impl Fn(&World, Entity) + Send + Sync + 'static) -> &mut Self {
+        self.on_add.push(Box::new(hook));
+        self
+    }
+
+    /// Registers a hook called after every insertion of the component, including ones that
+    /// replace an existing value. Runs after any `on_add` hooks.
+    pub fn on_insert(
+        &mut self,
+        hook: impl Fn(&World, Entity) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_insert.push(Box::new(hook));
+        self
+    }
+
+    /// Registers a hook called just before the component is removed from an entity, or the
+    /// entity is destroyed, while the component can still be read.
+    pub fn on_remove(
+        &mut self,
+        hook: impl Fn(&World, Entity) + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.on_remove.push(Box::new(hook));
+        self
+    }
+}
+
+/// Every component type's hooks.
+#[derive(Default)]
+pub(crate) struct Hooks {
+    hooks: HashMap<TypeId, ComponentHooks>,
+}
+
+impl Hooks {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub(crate) fn register(&mut self, type_id: TypeId) -> &mut ComponentHooks {
+        self.hooks.entry(type_id).or_default()
+    }
+
+    pub(crate) fn on_add(&self, world: &World, entity: Entity, type_id: TypeId) {
+        if let Some(hooks) = self.hooks.get(&type_id) {
+            hooks.on_add.iter().for_each(|hook| hook(world, entity));
+        }
+    }
+
+    pub(crate) fn on_insert(&self, world: &World, entity: Entity, type_id: TypeId) {
+        if let Some(hooks) = self.hooks.get(&type_id) {
+            hooks.on_insert.iter().for_each(|hook| hook(world, entity));
+        }
+    }
+
+    pub(crate) fn on_remove(&self, world: &World, entity: Entity, type_id: TypeId) {
+        if let Some(hooks) = self.hooks.get(&type_id) {
+            hooks.on_remove.iter().for_each(|hook| hook(world, entity));
+        }
+    }
+}
+
+/// The entities that lost a component type during the current and previous frame.
+///
+/// Entries are numbered from the first removal ever recorded, so a reader can remember how far
+/// it got with a single index.
+#[derive(Debug, Default)]
+struct RemovedLog {
+    entities: Vec<Entity>,
+    // the index of `entities[0]`
+    start: usize,
+    // the index of the first removal this frame
+    frame_start: usize,
+}
+
+impl RemovedLog {
+    fn end(&self) -> usize {
+        self.start + self.entities.len()
+    }
+
+    fn since(&self, cursor: usize) -> &[Entity] {
+        &self.entities[cursor.saturating_sub(self.start).min(self.entities.len())..]
+    }
+}
+
+/// Removals of every component type, kept for two frames.
+#[derive(Debug, Default)]
+pub(crate) struct RemovedComponentsLog {
+    logs: HashMap<TypeId, RemovedLog>,
+}
+
+impl RemovedComponentsLog {
+    pub(crate) fn record(&mut self, type_id: TypeId, entity: Entity) {
+        self.logs.entry(type_id).or_default().entities.push(entity);
+    }
+
+    /// Drops the previous frame's removals and starts a new frame.
+    pub(crate) fn update(&mut self) {
+        for log in self.logs.values_mut() {
+            log.entities.drain(..log.frame_start - log.start);
+            log.start = log.frame_start;
+            log.frame_start = log.end();
+        }
+    }
+
+    fn get(&self, type_id: TypeId) -> Option<&RemovedLog> {
+        self.logs.get(&type_id)
+    }
+}
+
+/// Reads the entities that lost a `T`, either by having it removed or by being destroyed, since
+/// the last time this reader was read.
+///
+/// Removals are kept until the second [`World::clear_trackers`] after they happened, so a reader
+/// has to be read at least once a frame not to miss any.
+pub struct RemovedComponents<'w, 's, T: Component> {
+    world: &'w World,
+    cursor: &'s mut usize,
+    _marker: PhantomData<T>,
+}
+
+impl<'w, 's, T: Component> RemovedComponents<'w, 's, T> {
+    /// `cursor` remembers how far the reader got, and should start at `0`.
+    pub fn new(world: &'w World, cursor: &'s mut usize) -> Self {
+        Self {
+            world,
+            cursor,
+            _marker: PhantomData,
+        }
+    }
+
+    fn log(&self) -> Option<&'w RemovedLog> {
+        self.world.removed_components().get(TypeId::of::<T>())
+    }
+
+    /// Returns the entities that lost a `T` since the last read, and marks them as read.
+    pub fn read(&mut self) -> impl Iterator<Item = Entity> + 'w {
+        let removed = self.log().map_or(&[][..], |log| log.since(*self.cursor));
+        if let Some(log) = self.log() {
+            *self.cursor = log.end();
+        }
+        removed.iter().copied()
+    }
+
+    /// The number of unread entities.
+    pub fn len(&self) -> usize {
+        self.log().map_or(0, |log| log.since(*self.cursor).len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Marks every removal so far as read.
+    pub fn clear(&mut self) {
+        if let Some(log) = self.log() {
+            *self.cursor = log.end();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position {
+        x: f32,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Velocity {
+        dx: f32,
+    }
+
+    #[test]
+    fn test_hooks() {
+        let mut world = World::new();
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let (add, insert, remove) = (log.clone(), log.clone(), log.clone());
+        world
+            .register_component_hooks::<Position>()
+            .on_add(move |_, entity| add.lock().unwrap().push(("add", entity)))
+            .on_insert(move |_, entity| insert.lock().unwrap().push(("insert", entity)))
+            .on_remove(move |world, entity| {
+                // the component is still there while the hook runs
+                assert!(world.has_component::<Position>(entity));
+                remove.lock().unwrap().push(("remove", entity));
+            });
+
+        let a = world.spawn((Position { x: 0.0 }, Velocity { dx: 1.0 }));
+        world.insert_component(a, Position { x: 1.0 }).unwrap();
+        world.remove_component::<Position>(a);
+        world.remove_component::<Position>(a);
+        let b = world.spawn((Position { x: 2.0 },));
+        world.destroy_entity(b).unwrap();
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                ("add", a),
+                ("insert", a),
+                ("insert", a),
+                ("remove", a),
+                ("add", b),
+                ("insert", b),
+                ("remove", b),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hook_deferred_commands() {
+        let mut world = World::new();
+        let spawned = Arc::new(AtomicUsize::new(0));
+
+        let counter = spawned.clone();
+        world
+            .register_component_hooks::<Position>()
+            .on_remove(move |world, _| {
+                let mut queue = crate::command::CommandQueue::new();
+                crate::command::Commands::new(&mut queue, world).spawn((Velocity { dx: 0.0 },));
+                world.defer(&mut queue);
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+
+        let entity = world.spawn((Position { x: 0.0 },));
+        world.destroy_entity(entity).unwrap();
+        world.apply_deferred().unwrap();
+
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+        assert_eq!(world.query::<&Velocity>().len(), 1);
+    }
+
+    #[test]
+    fn test_removed_components() {
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 0.0 },));
+        let b = world.spawn((Position { x: 1.0 }, Velocity { dx: 1.0 }));
+        let c = world.spawn((Position { x: 2.0 },));
+
+        let mut cursor = 0;
+        world.remove_component::<Position>(a);
+        world.destroy_entity(b).unwrap();
+
+        let mut removed = RemovedComponents::<Position>::new(&world, &mut cursor);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed.read().collect::<Vec<_>>(), vec![a, b]);
+        assert!(removed.is_empty());
+
+        // removals are kept for one more frame, then dropped
+        let mut late_cursor = 0;
+        world.clear_trackers();
+        world.remove_bundle::<(Position,)>(c);
+        assert_eq!(
+            RemovedComponents::<Position>::new(&world, &mut cursor)
+                .read()
+                .collect::<Vec<_>>(),
+            vec![c]
+        );
+        world.clear_trackers();
+        assert_eq!(
+            RemovedComponents::<Position>::new(&world, &mut late_cursor)
+                .read()
+                .collect::<Vec<_>>(),
+            vec![c]
+        );
+        assert!(RemovedComponents::<Velocity>::new(&world, &mut 0)
+            .read()
+            .next()
+            .is_none());
+    }
+}
@@ -1,60 +1,671 @@
-use std::any::TypeId;
+use std::{
+    any::TypeId,
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    ptr::NonNull,
+    rc::{Rc, Weak},
+};
 
-use katabatic_util::lock::{MapRead, MapWrite};
+use katabatic_util::lock::{Lock, Read, Write};
 
-use crate::{component::Component, entity::Entity, world::World};
+use crate::{
+    access::Access, column::Column, component::Component, entity::Entity, storage::Archetype,
+    tick::Tick, world::World,
+};
 
-pub struct Query<'a, T: Component> {
-    pub(crate) world: &'a World,
-    pub(crate) entities: Vec<Entity>,
-    _marker: std::marker::PhantomData<T>,
+/// Data that can be fetched from each entity matched by a [`Query`].
+///
+/// Implemented for `&T`, `&mut T`, `Option<&T>`, `Option<&mut T>`, [`Entity`] and tuples of these.
+pub trait QueryData {
+    type Item<'w>;
+
+    /// The columns of one archetype that items are fetched from, locked once for the whole
+    /// archetype.
+    type Fetch<'w>;
+
+    /// Returns `true` if an archetype with the given component types contains everything this
+    /// fetches.
+    fn matches(type_ids: &[TypeId]) -> bool;
+
+    fn access(access: &mut Access);
+
+    /// Locks the columns this fetches from in `archetype`, which must match, or reuses the locks
+    /// `borrows` already holds on them. Components borrowed mutably are marked as changed at
+    /// [`Borrows::this_run`] as they're fetched.
+    ///
+    /// Returns `None` instead of waiting for a lock held elsewhere, unless `wait` is set.
+    fn init_fetch<'w>(
+        archetype: &'w Archetype,
+        borrows: &Borrows<'w>,
+        wait: bool,
+    ) -> Option<Self::Fetch<'w>>;
+
+    /// Fetches `entity`, which is at `row` of the archetype `fetch` was made for. Returns `None`
+    /// if a component of that row is still borrowed mutably by an earlier item.
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, entity: Entity, row: usize) -> Option<Self::Item<'w>>;
+}
+
+/// A filter on which entities a [`Query`] visits. Nothing is fetched for a filter.
+pub trait QueryFilter {
+    /// Returns `true` if entities in an archetype with the given component types can pass.
+    fn matches(type_ids: &[TypeId]) -> bool;
+
+    /// Adds what the filter reads to `access`, which already holds the query data's access.
+    #[allow(unused_variables)]
+    fn access(access: &mut Access) {}
+
+    /// Returns `true` if the entity at `row` of a matching archetype passes. `last_run` and
+    /// `this_run` are the ticks that change detection compares against.
+    #[allow(unused_variables)]
+    fn filter(archetype: &Archetype, row: usize, last_run: Tick, this_run: Tick) -> bool {
+        true
+    }
+}
+
+/// A component borrowed by a [`Query`]. Every item fetched from the same archetype shares one
+/// read lock on the component's column, which is released once the last of them is dropped.
+pub struct Ref<'w, T: Component> {
+    _column: Rc<Read<'w, Column>>,
+    value: NonNull<T>,
+}
+
+impl<T: Component> Deref for Ref<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // the column can't change while it's locked
+        unsafe { self.value.as_ref() }
+    }
+}
+
+impl<T: Component + Debug> Debug for Ref<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: Component + PartialEq> PartialEq for Ref<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+/// A component borrowed mutably by a [`Query`]. Every item fetched from the same archetype
+/// shares one write lock on the component's column, which is released once the last of them is
+/// dropped.
+pub struct Mut<'w, T: Component> {
+    column: Rc<WriteBorrow<'w>>,
+    value: NonNull<T>,
+    row: usize,
+}
+
+impl<T: Component> Drop for Mut<'_, T> {
+    fn drop(&mut self) {
+        self.column.lent.borrow_mut().remove(&self.row);
+    }
+}
+
+impl<T: Component> Deref for Mut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.value.as_ref() }
+    }
 }
 
-impl<'a, T: Component> Query<'a, T> {
-    pub fn new(world: &'a World) -> Self {
-        // gather the entities that contain the component
-        let storage = world.storage();
-        let entities = storage
-            .entity_iter()
-            .filter(|entity| storage.has_component_by_type_id(*entity, TypeId::of::<T>()))
-            .collect::<Vec<_>>();
+impl<T: Component> DerefMut for Mut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // the column is locked for writing, and no two live items sharing the lock are the same
+        // row
+        unsafe { self.value.as_mut() }
+    }
+}
+
+impl<T: Component + Debug> Debug for Mut<'_, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T: Component + PartialEq> PartialEq for Mut<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
 
+/// A column locked for writing, and the rows of it that items are borrowing.
+pub struct WriteBorrow<'w> {
+    column: Write<'w, Column>,
+    base: NonNull<u8>,
+    lent: RefCell<HashSet<usize>>,
+}
+
+/// The column locks held by a [`Query`] and the items it handed out. Fetching from a column that
+/// is already locked reuses the lock, so items fetched at different times never wait on each
+/// other.
+pub struct Borrows<'w> {
+    reads: RefCell<HashMap<*const Lock<Column>, Weak<Read<'w, Column>>>>,
+    writes: RefCell<HashMap<*const Lock<Column>, Weak<WriteBorrow<'w>>>>,
+    this_run: Tick,
+}
+
+impl<'w> Borrows<'w> {
+    fn new(this_run: Tick) -> Self {
         Self {
+            reads: RefCell::default(),
+            writes: RefCell::default(),
+            this_run,
+        }
+    }
+
+    /// The tick components borrowed mutably are marked as changed at.
+    pub fn this_run(&self) -> Tick {
+        self.this_run
+    }
+
+    fn read(&self, column: &'w Lock<Column>, wait: bool) -> Option<Rc<Read<'w, Column>>> {
+        let mut reads = self.reads.borrow_mut();
+        if let Some(read) = reads.get(&(column as *const _)).and_then(Weak::upgrade) {
+            return Some(read);
+        }
+        let read = Rc::new(match wait {
+            true => column.read(),
+            false => column.try_read()?,
+        });
+        reads.insert(column, Rc::downgrade(&read));
+        Some(read)
+    }
+
+    fn write<T: Component>(
+        &self,
+        column: &'w Lock<Column>,
+        wait: bool,
+    ) -> Option<Rc<WriteBorrow<'w>>> {
+        let mut writes = self.writes.borrow_mut();
+        if let Some(write) = writes.get(&(column as *const _)).and_then(Weak::upgrade) {
+            return Some(write);
+        }
+        let mut column_write = match wait {
+            true => column.write(),
+            false => column.try_write()?,
+        };
+        let base = NonNull::from(column_write.as_mut_slice::<T>()).cast();
+        let write = Rc::new(WriteBorrow {
+            column: column_write,
+            base,
+            lent: RefCell::default(),
+        });
+        writes.insert(column, Rc::downgrade(&write));
+        Some(write)
+    }
+}
+
+/// An archetype's column of `T`s, locked for reading.
+pub struct ReadFetch<'w, T: Component> {
+    column: Rc<Read<'w, Column>>,
+    base: NonNull<T>,
+}
+
+/// An archetype's column of `T`s, locked for writing.
+pub struct WriteFetch<'w, T: Component> {
+    column: Rc<WriteBorrow<'w>>,
+    archetype: &'w Archetype,
+    index: usize,
+    this_run: Tick,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Component> QueryData for &T {
+    type Item<'w> = Ref<'w, T>;
+    type Fetch<'w> = ReadFetch<'w, T>;
+
+    fn matches(type_ids: &[TypeId]) -> bool {
+        type_ids.contains(&TypeId::of::<T>())
+    }
+
+    fn access(access: &mut Access) {
+        access.add_read(TypeId::of::<T>());
+    }
+
+    fn init_fetch<'w>(
+        archetype: &'w Archetype,
+        borrows: &Borrows<'w>,
+        wait: bool,
+    ) -> Option<Self::Fetch<'w>> {
+        let column = borrows.read(archetype.column(TypeId::of::<T>()).unwrap(), wait)?;
+        let base = NonNull::from(column.as_slice::<T>()).cast();
+        Some(ReadFetch { column, base })
+    }
+
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, _entity: Entity, row: usize) -> Option<Self::Item<'w>> {
+        debug_assert!(row < fetch.column.len());
+        Some(Ref {
+            _column: fetch.column.clone(),
+            value: unsafe { fetch.base.add(row) },
+        })
+    }
+}
+
+impl<T: Component> QueryData for &mut T {
+    type Item<'w> = Mut<'w, T>;
+    type Fetch<'w> = WriteFetch<'w, T>;
+
+    fn matches(type_ids: &[TypeId]) -> bool {
+        type_ids.contains(&TypeId::of::<T>())
+    }
+
+    fn access(access: &mut Access) {
+        access.add_write(TypeId::of::<T>());
+    }
+
+    fn init_fetch<'w>(
+        archetype: &'w Archetype,
+        borrows: &Borrows<'w>,
+        wait: bool,
+    ) -> Option<Self::Fetch<'w>> {
+        let index = archetype.column_index(TypeId::of::<T>()).unwrap();
+        let column = borrows.write::<T>(archetype.column(TypeId::of::<T>()).unwrap(), wait)?;
+        Some(WriteFetch {
+            column,
+            archetype,
+            index,
+            this_run: borrows.this_run,
+            _marker: PhantomData,
+        })
+    }
+
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, _entity: Entity, row: usize) -> Option<Self::Item<'w>> {
+        debug_assert!(row < fetch.column.column.len());
+        if !fetch.column.lent.borrow_mut().insert(row) {
+            return None;
+        }
+        fetch
+            .archetype
+            .set_changed(fetch.index, row, fetch.this_run);
+        Some(Mut {
+            column: fetch.column.clone(),
+            value: unsafe { fetch.column.base.cast::<T>().add(row) },
+            row,
+        })
+    }
+}
+
+impl<T: Component> QueryData for Option<&T> {
+    type Item<'w> = Option<Ref<'w, T>>;
+    type Fetch<'w> = Option<ReadFetch<'w, T>>;
+
+    fn matches(_type_ids: &[TypeId]) -> bool {
+        true
+    }
+
+    fn access(access: &mut Access) {
+        access.add_read(TypeId::of::<T>());
+    }
+
+    fn init_fetch<'w>(
+        archetype: &'w Archetype,
+        borrows: &Borrows<'w>,
+        wait: bool,
+    ) -> Option<Self::Fetch<'w>> {
+        if !archetype.has_component::<T>() {
+            return Some(None);
+        }
+        <&T>::init_fetch(archetype, borrows, wait).map(Some)
+    }
+
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, entity: Entity, row: usize) -> Option<Self::Item<'w>> {
+        match fetch {
+            Some(fetch) => <&T>::fetch(fetch, entity, row).map(Some),
+            None => Some(None),
+        }
+    }
+}
+
+impl<T: Component> QueryData for Option<&mut T> {
+    type Item<'w> = Option<Mut<'w, T>>;
+    type Fetch<'w> = Option<WriteFetch<'w, T>>;
+
+    fn matches(_type_ids: &[TypeId]) -> bool {
+        true
+    }
+
+    fn access(access: &mut Access) {
+        access.add_write(TypeId::of::<T>());
+    }
+
+    fn init_fetch<'w>(
+        archetype: &'w Archetype,
+        borrows: &Borrows<'w>,
+        wait: bool,
+    ) -> Option<Self::Fetch<'w>> {
+        if !archetype.has_component::<T>() {
+            return Some(None);
+        }
+        <&mut T>::init_fetch(archetype, borrows, wait).map(Some)
+    }
+
+    fn fetch<'w>(fetch: &Self::Fetch<'w>, entity: Entity, row: usize) -> Option<Self::Item<'w>> {
+        match fetch {
+            Some(fetch) => <&mut T>::fetch(fetch, entity, row).map(Some),
+            None => Some(None),
+        }
+    }
+}
+
+impl QueryData for Entity {
+    type Item<'w> = Entity;
+    type Fetch<'w> = ();
+
+    fn matches(_type_ids: &[TypeId]) -> bool {
+        true
+    }
+
+    fn access(_access: &mut Access) {}
+
+    fn init_fetch<'w>(
+        _archetype: &'w Archetype,
+        _borrows: &Borrows<'w>,
+        _wait: bool,
+    ) -> Option<Self::Fetch<'w>> {
+        Some(())
+    }
+
+    fn fetch<'w>(_fetch: &Self::Fetch<'w>, entity: Entity, _row: usize) -> Option<Self::Item<'w>> {
+        Some(entity)
+    }
+}
+
+/// Only matches entities that have a `T`, without fetching it.
+pub struct With<T: Component>(PhantomData<T>);
+
+/// Only matches entities that don't have a `T`.
+pub struct Without<T: Component>(PhantomData<T>);
+
+impl<T: Component> QueryFilter for With<T> {
+    fn matches(type_ids: &[TypeId]) -> bool {
+        type_ids.contains(&TypeId::of::<T>())
+    }
+}
+
+impl<T: Component> QueryFilter for Without<T> {
+    fn matches(type_ids: &[TypeId]) -> bool {
+        !type_ids.contains(&TypeId::of::<T>())
+    }
+}
+
+/// Only matches entities whose `T` was added since the system last ran.
+pub struct Added<T: Component>(PhantomData<T>);
+
+/// Only matches entities whose `T` was added or borrowed mutably since the system last ran.
+pub struct Changed<T: Component>(PhantomData<T>);
+
+impl<T: Component> QueryFilter for Added<T> {
+    fn matches(type_ids: &[TypeId]) -> bool {
+        type_ids.contains(&TypeId::of::<T>())
+    }
+
+    fn access(access: &mut Access) {
+        add_ticks_read::<T>(access);
+    }
+
+    fn filter(archetype: &Archetype, row: usize, last_run: Tick, this_run: Tick) -> bool {
+        archetype
+            .ticks(TypeId::of::<T>(), row)
+            .is_some_and(|ticks| ticks.is_added(last_run, this_run))
+    }
+}
+
+impl<T: Component> QueryFilter for Changed<T> {
+    fn matches(type_ids: &[TypeId]) -> bool {
+        type_ids.contains(&TypeId::of::<T>())
+    }
+
+    fn access(access: &mut Access) {
+        add_ticks_read::<T>(access);
+    }
+
+    fn filter(archetype: &Archetype, row: usize, last_run: Tick, this_run: Tick) -> bool {
+        archetype
+            .ticks(TypeId::of::<T>(), row)
+            .is_some_and(|ticks| ticks.is_changed(last_run, this_run))
+    }
+}
+
+/// Registers a read of `T`'s ticks, unless the query already borrows `T` mutably. This doesn't
+/// borrow the component, but keeps the filter from running alongside systems changing it.
+fn add_ticks_read<T: Component>(access: &mut Access) {
+    if !access.has_write(TypeId::of::<T>()) {
+        access.add_read(TypeId::of::<T>());
+    }
+}
+
+macro_rules! impl_query_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: QueryData),*> QueryData for ($($name,)*) {
+            type Item<'w> = ($($name::Item<'w>,)*);
+            type Fetch<'w> = ($($name::Fetch<'w>,)*);
+
+            #[allow(unused_variables)]
+            fn matches(type_ids: &[TypeId]) -> bool {
+                true $(&& $name::matches(type_ids))*
+            }
+
+            #[allow(unused_variables)]
+            fn access(access: &mut Access) {
+                $($name::access(access);)*
+            }
+
+            #[allow(unused_variables, clippy::unused_unit)]
+            fn init_fetch<'w>(
+                archetype: &'w Archetype,
+                borrows: &Borrows<'w>,
+                wait: bool,
+            ) -> Option<Self::Fetch<'w>> {
+                Some(($($name::init_fetch(archetype, borrows, wait)?,)*))
+            }
+
+            #[allow(unused_variables, non_snake_case, clippy::unused_unit)]
+            fn fetch<'w>(
+                fetch: &Self::Fetch<'w>,
+                entity: Entity,
+                row: usize,
+            ) -> Option<Self::Item<'w>> {
+                let ($($name,)*) = fetch;
+                Some(($($name::fetch($name, entity, row)?,)*))
+            }
+        }
+
+        impl<$($name: QueryFilter),*> QueryFilter for ($($name,)*) {
+            #[allow(unused_variables)]
+            fn matches(type_ids: &[TypeId]) -> bool {
+                true $(&& $name::matches(type_ids))*
+            }
+
+            #[allow(unused_variables)]
+            fn access(access: &mut Access) {
+                $($name::access(access);)*
+            }
+
+            #[allow(unused_variables)]
+            fn filter(archetype: &Archetype, row: usize, last_run: Tick, this_run: Tick) -> bool {
+                true $(&& $name::filter(archetype, row, last_run, this_run))*
+            }
+        }
+    };
+}
+
+impl_query_tuple!();
+impl_query_tuple!(A);
+impl_query_tuple!(A, B);
+impl_query_tuple!(A, B, C);
+impl_query_tuple!(A, B, C, D);
+impl_query_tuple!(A, B, C, D, E);
+impl_query_tuple!(A, B, C, D, E, F);
+impl_query_tuple!(A, B, C, D, E, F, G);
+impl_query_tuple!(A, B, C, D, E, F, G, H);
+
+/// The entities of a [`World`] that have the components `Q` fetches and pass the filter `F`.
+///
+/// Iterating visits the matched archetypes one at a time, locking each of their columns once
+/// and reading the rows in order. [`get`](Self::get) looks a single entity up instead.
+///
+/// Items from the same query share their column locks, so any number of them can be held at
+/// once. A component can't be borrowed mutably by two of them at the same time though: `get`
+/// returns `None` for an entity that's still borrowed, and iterating over it panics.
+pub struct Query<'w, Q: QueryData, F: QueryFilter = ()> {
+    archetypes: Vec<&'w Archetype>,
+    world: &'w World,
+    borrows: Borrows<'w>,
+    last_run: Tick,
+    this_run: Tick,
+    _marker: PhantomData<(Q, F)>,
+}
+
+impl<'w, Q: QueryData, F: QueryFilter> Query<'w, Q, F> {
+    /// Creates a query that detects changes made since the world's
+    /// [last change tick](World::last_change_tick).
+    pub fn new(world: &'w World) -> Self {
+        Self::with_ticks(world, world.last_change_tick(), world.change_tick())
+    }
+
+    /// Creates a query that detects changes made after `last_run`, and marks components it
+    /// borrows mutably as changed at `this_run`.
+    pub fn with_ticks(world: &'w World, last_run: Tick, this_run: Tick) -> Self {
+        let mut access = Access::new();
+        Q::access(&mut access);
+        F::access(&mut access);
+        assert!(
+            !access.is_self_conflicting(),
+            "Query::new(): {} accesses the same component mutably more than once",
+            std::any::type_name::<Q>()
+        );
+
+        let archetypes = world
+            .storage()
+            .archetypes()
+            .filter(|archetype| Self::matches_archetype(archetype.type_ids()))
+            .collect();
+
+        Self {
+            archetypes,
             world,
-            entities,
-            _marker: std::marker::PhantomData,
+            borrows: Borrows::new(this_run),
+            last_run,
+            this_run,
+            _marker: PhantomData,
         }
     }
 
-    pub fn entity_iter(&self) -> impl Iterator<Item = Entity> + '_ {
-        self.entities.iter().copied()
+    pub fn last_run(&self) -> Tick {
+        self.last_run
+    }
+
+    pub fn this_run(&self) -> Tick {
+        self.this_run
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = MapRead<'_, T>> + '_ {
-        self.entities
-            .iter()
-            .filter_map(move |entity| self.world.get_component::<T>(*entity))
+    fn matches_archetype(type_ids: &[TypeId]) -> bool {
+        Q::matches(type_ids) && F::matches(type_ids)
     }
 
-    pub fn iter_mut(&self) -> impl Iterator<Item = MapWrite<'_, T>> + '_ {
-        self.entities
-            .iter()
-            .filter_map(move |entity| self.world.get_component_mut::<T>(*entity))
+    fn passes(&self, archetype: &Archetype, row: usize) -> bool {
+        F::filter(archetype, row, self.last_run, self.this_run)
     }
 
-    pub fn get(&self, entity: Entity) -> Option<MapRead<'_, T>> {
-        if self.entities.contains(&entity) {
-            self.world.get_component::<T>(entity)
-        } else {
-            None
+    pub fn entity_iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        // the borrows make `Query` invariant over `'w`, so shorten it before capturing anything
+        let archetypes: &[&Archetype] = &self.archetypes;
+        let (last_run, this_run) = (self.last_run, self.this_run);
+        archetypes.iter().flat_map(move |&archetype| {
+            archetype
+                .entities()
+                .iter()
+                .enumerate()
+                .filter(move |&(row, _)| F::filter(archetype, row, last_run, this_run))
+                .map(|(_, &entity)| entity)
+        })
+    }
+
+    /// Fetches every matching entity. The columns of each archetype stay locked until the
+    /// iterator moves past it and every item fetched from it is dropped.
+    ///
+    /// Panics on reaching an entity that an item from [`get`](Self::get) still borrows
+    /// mutably.
+    pub fn iter(&self) -> impl Iterator<Item = Q::Item<'w>> + '_ {
+        QueryIter {
+            query: self,
+            archetypes: self.archetypes.iter(),
+            current: None,
+            row: 0,
         }
     }
 
-    pub fn get_mut(&self, entity: Entity) -> Option<MapWrite<'_, T>> {
-        if self.entities.contains(&entity) {
-            self.world.get_component_mut::<T>(entity)
-        } else {
-            None
+    /// Fetches `entity` if it matches. Returns `None` rather than waiting if one of its
+    /// components is already borrowed mutably, by an item of this query or from elsewhere.
+    pub fn get(&self, entity: Entity) -> Option<Q::Item<'w>> {
+        let (archetype, row) = self.locate(entity)?;
+        let fetch = Q::init_fetch(archetype, &self.borrows, false)?;
+        Q::fetch(&fetch, entity, row)
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.locate(entity).is_some()
+    }
+
+    fn locate(&self, entity: Entity) -> Option<(&'w Archetype, usize)> {
+        let storage = self.world.storage();
+        let location = storage.location_of(entity)?;
+        let archetype = storage.archetype(location.archetype_id)?;
+        (Self::matches_archetype(archetype.type_ids()) && self.passes(archetype, location.row))
+            .then_some((archetype, location.row))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entity_iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entity_iter().next().is_none()
+    }
+}
+
+struct QueryIter<'w, 'q, Q: QueryData, F: QueryFilter> {
+    query: &'q Query<'w, Q, F>,
+    archetypes: std::slice::Iter<'q, &'w Archetype>,
+    current: Option<(&'w Archetype, Q::Fetch<'w>)>,
+    row: usize,
+}
+
+impl<'w, Q: QueryData, F: QueryFilter> Iterator for QueryIter<'w, '_, Q, F> {
+    type Item = Q::Item<'w>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((archetype, fetch)) = &self.current {
+                while self.row < archetype.len() {
+                    let row = self.row;
+                    self.row += 1;
+                    if self.query.passes(archetype, row) {
+                        let entity = archetype.entities()[row];
+                        let item = Q::fetch(fetch, entity, row);
+                        return Some(item.unwrap_or_else(|| {
+                            panic!("Query::iter(): {:?} is already borrowed mutably", entity)
+                        }));
+                    }
+                }
+            }
+
+            // let go of the finished archetype's columns before locking the next one's
+            self.current = None;
+            let archetype = *self.archetypes.next()?;
+            if !archetype.is_empty() {
+                let fetch = Q::init_fetch(archetype, &self.query.borrows, true).unwrap();
+                self.current = Some((archetype, fetch));
+                self.row = 0;
+            }
         }
     }
 }
@@ -75,6 +686,9 @@ mod tests {
         dy: f32,
     }
 
+    #[derive(Clone, Debug, PartialEq)]
+    struct Frozen;
+
     #[test]
     fn query() {
         let mut world = World::new();
@@ -82,24 +696,245 @@ mod tests {
         let entity2 = world.create_entity();
         let _entity3 = world.create_entity();
 
-        world.insert_component(entity1, Position { x: 1.0, y: 2.0 });
-        world.insert_component(entity2, Position { x: 3.0, y: 4.0 });
-        world.insert_component(entity2, Velocity { dx: 5.0, dy: 6.0 });
+        world
+            .insert_component(entity1, Position { x: 1.0, y: 2.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Position { x: 3.0, y: 4.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Velocity { dx: 5.0, dy: 6.0 })
+            .unwrap();
 
-        let query = world.query::<Position>();
+        let query = world.query::<&Position>();
 
         let positions: Vec<_> = query.iter().collect();
         assert_eq!(positions.len(), 2);
+        drop(positions);
         assert_eq!(query.get(entity1).unwrap().x, 1.0);
         assert_eq!(query.get(entity1).unwrap().y, 2.0);
         assert_eq!(query.get(entity2).unwrap().x, 3.0);
         assert_eq!(query.get(entity2).unwrap().y, 4.0);
 
-        let query = world.query::<Velocity>();
+        let query = world.query::<&Velocity>();
 
         let velocities: Vec<_> = query.iter().collect();
         assert_eq!(velocities.len(), 1);
+        drop(velocities);
         assert_eq!(query.get(entity2).unwrap().dx, 5.0);
         assert_eq!(query.get(entity2).unwrap().dy, 6.0);
     }
+
+    #[test]
+    fn query_tuple() {
+        let mut world = World::new();
+        let entity1 = world.create_entity();
+        let entity2 = world.create_entity();
+
+        world
+            .insert_component(entity1, Position { x: 1.0, y: 2.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Position { x: 3.0, y: 4.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Velocity { dx: 5.0, dy: 6.0 })
+            .unwrap();
+
+        let query = world.query::<(&mut Position, &Velocity)>();
+        assert_eq!(query.entity_iter().collect::<Vec<_>>(), vec![entity2]);
+
+        for (mut position, velocity) in query.iter() {
+            position.x += velocity.dx;
+            position.y += velocity.dy;
+        }
+
+        assert_eq!(
+            *world.get_component::<Position>(entity2).unwrap(),
+            Position { x: 8.0, y: 10.0 }
+        );
+        assert!(query.get(entity1).is_none());
+    }
+
+    #[test]
+    fn query_filters() {
+        let mut world = World::new();
+        let entity1 = world.create_entity();
+        let entity2 = world.create_entity();
+        let entity3 = world.create_entity();
+
+        world
+            .insert_component(entity1, Position { x: 1.0, y: 2.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Position { x: 3.0, y: 4.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Velocity { dx: 5.0, dy: 6.0 })
+            .unwrap();
+        world
+            .insert_component(entity3, Position { x: 7.0, y: 8.0 })
+            .unwrap();
+        world.insert_component(entity3, Frozen).unwrap();
+
+        let query = world.query_filtered::<Entity, (With<Position>, Without<Frozen>)>();
+        let mut entities = query.entity_iter().collect::<Vec<_>>();
+        entities.sort();
+        assert_eq!(entities, vec![entity1, entity2]);
+
+        let query = world.query_filtered::<&Position, With<Velocity>>();
+        assert_eq!(query.len(), 1);
+        assert_eq!(query.get(entity2).unwrap().x, 3.0);
+        assert!(query.get(entity1).is_none());
+    }
+
+    #[test]
+    fn query_optional() {
+        let mut world = World::new();
+        let entity1 = world.create_entity();
+        let entity2 = world.create_entity();
+
+        world
+            .insert_component(entity1, Position { x: 1.0, y: 2.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Position { x: 3.0, y: 4.0 })
+            .unwrap();
+        world
+            .insert_component(entity2, Velocity { dx: 5.0, dy: 6.0 })
+            .unwrap();
+
+        let query = world.query::<(Entity, &Position, Option<&Velocity>)>();
+        assert_eq!(query.len(), 2);
+
+        let (_, _, velocity) = query.get(entity1).unwrap();
+        assert!(velocity.is_none());
+        let (entity, position, velocity) = query.get(entity2).unwrap();
+        assert_eq!(entity, entity2);
+        assert_eq!(position.x, 3.0);
+        assert_eq!(velocity.unwrap().dx, 5.0);
+    }
+
+    #[test]
+    fn query_change_detection() {
+        let mut world = World::new();
+        let entity1 = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let entity2 = world.spawn((Position { x: 3.0, y: 4.0 },));
+
+        let query = world.query_filtered::<Entity, Added<Position>>();
+        assert_eq!(query.len(), 2);
+
+        world.clear_trackers();
+        assert!(world.query_filtered::<Entity, Added<Position>>().is_empty());
+        assert!(world
+            .query_filtered::<Entity, Changed<Position>>()
+            .is_empty());
+
+        // borrowing mutably through a query marks the component as changed
+        let query = world.query::<&mut Position>();
+        query.get(entity2).unwrap().x += 1.0;
+
+        let query = world.query_filtered::<Entity, Changed<Position>>();
+        assert_eq!(query.entity_iter().collect::<Vec<_>>(), vec![entity2]);
+        assert!(query.get(entity1).is_none());
+        assert!(world.query_filtered::<Entity, Added<Position>>().is_empty());
+
+        world
+            .insert_component(entity1, Velocity { dx: 0.0, dy: 0.0 })
+            .unwrap();
+        let query = world.query_filtered::<Entity, (Added<Velocity>, Changed<Position>)>();
+        assert!(query.is_empty());
+        let query = world.query_filtered::<Entity, Added<Velocity>>();
+        assert_eq!(query.entity_iter().collect::<Vec<_>>(), vec![entity1]);
+    }
+
+    #[test]
+    fn query_locks_columns_per_archetype() {
+        let mut world = World::new();
+        let entity1 = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let entity2 = world.spawn((Position { x: 3.0, y: 4.0 }, Velocity { dx: 1.0, dy: 1.0 }));
+        let entity3 = world.spawn((Position { x: 5.0, y: 6.0 },));
+        world.clear_trackers();
+
+        let column_locked = |entity| {
+            let archetype = world.storage().archetype_of(entity).unwrap();
+            archetype
+                .column(TypeId::of::<Position>())
+                .unwrap()
+                .try_write()
+                .is_none()
+        };
+
+        let query = world.query::<(Entity, &mut Position)>();
+        let mut items = query.iter().collect::<Vec<_>>();
+        items.sort_by_key(|(entity, _)| *entity);
+        assert_eq!(
+            items.iter().map(|(entity, _)| *entity).collect::<Vec<_>>(),
+            vec![entity1, entity2, entity3]
+        );
+        for (_, position) in &mut items {
+            position.x += 10.0;
+        }
+        assert!(column_locked(entity1) && column_locked(entity2));
+        drop(items);
+        assert!(!column_locked(entity1) && !column_locked(entity2));
+
+        let query = world.query_filtered::<&Position, Changed<Position>>();
+        let xs = query.iter().map(|position| position.x).collect::<Vec<_>>();
+        assert_eq!(xs.len(), 3);
+        assert!(xs.iter().all(|&x| x > 10.0));
+        assert!(world.query::<&Velocity>().contains(entity2));
+        assert!(!world.query::<&Velocity>().contains(entity3));
+    }
+
+    #[test]
+    fn query_get_shares_column_locks() {
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 },));
+
+        let query = world.query::<&mut Position>();
+        let mut first = query.get(a).unwrap();
+        let mut second = query.get(b).unwrap();
+        // already borrowed by `first`
+        assert!(query.get(a).is_none());
+        first.x += 10.0;
+        second.x += 10.0;
+        drop(first);
+        drop(second);
+        assert_eq!(query.get(a).unwrap().x, 11.0);
+
+        for mut position in query.iter().filter(|position| position.x > 12.0) {
+            position.y = 0.0;
+            assert!(query.get(a).is_some());
+            assert!(query.get(b).is_none());
+        }
+        drop(query);
+        assert_eq!(world.get_component::<Position>(b).unwrap().y, 0.0);
+
+        // locked from elsewhere
+        let column = world.get_component_mut::<Position>(a).unwrap();
+        assert!(world.query::<&Position>().get(b).is_none());
+        drop(column);
+        assert!(world.query::<&Position>().get(b).is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn query_iter_over_borrowed_entity() {
+        let mut world = World::new();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 },));
+        world.spawn((Position { x: 3.0, y: 4.0 },));
+
+        let query = world.query::<&mut Position>();
+        let _held = query.get(a).unwrap();
+        for _ in query.iter() {}
+    }
+
+    #[test]
+    #[should_panic]
+    fn query_conflicting_access() {
+        let world = World::new();
+        let _ = world.query::<(&mut Position, &Position)>();
+    }
 }
@@ -0,0 +1,976 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    sync::Arc,
+};
+
+use katabatic_util::{
+    error::{KError, KResult},
+    kerror,
+};
+
+use crate::{component::Component, entity::Entity, world::World};
+
+pub use katabatic_macros::Reflect;
+
+#[doc(hidden)]
+pub use katabatic_util::error::KResult as __KResult;
+
+/// A type whose structure can be inspected and edited at runtime, without knowing the type.
+///
+/// Implemented for primitives, [`String`], [`Entity`], [`Option`] and glam's vectors and
+/// quaternions, and for structs and enums with `#[derive(Reflect)]`. Fields marked
+/// `#[reflect(ignore)]` are skipped, and filled in with [`Default`] when building a value.
+pub trait Reflect: Component {
+    fn type_name(&self) -> &'static str {
+        type_name::<Self>()
+    }
+
+    fn reflect_ref(&self) -> ReflectRef<'_>;
+
+    fn reflect_mut(&mut self) -> ReflectMut<'_>;
+
+    /// Copies the value into a [`Value`] tree.
+    fn to_value(&self) -> Value;
+
+    /// Overwrites the value with `value`. Structs only overwrite the fields `value` has.
+    fn set_value(&mut self, value: &Value) -> KResult<()>;
+
+    /// Builds a new value from a [`Value`] tree, which must have every field.
+    fn from_value(value: &Value) -> KResult<Self>
+    where
+        Self: Sized;
+
+    /// A hash of the type's name and layout, which changes whenever a reflected field is added,
+    /// removed, renamed or changes type. Stable across builds.
+    fn schema_hash() -> u64
+    where
+        Self: Sized;
+}
+
+/// Hashes type layouts for [`Reflect::schema_hash`], using 64-bit FNV-1a so hashes don't change
+/// between builds or compiler versions.
+pub struct SchemaHasher(u64);
+
+impl Default for SchemaHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SchemaHasher {
+    pub fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Writes a string, along with its length so neighbouring strings can't run together.
+    pub fn write_str(&mut self, value: &str) {
+        self.write_u64(value.len() as u64);
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn name_hash(name: &str) -> u64 {
+    let mut hasher = SchemaHasher::new();
+    hasher.write_str(name);
+    hasher.finish()
+}
+
+/// A reflected value, seen as one of the kinds of type [`Reflect`] supports.
+pub enum ReflectRef<'a> {
+    Struct(&'a dyn Struct),
+    Enum(&'a dyn Enum),
+    Value(&'a dyn Reflect),
+}
+
+/// A mutable reflected value, seen as one of the kinds of type [`Reflect`] supports.
+pub enum ReflectMut<'a> {
+    Struct(&'a mut dyn Struct),
+    Enum(&'a mut dyn Enum),
+    Value(&'a mut dyn Reflect),
+}
+
+/// A struct with reflected fields. Fields of tuple structs are named `0`, `1` and so on.
+pub trait Struct: Reflect {
+    fn field(&self, name: &str) -> Option<&dyn Reflect>;
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect>;
+
+    fn field_names(&self) -> &'static [&'static str];
+}
+
+/// An enum with reflected variants. Fields are those of the current variant.
+pub trait Enum: Reflect {
+    fn variant_name(&self) -> &'static str;
+
+    fn variant_names(&self) -> &'static [&'static str];
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect>;
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect>;
+
+    fn field_names(&self) -> &'static [&'static str];
+}
+
+impl dyn Reflect {
+    pub fn is<T: Reflect>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: Reflect>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Reflect>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+
+    /// Returns the field with the given name, if this is a struct or enum.
+    pub fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        match self.reflect_ref() {
+            ReflectRef::Struct(value) => value.field(name),
+            ReflectRef::Enum(value) => value.field(name),
+            ReflectRef::Value(_) => None,
+        }
+    }
+
+    pub fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        match self.reflect_mut() {
+            ReflectMut::Struct(value) => value.field_mut(name),
+            ReflectMut::Enum(value) => value.field_mut(name),
+            ReflectMut::Value(_) => None,
+        }
+    }
+
+    /// Follows a path of field names separated by `.`, e.g. `position.x`. An empty path
+    /// returns the value itself.
+    pub fn path(&self, path: &str) -> KResult<&dyn Reflect> {
+        let mut value = self;
+        for name in path_segments(path) {
+            value = value
+                .field(name)
+                .ok_or_else(|| no_field(value, name, path))?;
+        }
+        Ok(value)
+    }
+
+    pub fn path_mut(&mut self, path: &str) -> KResult<&mut dyn Reflect> {
+        let mut value = self;
+        for name in path_segments(path) {
+            let type_name = value.type_name();
+            value = value.field_mut(name).ok_or_else(|| {
+                kerror!(format!(
+                    "Reflect::path_mut(): {} has no field {} (in path {:?})",
+                    type_name, name, path
+                ))
+            })?;
+        }
+        Ok(value)
+    }
+}
+
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    path.split('.').filter(|name| !name.is_empty())
+}
+
+fn no_field(value: &dyn Reflect, name: &str, path: &str) -> KError {
+    kerror!(format!(
+        "Reflect::path(): {} has no field {} (in path {:?})",
+        value.type_name(),
+        name,
+        path
+    ))
+}
+
+/// A type-erased copy of a reflected value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Char(char),
+    String(String),
+    Entity(Entity),
+    /// A struct's fields, in declaration order.
+    Struct(Vec<(String, Value)>),
+    /// The current variant of an enum, and its fields.
+    Enum {
+        variant: String,
+        fields: Vec<(String, Value)>,
+    },
+}
+
+impl Value {
+    /// Returns a struct's fields, or an error naming `type_name` if this isn't a struct.
+    /// [`Value::Unit`] counts as a struct without fields.
+    pub fn as_struct(&self, type_name: &str) -> KResult<&[(String, Value)]> {
+        match self {
+            Value::Struct(fields) => Ok(fields),
+            Value::Unit => Ok(&[]),
+            other => Err(mismatch(type_name, "a struct", other)),
+        }
+    }
+
+    /// Returns an enum's variant and fields, or an error naming `type_name` if this isn't an
+    /// enum.
+    pub fn as_enum(&self, type_name: &str) -> KResult<(&str, &[(String, Value)])> {
+        match self {
+            Value::Enum { variant, fields } => Ok((variant, fields)),
+            other => Err(mismatch(type_name, "an enum", other)),
+        }
+    }
+
+    /// Calls `f` with every [`Entity`] inside the value, so they can be remapped.
+    pub fn map_entities(&mut self, f: &mut dyn FnMut(Entity) -> Entity) {
+        match self {
+            Value::Entity(entity) => *entity = f(*entity),
+            Value::Struct(fields) | Value::Enum { fields, .. } => {
+                for (_, value) in fields {
+                    value.map_entities(f);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Looks up a field in the fields of a [`Value::Struct`] or [`Value::Enum`]. Used by
+/// `#[derive(Reflect)]`.
+pub fn field_value<'a>(
+    fields: &'a [(String, Value)],
+    name: &str,
+    type_name: &str,
+) -> KResult<&'a Value> {
+    fields
+        .iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, value)| value)
+        .ok_or_else(|| kerror!(format!("Value: {} is missing field {}", type_name, name)))
+}
+
+/// Overwrites every field of `target` that `value` has. Used by `#[derive(Reflect)]`.
+pub fn set_struct(target: &mut dyn Struct, value: &Value) -> KResult<()> {
+    let type_name = target.type_name();
+    for (name, value) in value.as_struct(type_name)? {
+        target
+            .field_mut(name)
+            .ok_or_else(|| kerror!(format!("Value: {} has no field {}", type_name, name)))?
+            .set_value(value)?;
+    }
+    Ok(())
+}
+
+/// The error for a [`Value`] of the wrong kind. Used by `#[derive(Reflect)]`.
+pub fn mismatch(type_name: &str, expected: &str, found: &Value) -> KError {
+    kerror!(format!(
+        "Value: expected {} for {}, found {:?}",
+        expected, type_name, found
+    ))
+}
+
+/// The error for an enum variant that doesn't exist. Used by `#[derive(Reflect)]`.
+pub fn unknown_variant(type_name: &str, variant: &str) -> KError {
+    kerror!(format!("Value: {} has no variant {}", type_name, variant))
+}
+
+macro_rules! impl_reflect_value {
+    ($($ty:ty => $variant:ident as $repr:ty),* $(,)?) => {
+        $(
+            impl Reflect for $ty {
+                fn reflect_ref(&self) -> ReflectRef<'_> {
+                    ReflectRef::Value(self)
+                }
+
+                fn reflect_mut(&mut self) -> ReflectMut<'_> {
+                    ReflectMut::Value(self)
+                }
+
+                fn to_value(&self) -> Value {
+                    Value::$variant(<$repr>::from(self.clone()))
+                }
+
+                fn set_value(&mut self, value: &Value) -> KResult<()> {
+                    *self = Self::from_value(value)?;
+                    Ok(())
+                }
+
+                fn from_value(value: &Value) -> KResult<Self> {
+                    FromPrimitive::from_primitive(value)
+                        .ok_or_else(|| mismatch(type_name::<Self>(), stringify!($ty), value))
+                }
+
+                fn schema_hash() -> u64 {
+                    name_hash(stringify!($ty))
+                }
+            }
+        )*
+    };
+}
+
+/// Conversion from a primitive [`Value`], allowing any number kind that fits.
+trait FromPrimitive: Sized {
+    fn from_primitive(value: &Value) -> Option<Self>;
+}
+
+macro_rules! impl_from_primitive_int {
+    ($($ty:ty),*) => {
+        $(
+            impl FromPrimitive for $ty {
+                fn from_primitive(value: &Value) -> Option<Self> {
+                    match *value {
+                        Value::Int(value) => value.try_into().ok(),
+                        Value::UInt(value) => value.try_into().ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_primitive_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl FromPrimitive for f32 {
+    fn from_primitive(value: &Value) -> Option<Self> {
+        f64::from_primitive(value).map(|value| value as f32)
+    }
+}
+
+impl FromPrimitive for f64 {
+    fn from_primitive(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Float(value) => Some(value),
+            Value::Int(value) => Some(value as f64),
+            Value::UInt(value) => Some(value as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromPrimitive for bool {
+    fn from_primitive(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Bool(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPrimitive for char {
+    fn from_primitive(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Char(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl FromPrimitive for String {
+    fn from_primitive(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl FromPrimitive for Entity {
+    fn from_primitive(value: &Value) -> Option<Self> {
+        match *value {
+            Value::Entity(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl_reflect_value!(
+    i8 => Int as i64,
+    i16 => Int as i64,
+    i32 => Int as i64,
+    i64 => Int as i64,
+    u8 => UInt as u64,
+    u16 => UInt as u64,
+    u32 => UInt as u64,
+    u64 => UInt as u64,
+    f32 => Float as f64,
+    f64 => Float as f64,
+    bool => Bool as bool,
+    char => Char as char,
+    String => String as String,
+    Entity => Entity as Entity,
+);
+
+/// Reflects glam types as structs of their `f32` components.
+macro_rules! impl_reflect_glam {
+    ($($ty:ident { $($field:ident),* } => $new:path),* $(,)?) => {
+        $(
+            impl Reflect for glam::$ty {
+                fn reflect_ref(&self) -> ReflectRef<'_> {
+                    ReflectRef::Struct(self)
+                }
+
+                fn reflect_mut(&mut self) -> ReflectMut<'_> {
+                    ReflectMut::Struct(self)
+                }
+
+                fn to_value(&self) -> Value {
+                    Value::Struct(vec![
+                        $((stringify!($field).to_string(), self.$field.to_value()),)*
+                    ])
+                }
+
+                fn set_value(&mut self, value: &Value) -> KResult<()> {
+                    set_struct(self, value)
+                }
+
+                fn from_value(value: &Value) -> KResult<Self> {
+                    let type_name = type_name::<Self>();
+                    let fields = value.as_struct(type_name)?;
+                    Ok($new($(
+                        f32::from_value(field_value(fields, stringify!($field), type_name)?)?,
+                    )*))
+                }
+
+                fn schema_hash() -> u64 {
+                    let mut hasher = SchemaHasher::new();
+                    hasher.write_str(stringify!($ty));
+                    let names: &[&str] = &[$(stringify!($field)),*];
+                    hasher.write_u64(names.len() as u64);
+                    for name in names {
+                        hasher.write_str(name);
+                        hasher.write_u64(f32::schema_hash());
+                    }
+                    hasher.finish()
+                }
+            }
+
+            impl Struct for glam::$ty {
+                fn field(&self, name: &str) -> Option<&dyn Reflect> {
+                    $(
+                        if name == stringify!($field) {
+                            return Some(&self.$field);
+                        }
+                    )*
+                    None
+                }
+
+                fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+                    $(
+                        if name == stringify!($field) {
+                            return Some(&mut self.$field);
+                        }
+                    )*
+                    None
+                }
+
+                fn field_names(&self) -> &'static [&'static str] {
+                    &[$(stringify!($field)),*]
+                }
+            }
+        )*
+    };
+}
+
+impl_reflect_glam!(
+    Vec2 { x, y } => glam::Vec2::new,
+    Vec3 { x, y, z } => glam::Vec3::new,
+    Vec4 { x, y, z, w } => glam::Vec4::new,
+    Quat { x, y, z, w } => glam::Quat::from_xyzw,
+);
+
+impl Reflect for () {
+    fn reflect_ref(&self) -> ReflectRef<'_> {
+        ReflectRef::Value(self)
+    }
+
+    fn reflect_mut(&mut self) -> ReflectMut<'_> {
+        ReflectMut::Value(self)
+    }
+
+    fn to_value(&self) -> Value {
+        Value::Unit
+    }
+
+    fn set_value(&mut self, value: &Value) -> KResult<()> {
+        Self::from_value(value)
+    }
+
+    fn from_value(value: &Value) -> KResult<Self> {
+        match value {
+            Value::Unit => Ok(()),
+            other => Err(mismatch("()", "()", other)),
+        }
+    }
+
+    fn schema_hash() -> u64 {
+        name_hash("()")
+    }
+}
+
+impl<T: Reflect> Reflect for Option<T> {
+    fn reflect_ref(&self) -> ReflectRef<'_> {
+        ReflectRef::Enum(self)
+    }
+
+    fn reflect_mut(&mut self) -> ReflectMut<'_> {
+        ReflectMut::Enum(self)
+    }
+
+    fn to_value(&self) -> Value {
+        match self {
+            Some(value) => Value::Enum {
+                variant: "Some".to_string(),
+                fields: vec![("0".to_string(), value.to_value())],
+            },
+            None => Value::Enum {
+                variant: "None".to_string(),
+                fields: Vec::new(),
+            },
+        }
+    }
+
+    fn set_value(&mut self, value: &Value) -> KResult<()> {
+        *self = Self::from_value(value)?;
+        Ok(())
+    }
+
+    fn from_value(value: &Value) -> KResult<Self> {
+        let type_name = type_name::<Self>();
+        match value.as_enum(type_name)? {
+            ("Some", fields) => Ok(Some(T::from_value(field_value(fields, "0", type_name)?)?)),
+            ("None", _) => Ok(None),
+            (variant, _) => Err(unknown_variant(type_name, variant)),
+        }
+    }
+
+    fn schema_hash() -> u64 {
+        let mut hasher = SchemaHasher::new();
+        hasher.write_str("Option");
+        hasher.write_u64(T::schema_hash());
+        hasher.finish()
+    }
+}
+
+impl<T: Reflect> Enum for Option<T> {
+    fn variant_name(&self) -> &'static str {
+        match self {
+            Some(_) => "Some",
+            None => "None",
+        }
+    }
+
+    fn variant_names(&self) -> &'static [&'static str] {
+        &["None", "Some"]
+    }
+
+    fn field(&self, name: &str) -> Option<&dyn Reflect> {
+        match (self, name) {
+            (Some(value), "0") => Some(value),
+            _ => None,
+        }
+    }
+
+    fn field_mut(&mut self, name: &str) -> Option<&mut dyn Reflect> {
+        match (self, name) {
+            (Some(value), "0") => Some(value),
+            _ => None,
+        }
+    }
+
+    fn field_names(&self) -> &'static [&'static str] {
+        match self {
+            Some(_) => &["0"],
+            None => &[],
+        }
+    }
+}
+
+/// Everything needed to work with a registered type without knowing it statically.
+#[derive(Clone)]
+pub struct TypeRegistration {
+    type_id: TypeId,
+    type_name: &'static str,
+    short_name: String,
+    schema_hash: u64,
+    from_value: fn(&Value) -> KResult<Box<dyn Reflect>>,
+    as_reflect: fn(&dyn Component) -> Option<&dyn Reflect>,
+    as_reflect_mut: fn(&mut dyn Component) -> Option<&mut dyn Reflect>,
+    insert: fn(&mut World, Entity, Box<dyn Reflect>) -> KResult<()>,
+    remove: fn(&mut World, Entity) -> bool,
+    // extra data other crates attach to the type, keyed by the data's type
+    data: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl TypeRegistration {
+    pub fn of<T: Reflect>() -> Self {
+        Self {
+            type_id: TypeId::of::<T>(),
+            type_name: type_name::<T>(),
+            short_name: short_type_name(type_name::<T>()),
+            schema_hash: T::schema_hash(),
+            from_value: |value| Ok(Box::new(T::from_value(value)?)),
+            as_reflect: |component| {
+                let component = component.as_any().downcast_ref::<T>()?;
+                Some(component)
+            },
+            as_reflect_mut: |component| {
+                let component = component.as_any_mut().downcast_mut::<T>()?;
+                Some(component)
+            },
+            insert: |world, entity, value| {
+                let value = value.as_any_box().downcast::<T>().map_err(|_| {
+                    kerror!(format!(
+                        "TypeRegistration::insert(): value is not a {}",
+                        type_name::<T>()
+                    ))
+                })?;
+                world.insert_component(entity, *value)
+            },
+            remove: |world, entity| world.remove_component::<T>(entity).is_some(),
+            data: HashMap::new(),
+        }
+    }
+
+    /// Attaches extra data to the type, such as how to treat it as something other than a
+    /// component. Replaces any data of the same type.
+    pub fn insert_data<D: Any + Send + Sync>(&mut self, data: D) {
+        self.data.insert(TypeId::of::<D>(), Arc::new(data));
+    }
+
+    pub fn data<D: Any + Send + Sync>(&self) -> Option<&D> {
+        self.data.get(&TypeId::of::<D>())?.downcast_ref()
+    }
+
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    /// The type name without any module paths, e.g. `Option<Position>`.
+    pub fn short_name(&self) -> &str {
+        &self.short_name
+    }
+
+    /// See [`Reflect::schema_hash`].
+    pub fn schema_hash(&self) -> u64 {
+        self.schema_hash
+    }
+
+    pub fn from_value(&self, value: &Value) -> KResult<Box<dyn Reflect>> {
+        (self.from_value)(value)
+    }
+
+    /// Views a component of this type as [`Reflect`]. Returns `None` for other types.
+    pub fn as_reflect<'a>(&self, component: &'a dyn Component) -> Option<&'a dyn Reflect> {
+        (self.as_reflect)(component)
+    }
+
+    pub fn as_reflect_mut<'a>(
+        &self,
+        component: &'a mut dyn Component,
+    ) -> Option<&'a mut dyn Reflect> {
+        (self.as_reflect_mut)(component)
+    }
+
+    /// Inserts a value of this type, e.g. one made with [`from_value`](Self::from_value), as a
+    /// component of `entity`.
+    pub fn insert(
+        &self,
+        world: &mut World,
+        entity: Entity,
+        value: Box<dyn Reflect>,
+    ) -> KResult<()> {
+        (self.insert)(world, entity, value)
+    }
+
+    /// Removes the component of this type from `entity`, returning whether it had one.
+    pub fn remove(&self, world: &mut World, entity: Entity) -> bool {
+        (self.remove)(world, entity)
+    }
+}
+
+/// The reflected types known to an app, looked up by [`TypeId`] or by name.
+///
+/// Stored as a resource of the app's [`World`], which uses it to find components by name.
+#[derive(Default, Clone)]
+pub struct TypeRegistry {
+    types: HashMap<TypeId, TypeRegistration>,
+    // full and short names; a short name shared by several types maps to `None`
+    names: HashMap<String, Option<TypeId>>,
+}
+
+impl TypeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `T`. Registering a type again does nothing.
+    pub fn register<T: Reflect>(&mut self) {
+        if self.types.contains_key(&TypeId::of::<T>()) {
+            return;
+        }
+
+        let registration = TypeRegistration::of::<T>();
+        self.names.insert(
+            registration.type_name.to_string(),
+            Some(registration.type_id),
+        );
+        self.names
+            .entry(registration.short_name.clone())
+            .and_modify(|id| *id = None)
+            .or_insert(Some(registration.type_id));
+        self.types.insert(registration.type_id, registration);
+    }
+
+    pub fn contains(&self, type_id: TypeId) -> bool {
+        self.types.contains_key(&type_id)
+    }
+
+    pub fn get(&self, type_id: TypeId) -> Option<&TypeRegistration> {
+        self.types.get(&type_id)
+    }
+
+    pub fn get_mut(&mut self, type_id: TypeId) -> Option<&mut TypeRegistration> {
+        self.types.get_mut(&type_id)
+    }
+
+    /// Looks a type up by its full name, or by its short name if no other registered type
+    /// shares it.
+    pub fn get_with_name(&self, name: &str) -> Option<&TypeRegistration> {
+        self.get((*self.names.get(name)?)?)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &TypeRegistration> + '_ {
+        self.types.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.types.is_empty()
+    }
+}
+
+/// Strips the module path from every type in a type name, so `std::option::Option<a::B>`
+/// becomes `Option<B>`.
+pub(crate) fn short_type_name(name: &str) -> String {
+    let mut short = String::with_capacity(name.len());
+    let mut segment_start = 0;
+    for (index, c) in name.char_indices() {
+        if matches!(c, '<' | '>' | ',' | ' ' | '(' | ')' | '[' | ']' | '&' | ';') {
+            short.push_str(last_path_segment(&name[segment_start..index]));
+            short.push(c);
+            segment_start = index + c.len_utf8();
+        }
+    }
+    short.push_str(last_path_segment(&name[segment_start..]));
+    short
+}
+
+fn last_path_segment(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Target(Entity, Option<u32>);
+
+    #[derive(Debug, PartialEq, Reflect)]
+    enum Shape {
+        Circle { radius: f32 },
+        Rect(f32, f32),
+        Point,
+    }
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Body {
+        position: Position,
+        shape: Shape,
+        #[reflect(ignore)]
+        cache: Vec<u8>,
+    }
+
+    #[test]
+    fn test_reflect_struct() {
+        let mut body = Body {
+            position: Position { x: 1.0, y: 2.0 },
+            shape: Shape::Rect(3.0, 4.0),
+            cache: vec![1, 2, 3],
+        };
+
+        let reflect: &mut dyn Reflect = &mut body;
+        assert_eq!(
+            reflect.path("position.y").unwrap().downcast_ref::<f32>(),
+            Some(&2.0)
+        );
+        assert_eq!(
+            reflect.path("shape.1").unwrap().downcast_ref::<f32>(),
+            Some(&4.0)
+        );
+        assert!(reflect.path("position.z").is_err());
+        assert!(reflect.path("cache").is_err());
+
+        reflect
+            .path_mut("position.x")
+            .unwrap()
+            .set_value(&Value::Int(5))
+            .unwrap();
+        assert!(reflect
+            .path_mut("position.x")
+            .unwrap()
+            .set_value(&Value::String("five".to_string()))
+            .is_err());
+        assert_eq!(body.position.x, 5.0);
+
+        let ReflectRef::Struct(reflect) = body.reflect_ref() else {
+            panic!("Body should reflect as a struct");
+        };
+        assert_eq!(reflect.field_names(), &["position", "shape"]);
+    }
+
+    #[test]
+    fn test_reflect_enum() {
+        let mut shape = Shape::Circle { radius: 1.0 };
+        let ReflectMut::Enum(reflect) = shape.reflect_mut() else {
+            panic!("Shape should reflect as an enum");
+        };
+        assert_eq!(reflect.variant_name(), "Circle");
+        assert_eq!(reflect.variant_names(), &["Circle", "Rect", "Point"]);
+        reflect
+            .field_mut("radius")
+            .unwrap()
+            .set_value(&Value::Float(2.0))
+            .unwrap();
+        assert_eq!(shape, Shape::Circle { radius: 2.0 });
+
+        shape
+            .set_value(&Value::Enum {
+                variant: "Point".to_string(),
+                fields: Vec::new(),
+            })
+            .unwrap();
+        assert_eq!(shape, Shape::Point);
+    }
+
+    #[test]
+    fn test_value_round_trip() {
+        let body = Body {
+            position: Position { x: 1.0, y: 2.0 },
+            shape: Shape::Circle { radius: 3.0 },
+            cache: vec![1, 2, 3],
+        };
+        let target = Target(Entity::new(3, 1), Some(7));
+
+        let value = body.to_value();
+        assert_eq!(
+            Body::from_value(&value).unwrap(),
+            Body {
+                cache: Vec::new(),
+                ..body
+            }
+        );
+
+        let mut value = target.to_value();
+        value.map_entities(&mut |entity| Entity::new(entity.id() + 1, 0));
+        assert_eq!(
+            Target::from_value(&value).unwrap(),
+            Target(Entity::new(4, 0), Some(7))
+        );
+
+        assert!(
+            Position::from_value(&Value::Struct(vec![("x".to_string(), Value::Float(1.0))]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_type_registry() {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Position>();
+        registry.register::<Option<Position>>();
+
+        let registration = registry.get_with_name("Position").unwrap();
+        assert_eq!(registration.type_id(), TypeId::of::<Position>());
+        assert_eq!(
+            registry
+                .get_with_name(type_name::<Position>())
+                .unwrap()
+                .type_id(),
+            TypeId::of::<Position>()
+        );
+        assert_eq!(
+            registry
+                .get_with_name("Option<Position>")
+                .unwrap()
+                .type_id(),
+            TypeId::of::<Option<Position>>()
+        );
+        assert!(registry.get_with_name("Velocity").is_none());
+    }
+
+    #[test]
+    fn test_world_reflect_path() {
+        let mut world = World::new();
+        let mut registry = TypeRegistry::new();
+        registry.register::<Position>();
+        world.insert_resource(registry);
+
+        let entity = world.spawn((Position { x: 1.0, y: 2.0 },));
+        assert_eq!(
+            world.reflect_path(entity, "Position.x").unwrap(),
+            Value::Float(1.0)
+        );
+
+        world
+            .set_reflect_path(entity, "Position.y", &Value::Float(3.0))
+            .unwrap();
+        assert_eq!(world.get_component::<Position>(entity).unwrap().y, 3.0);
+        world
+            .set_reflect_path(
+                entity,
+                "Position",
+                &Value::Struct(vec![("x".to_string(), Value::Float(4.0))]),
+            )
+            .unwrap();
+        assert_eq!(
+            *world.get_component::<Position>(entity).unwrap(),
+            Position { x: 4.0, y: 3.0 }
+        );
+
+        assert!(world.reflect_path(entity, "Velocity.x").is_err());
+        assert!(world.reflect_path(entity, "Position.z").is_err());
+        let empty = world.create_entity();
+        assert!(world.reflect_path(empty, "Position.x").is_err());
+    }
+}
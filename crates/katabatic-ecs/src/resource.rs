@@ -0,0 +1,214 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::HashMap,
+    mem::ManuallyDrop,
+    thread::{self, ThreadId},
+};
+
+use katabatic_util::lock::{Lock, MapRead, MapWrite};
+
+/// A global value stored in the [`World`](crate::world::World) by its type.
+///
+/// Values that aren't `Send + Sync` can still be stored as non-`Send` resources, which may only
+/// be touched from the thread that inserted them.
+pub trait Resource: Send + Sync + 'static {}
+
+impl<T: Send + Sync + 'static> Resource for T {}
+
+/// Storage for a world's resources, one value per type.
+#[derive(Default)]
+pub struct Resources {
+    resources: HashMap<TypeId, Lock<Box<dyn Any + Send + Sync>>>,
+    non_send: HashMap<TypeId, NonSendData>,
+}
+
+impl Resources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `value`, returning the previous value of the same type, if any.
+    pub fn insert<R: Resource>(&mut self, value: R) -> Option<R> {
+        self.resources
+            .insert(TypeId::of::<R>(), Lock::new(Box::new(value)))
+            .map(|old| downcast(old.into_inner()))
+    }
+
+    pub fn remove<R: Resource>(&mut self) -> Option<R> {
+        self.resources
+            .remove(&TypeId::of::<R>())
+            .map(|old| downcast(old.into_inner()))
+    }
+
+    pub fn contains<R: Resource>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+
+    pub fn get<R: Resource>(&self) -> Option<MapRead<'_, R>> {
+        let lock = self.resources.get(&TypeId::of::<R>())?;
+        lock.filter_map_read(|value| value.downcast_ref::<R>())
+    }
+
+    pub fn get_mut<R: Resource>(&self) -> Option<MapWrite<'_, R>> {
+        let lock = self.resources.get(&TypeId::of::<R>())?;
+        lock.filter_map_write(|value| value.downcast_mut::<R>())
+    }
+
+    /// Inserts a value that can only be used from the current thread, returning the previous
+    /// value of the same type, if any.
+    ///
+    /// # Panics
+    /// Panics if a previous value was inserted on another thread.
+    pub fn insert_non_send<R: 'static>(&mut self, value: R) -> Option<R> {
+        self.non_send
+            .insert(TypeId::of::<R>(), NonSendData::new(value))
+            .map(NonSendData::into_inner)
+    }
+
+    /// # Panics
+    /// Panics if the value was inserted on another thread.
+    pub fn remove_non_send<R: 'static>(&mut self) -> Option<R> {
+        self.non_send
+            .remove(&TypeId::of::<R>())
+            .map(NonSendData::into_inner)
+    }
+
+    pub fn contains_non_send<R: 'static>(&self) -> bool {
+        self.non_send.contains_key(&TypeId::of::<R>())
+    }
+
+    /// # Panics
+    /// Panics if the value was inserted on another thread.
+    pub fn get_non_send<R: 'static>(&self) -> Option<MapRead<'_, R>> {
+        let data = self.non_send.get(&TypeId::of::<R>())?;
+        data.check_thread();
+        data.value
+            .filter_map_read(|value| value.downcast_ref::<R>())
+    }
+
+    /// # Panics
+    /// Panics if the value was inserted on another thread.
+    pub fn get_non_send_mut<R: 'static>(&self) -> Option<MapWrite<'_, R>> {
+        let data = self.non_send.get(&TypeId::of::<R>())?;
+        data.check_thread();
+        data.value
+            .filter_map_write(|value| value.downcast_mut::<R>())
+    }
+
+    pub fn type_ids(&self) -> impl Iterator<Item = TypeId> + '_ {
+        self.resources.keys().chain(self.non_send.keys()).copied()
+    }
+}
+
+fn downcast<R: 'static>(value: Box<dyn Any + Send + Sync>) -> R {
+    *value
+        .downcast::<R>()
+        .expect("Resources: stored value has the wrong type")
+}
+
+/// A non-`Send` value, along with the thread it belongs to.
+struct NonSendData {
+    value: ManuallyDrop<Lock<Box<dyn Any>>>,
+    type_name: &'static str,
+    owner: ThreadId,
+}
+
+// SAFETY: the value is only ever accessed or dropped on its owner thread, which is checked
+unsafe impl Send for NonSendData {}
+unsafe impl Sync for NonSendData {}
+
+impl NonSendData {
+    fn new<R: 'static>(value: R) -> Self {
+        Self {
+            value: ManuallyDrop::new(Lock::new(Box::new(value))),
+            type_name: type_name::<R>(),
+            owner: thread::current().id(),
+        }
+    }
+
+    fn check_thread(&self) {
+        assert_eq!(
+            thread::current().id(),
+            self.owner,
+            "Resources: non-Send resource {} used outside of the thread it was inserted on",
+            self.type_name
+        );
+    }
+
+    fn into_inner<R: 'static>(mut self) -> R {
+        self.check_thread();
+        let value = unsafe { ManuallyDrop::take(&mut self.value) };
+        std::mem::forget(self);
+        *value
+            .into_inner()
+            .downcast::<R>()
+            .expect("Resources: stored value has the wrong type")
+    }
+}
+
+impl Drop for NonSendData {
+    fn drop(&mut self) {
+        if thread::current().id() == self.owner {
+            unsafe { ManuallyDrop::drop(&mut self.value) };
+        } else if !thread::panicking() {
+            // leaking is the only safe option, but it's almost certainly a bug
+            panic!(
+                "Resources: non-Send resource {} dropped outside of the thread it was inserted on",
+                self.type_name
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::world::World;
+
+    #[derive(Debug, PartialEq)]
+    struct Gravity(f32);
+
+    #[test]
+    fn test_resources() {
+        let mut world = World::new();
+
+        assert!(world.resource::<Gravity>().is_none());
+        assert_eq!(world.insert_resource(Gravity(-9.8)), None);
+        assert!(world.contains_resource::<Gravity>());
+
+        world.resource_mut::<Gravity>().unwrap().0 *= 2.0;
+        assert_eq!(*world.resource::<Gravity>().unwrap(), Gravity(-19.6));
+
+        assert_eq!(world.insert_resource(Gravity(1.0)), Some(Gravity(-19.6)));
+        assert_eq!(world.remove_resource::<Gravity>(), Some(Gravity(1.0)));
+        assert!(!world.contains_resource::<Gravity>());
+    }
+
+    #[test]
+    fn test_non_send_resources() {
+        let mut world = World::new();
+        let rc = Rc::new(5);
+
+        world.insert_non_send_resource(rc.clone());
+        assert_eq!(**world.non_send_resource::<Rc<i32>>().unwrap(), 5);
+        assert_eq!(Rc::strong_count(&rc), 2);
+
+        drop(world);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn test_non_send_wrong_thread() {
+        let mut world = World::new();
+        world.insert_non_send_resource(Rc::new(5));
+
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(|| world.non_send_resource::<Rc<i32>>().is_some())
+                .join()
+        });
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::{BTreeMap, HashSet};
+
+use katabatic_util::{
+    error::{KError, KResult},
+    kbail, kensure, kerror,
+};
+
+use crate::{
+    entity::Entity,
+    reflect::{short_type_name, Reflect, TypeRegistration, TypeRegistry, Value},
+    world::World,
+};
+
+const MAGIC: &[u8; 4] = b"KTBS";
+
+/// The snapshot format version, bumped whenever the encoding changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A binary copy of a [`World`]'s entities and reflected components, made with
+/// [`World::snapshot`] and applied with [`World::restore`].
+///
+/// Snapshots start with a format version, and record a [schema hash](Reflect::schema_hash) for
+/// every component type, so a snapshot made by a build with different component layouts is
+/// rejected instead of being misread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    bytes: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Wraps bytes from [`as_bytes`](Self::as_bytes), e.g. after reading them from a file. The
+    /// bytes are only checked when the snapshot is restored.
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        Self { bytes }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn capture(world: &World) -> Self {
+        let mut writer = Writer::default();
+        writer.bytes(MAGIC);
+        writer.u32(SNAPSHOT_VERSION);
+
+        let entities = world.entities();
+        let generations = entities.generations().collect::<Vec<_>>();
+        writer.len(generations.len());
+        for (generation, alive) in generations {
+            writer.u32(generation);
+            writer.u8(alive as u8);
+        }
+        writer.len(entities.free_entities().len());
+        for entity in entities.free_entities() {
+            writer.entity(*entity);
+        }
+
+        let registry = world.resource::<TypeRegistry>();
+        // components grouped by type, in a fixed order so equal worlds give equal snapshots
+        let mut components = BTreeMap::<&str, (&TypeRegistration, Vec<(u32, Value)>)>::new();
+        if let Some(registry) = &registry {
+            for entity in entities.iter() {
+                let Some(archetype) = world.storage().archetype_of(entity) else {
+                    continue;
+                };
+                for registration in archetype
+                    .type_ids()
+                    .iter()
+                    .filter_map(|&id| registry.get(id))
+                {
+                    let data = world
+                        .storage()
+                        .get_data(entity, registration.type_id())
+                        .unwrap();
+                    if let Some(reflect) = registration.as_reflect(&*data) {
+                        components
+                            .entry(registration.type_name())
+                            .or_insert_with(|| (registration, Vec::new()))
+                            .1
+                            .push((entity.id(), reflect.to_value()));
+                    }
+                }
+            }
+        }
+
+        writer.len(components.len());
+        for (type_name, (registration, values)) in components {
+            writer.str(type_name);
+            writer.u64(registration.schema_hash());
+            writer.len(values.len());
+            for (id, value) in values {
+                writer.u32(id);
+                writer.value(&value);
+            }
+        }
+
+        Self {
+            bytes: writer.bytes,
+        }
+    }
+
+    /// Reads the whole snapshot, checking it against `registry`.
+    pub(crate) fn decode(&self, registry: &TypeRegistry) -> KResult<DecodedSnapshot> {
+        let mut reader = Reader {
+            bytes: &self.bytes,
+            pos: 0,
+        };
+
+        kensure!(
+            reader.take(MAGIC.len()).ok() == Some(&MAGIC[..]),
+            "Snapshot: not a world snapshot"
+        );
+        let version = reader.u32()?;
+        kensure!(
+            version == SNAPSHOT_VERSION,
+            format!(
+                "Snapshot: made with format version {}, but this build reads version {}",
+                version, SNAPSHOT_VERSION
+            )
+        );
+
+        let mut generations = Vec::new();
+        for _ in 0..reader.len()? {
+            generations.push((reader.u32()?, reader.u8()? != 0));
+        }
+        let mut free_entities = Vec::new();
+        for _ in 0..reader.len()? {
+            free_entities.push(reader.entity()?);
+        }
+
+        let mut components = Vec::new();
+        let mut types = HashSet::new();
+        for _ in 0..reader.len()? {
+            let type_name = reader.str()?;
+            let schema_hash = reader.u64()?;
+            // fall back to the short name, in case the type has moved to another module
+            let registration = registry
+                .get_with_name(&type_name)
+                .or_else(|| registry.get_with_name(&short_type_name(&type_name)))
+                .ok_or_else(|| {
+                    kerror!(format!(
+                        "Snapshot: component type {} is not registered",
+                        type_name
+                    ))
+                })?;
+            kensure!(
+                schema_hash == registration.schema_hash(),
+                format!(
+                    "Snapshot: the layout of {} has changed since the snapshot was made \
+                     (schema hash {:016x}, now {:016x})",
+                    type_name,
+                    schema_hash,
+                    registration.schema_hash()
+                )
+            );
+            kensure!(
+                types.insert(registration.type_id()),
+                format!("Snapshot: component type {} appears twice", type_name)
+            );
+
+            for _ in 0..reader.len()? {
+                let id = reader.u32()?;
+                let entity = match generations.get(id as usize) {
+                    Some(&(generation, true)) => Entity::new(id, generation),
+                    _ => kbail!(format!(
+                        "Snapshot: {} component on entity {}, which is not alive",
+                        type_name, id
+                    )),
+                };
+                let value = reader.value()?;
+                components.push((
+                    registration.clone(),
+                    entity,
+                    registration.from_value(&value)?,
+                ));
+            }
+        }
+
+        kensure!(
+            reader.pos == self.bytes.len(),
+            "Snapshot: unexpected data after the end of the snapshot"
+        );
+
+        Ok(DecodedSnapshot {
+            generations,
+            free_entities,
+            components,
+        })
+    }
+}
+
+/// A snapshot that has been checked, and is ready to restore.
+pub(crate) struct DecodedSnapshot {
+    pub(crate) generations: Vec<(u32, bool)>,
+    pub(crate) free_entities: Vec<Entity>,
+    pub(crate) components: Vec<(TypeRegistration, Entity, Box<dyn Reflect>)>,
+}
+
+// value tags
+const UNIT: u8 = 0;
+const BOOL: u8 = 1;
+const INT: u8 = 2;
+const UINT: u8 = 3;
+const FLOAT: u8 = 4;
+const CHAR: u8 = 5;
+const STRING: u8 = 6;
+const ENTITY: u8 = 7;
+const STRUCT: u8 = 8;
+const ENUM: u8 = 9;
+
+/// Little-endian encoding, with lengths as `u32`s.
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(u32::try_from(len).expect("Snapshot: too many items"));
+    }
+
+    fn str(&mut self, value: &str) {
+        self.len(value.len());
+        self.bytes(value.as_bytes());
+    }
+
+    fn entity(&mut self, entity: Entity) {
+        self.u32(entity.id());
+        self.u32(entity.generation());
+    }
+
+    fn fields(&mut self, fields: &[(String, Value)]) {
+        self.len(fields.len());
+        for (name, value) in fields {
+            self.str(name);
+            self.value(value);
+        }
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Unit => self.u8(UNIT),
+            Value::Bool(value) => {
+                self.u8(BOOL);
+                self.u8(*value as u8);
+            }
+            Value::Int(value) => {
+                self.u8(INT);
+                self.bytes(&value.to_le_bytes());
+            }
+            Value::UInt(value) => {
+                self.u8(UINT);
+                self.u64(*value);
+            }
+            Value::Float(value) => {
+                self.u8(FLOAT);
+                self.u64(value.to_bits());
+            }
+            Value::Char(value) => {
+                self.u8(CHAR);
+                self.u32(*value as u32);
+            }
+            Value::String(value) => {
+                self.u8(STRING);
+                self.str(value);
+            }
+            Value::Entity(value) => {
+                self.u8(ENTITY);
+                self.entity(*value);
+            }
+            Value::Struct(fields) => {
+                self.u8(STRUCT);
+                self.fields(fields);
+            }
+            Value::Enum { variant, fields } => {
+                self.u8(ENUM);
+                self.str(variant);
+                self.fields(fields);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> KResult<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or_else(|| kerror!("Snapshot: unexpected end of data"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> KResult<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> KResult<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> KResult<u32> {
+        self.array().map(u32::from_le_bytes)
+    }
+
+    fn u64(&mut self) -> KResult<u64> {
+        self.array().map(u64::from_le_bytes)
+    }
+
+    fn len(&mut self) -> KResult<usize> {
+        let len = self.u32()? as usize;
+        // every item takes at least a byte, so a longer length can only be corrupt
+        kensure!(
+            len <= self.bytes.len() - self.pos,
+            "Snapshot: length is longer than the remaining data"
+        );
+        Ok(len)
+    }
+
+    fn str(&mut self) -> KResult<String> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| kerror!("Snapshot: string is not valid UTF-8"))
+    }
+
+    fn entity(&mut self) -> KResult<Entity> {
+        Ok(Entity::new(self.u32()?, self.u32()?))
+    }
+
+    fn fields(&mut self) -> KResult<Vec<(String, Value)>> {
+        let mut fields = Vec::new();
+        for _ in 0..self.len()? {
+            fields.push((self.str()?, self.value()?));
+        }
+        Ok(fields)
+    }
+
+    fn value(&mut self) -> KResult<Value> {
+        Ok(match self.u8()? {
+            UNIT => Value::Unit,
+            BOOL => Value::Bool(self.u8()? != 0),
+            INT => Value::Int(self.array().map(i64::from_le_bytes)?),
+            UINT => Value::UInt(self.u64()?),
+            FLOAT => Value::Float(f64::from_bits(self.u64()?)),
+            CHAR => Value::Char(
+                char::from_u32(self.u32()?).ok_or_else(|| kerror!("Snapshot: invalid char"))?,
+            ),
+            STRING => Value::String(self.str()?),
+            ENTITY => Value::Entity(self.entity()?),
+            STRUCT => Value::Struct(self.fields()?),
+            ENUM => Value::Enum {
+                variant: self.str()?,
+                fields: self.fields()?,
+            },
+            tag => return Err(invalid_tag(tag)),
+        })
+    }
+}
+
+fn invalid_tag(tag: u8) -> KError {
+    kerror!(format!("Snapshot: invalid value tag {}", tag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reflect::Reflect;
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, PartialEq, Reflect)]
+    enum Target {
+        None,
+        Entity(Entity),
+    }
+
+    mod v2 {
+        use crate::reflect::Reflect;
+
+        // the same name as the other `Position`, with another field
+        #[derive(Debug, PartialEq, Reflect)]
+        pub struct Position {
+            pub x: f32,
+            pub y: f32,
+            pub z: f32,
+        }
+    }
+
+    fn new_world() -> World {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Position>();
+        registry.register::<Target>();
+        let mut world = World::new();
+        world.insert_resource(registry);
+        world
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let mut world = new_world();
+        let a = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let b = world.spawn((Position { x: 3.0, y: 4.0 }, Target::Entity(a)));
+        let c = world.create_entity();
+        world.destroy_entity(c).unwrap();
+        let snapshot = world.snapshot();
+
+        // change the world, then roll it back
+        world.destroy_entity(a).unwrap();
+        world.get_component_mut::<Position>(b).unwrap().x = 10.0;
+        let d = world.spawn((Target::None,));
+        world.restore(&snapshot).unwrap();
+
+        assert!(world.is_alive(a) && world.is_alive(b));
+        assert!(!world.is_alive(d));
+        assert_eq!(world.entities().len(), 2);
+        assert_eq!(
+            *world.get_component::<Position>(b).unwrap(),
+            Position { x: 3.0, y: 4.0 }
+        );
+        assert_eq!(
+            *world.get_component::<Target>(b).unwrap(),
+            Target::Entity(a)
+        );
+        // the free list comes back too, so new entities get the same ids as before
+        assert_eq!(
+            world.create_entity(),
+            Entity::new(c.id(), c.generation() + 1)
+        );
+
+        // restoring into a different world gives the same result
+        let mut other = new_world();
+        other.restore(&snapshot).unwrap();
+        assert_eq!(other.snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_errors() {
+        let mut world = new_world();
+        let entity = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let snapshot = world.snapshot();
+
+        // a build where `Position` has changed
+        let mut registry = TypeRegistry::new();
+        registry.register::<v2::Position>();
+        registry.register::<Target>();
+        let mut other = World::new();
+        other.insert_resource(registry);
+        let error = other.restore(&snapshot).unwrap_err();
+        assert!(error.desc.unwrap().contains("has changed"));
+        assert!(other.entities().is_empty());
+
+        let mut bytes = snapshot.clone().into_bytes();
+        bytes[4] = 99;
+        let error = world.restore(&Snapshot::from_bytes(bytes)).unwrap_err();
+        assert!(error.desc.unwrap().contains("format version 99"));
+
+        let mut bytes = snapshot.clone().into_bytes();
+        bytes.truncate(bytes.len() - 3);
+        assert!(world.restore(&Snapshot::from_bytes(bytes)).is_err());
+        assert!(world.restore(&Snapshot::from_bytes(Vec::new())).is_err());
+
+        // failed restores leave the world alone
+        assert_eq!(
+            *world.get_component::<Position>(entity).unwrap(),
+            Position { x: 1.0, y: 2.0 }
+        );
+    }
+}
@@ -1,8 +1,19 @@
-use std::{any::TypeId, borrow::Cow, collections::HashMap};
+use std::{
+    any::TypeId,
+    borrow::Cow,
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
 use katabatic_util::lock::{Lock, MapRead, MapWrite};
 
-use crate::{component::Component, entity::Entity};
+use crate::{
+    bundle::Bundle,
+    column::Column,
+    component::{Component, ComponentInfo},
+    entity::Entity,
+    tick::{ComponentTicks, Tick},
+};
 
 pub struct Data {
     type_id: std::any::TypeId,
@@ -56,207 +67,344 @@ impl Data {
     }
 }
 
+impl Data {
+    /// Moves the component at `ptr` into a new boxed [`Data`].
+    ///
+    /// # Safety
+    /// `ptr` must point to a valid value of the type described by `info`, which must not be used
+    /// or dropped afterwards.
+    pub(crate) unsafe fn from_raw(info: &ComponentInfo, ptr: *mut u8) -> Self {
+        Self {
+            type_id: info.type_id(),
+            type_name: info.type_name().into(),
+            data: info.into_box(ptr),
+        }
+    }
+}
+
+/// All entities that have exactly the same set of component types.
+///
+/// Components are stored in one [`Column`] per type, and every column has the same row for the
+/// same entity, as recorded in the shared `entities` row table. Each column also has the
+/// [`ComponentTicks`] of every row.
+pub struct Archetype {
+    id: ArchetypeId,
+    signature: Box<[TypeId]>,
+    columns: Vec<Lock<Column>>,
+    ticks: Vec<ColumnTicks>,
+    type_ids: Vec<TypeId>,
+    entities: Vec<Entity>,
+    edges: ArchetypeEdges,
+}
+
+/// The added and changed ticks of each row of a column. Kept outside the column's lock, so they
+/// can be checked while the components themselves are borrowed.
 #[derive(Default)]
-pub struct SparseSet {
-    dense: Vec<Data>,
-    sparse: Vec<Option<usize>>,
-    indices: Vec<usize>,
+struct ColumnTicks {
+    added: Vec<Tick>,
+    changed: Vec<AtomicU32>,
 }
 
-impl SparseSet {
-    pub fn new() -> Self {
-        Self::default()
+impl ColumnTicks {
+    fn get(&self, row: usize) -> Option<ComponentTicks> {
+        Some(ComponentTicks {
+            added: *self.added.get(row)?,
+            changed: Tick::new(self.changed.get(row)?.load(Ordering::Relaxed)),
+        })
     }
 
-    pub fn dense_index_of(&self, id: usize) -> Option<usize> {
-        self.sparse.get(id).copied().flatten()
+    fn set_changed(&self, row: usize, tick: Tick) {
+        self.changed[row].store(tick.get(), Ordering::Relaxed);
     }
 
-    pub fn insert(&mut self, id: usize, data: Data) {
-        match self.dense_index_of(id) {
-            Some(index) => {
-                self.dense[index] = data;
-            }
-            None => {
-                let index = self.dense.len();
-                self.dense.push(data);
-                if id >= self.sparse.len() {
-                    self.sparse.resize(id + 1, None);
-                }
-                self.sparse[id] = Some(index);
-                self.indices.push(id);
-            }
-        }
+    fn push(&mut self, ticks: ComponentTicks) {
+        self.added.push(ticks.added);
+        self.changed.push(AtomicU32::new(ticks.changed.get()));
     }
 
-    pub fn remove(&mut self, id: usize) -> Option<Data> {
-        if id >= self.sparse.len() {
-            return None;
+    fn swap_remove(&mut self, row: usize) -> ComponentTicks {
+        ComponentTicks {
+            added: self.added.swap_remove(row),
+            changed: Tick::new(self.changed.swap_remove(row).into_inner()),
         }
+    }
 
-        let index = self.sparse[id].take()?;
-
-        let value = self.dense.swap_remove(index);
-        let _ = self.indices.swap_remove(index);
+    fn clear(&mut self) {
+        self.added.clear();
+        self.changed.clear();
+    }
 
-        if index < self.dense.len() {
-            let swapped = self.indices[index];
-            self.sparse[swapped] = Some(index);
+    fn check(&mut self, this_run: Tick) {
+        for added in &mut self.added {
+            added.check(this_run);
+        }
+        for changed in &mut self.changed {
+            let mut tick = Tick::new(*changed.get_mut());
+            tick.check(this_run);
+            *changed.get_mut() = tick.get();
         }
-
-        Some(value)
     }
+}
 
-    pub fn get(&self, id: usize) -> Option<&Data> {
-        if id >= self.sparse.len() {
-            return None;
-        }
+/// Cached transitions to the archetypes reached by adding or removing a single component type,
+/// or a whole [`Bundle`] keyed by the bundle's type.
+#[derive(Default)]
+struct ArchetypeEdges {
+    add: HashMap<TypeId, ArchetypeId>,
+    remove: HashMap<TypeId, ArchetypeId>,
+    add_bundle: HashMap<TypeId, ArchetypeId>,
+    remove_bundle: HashMap<TypeId, ArchetypeId>,
+}
 
-        let dense_index = self.sparse[id]?;
-        self.dense.get(dense_index)
+impl ArchetypeEdges {
+    fn remove_target(&mut self, id: ArchetypeId) {
+        self.add.retain(|_, dst| *dst != id);
+        self.remove.retain(|_, dst| *dst != id);
+        self.add_bundle.retain(|_, dst| *dst != id);
+        self.remove_bundle.retain(|_, dst| *dst != id);
     }
+}
 
-    pub fn get_mut(&mut self, id: usize) -> Option<&mut Data> {
-        if id >= self.sparse.len() {
-            return None;
+impl Archetype {
+    pub fn new(id: ArchetypeId, infos: impl IntoIterator<Item = ComponentInfo>) -> Self {
+        let mut columns = Vec::new();
+        let mut ticks = Vec::new();
+        let mut type_ids = Vec::new();
+        for info in infos {
+            type_ids.push(info.type_id());
+            columns.push(Lock::new(Column::new(info)));
+            ticks.push(ColumnTicks::default());
         }
 
-        let dense_index = self.sparse[id]?;
-        self.dense.get_mut(dense_index)
+        Self {
+            id,
+            signature: signature(type_ids.iter().copied()),
+            columns,
+            ticks,
+            type_ids,
+            entities: Vec::new(),
+            edges: ArchetypeEdges::default(),
+        }
     }
 
-    pub fn contains(&self, id: usize) -> bool {
-        self.sparse.get(id).copied().flatten().is_some()
+    pub fn id(&self) -> ArchetypeId {
+        self.id
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &Data> + '_ {
-        self.dense.iter()
+    /// The archetype's component types, sorted. Two archetypes never share a signature.
+    pub fn signature(&self) -> &[TypeId] {
+        &self.signature
     }
 
-    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Data> + '_ {
-        self.dense.iter_mut()
+    pub fn column_index(&self, type_id: TypeId) -> Option<usize> {
+        self.type_ids.iter().position(|&id| id == type_id)
     }
 
-    pub fn clear(&mut self) {
-        self.dense.clear();
-        self.sparse.clear();
-        self.indices.clear();
+    pub fn column(&self, type_id: TypeId) -> Option<&Lock<Column>> {
+        Some(&self.columns[self.column_index(type_id)?])
     }
 
-    pub fn len(&self) -> usize {
-        self.dense.len()
+    pub fn column_mut(&mut self, type_id: TypeId) -> Option<&mut Column> {
+        let index = self.column_index(type_id)?;
+        Some(self.columns[index].get_mut())
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.dense.is_empty()
+    pub fn component_infos(&self) -> impl Iterator<Item = ComponentInfo> + '_ {
+        self.columns.iter().map(|column| *column.read().info())
     }
-}
 
-#[derive(Default)]
-pub struct Archetype {
-    columns: Vec<Lock<SparseSet>>,
-    type_ids: Vec<std::any::TypeId>,
-}
-
-impl Archetype {
-    pub fn new() -> Self {
-        Self::default()
+    pub fn get<T: Component>(&self, row: usize) -> Option<MapRead<'_, T>> {
+        let column = self.column(TypeId::of::<T>())?;
+        column.filter_map_read(|column| column.get::<T>(row))
     }
 
-    pub fn insert(&mut self, id: usize, data: Data) {
-        let type_id = data.type_id();
-
-        let index = self
-            .type_ids
-            .iter()
-            .position(|&id| id == type_id)
-            .unwrap_or_else(|| {
-                self.type_ids.push(type_id);
-                self.columns.push(Lock::new(SparseSet::new()));
-                self.columns.len() - 1
-            });
+    /// Borrows the component mutably, marking it as changed at `change_tick`.
+    pub fn get_mut<T: Component>(&self, row: usize, change_tick: Tick) -> Option<MapWrite<'_, T>> {
+        let index = self.column_index(TypeId::of::<T>())?;
+        let component = self.columns[index].filter_map_write(|column| column.get_mut::<T>(row))?;
+        self.ticks[index].set_changed(row, change_tick);
+        Some(component)
+    }
 
-        self.columns[index].write().insert(id, data)
+    /// Marks the component in column `index` at `row` as changed at `tick`.
+    pub(crate) fn set_changed(&self, index: usize, row: usize, tick: Tick) {
+        self.ticks[index].set_changed(row, tick);
     }
 
-    pub fn remove(&mut self, id: usize) -> Vec<Data> {
-        self.columns
-            .iter_mut()
-            .filter_map(|column| column.write().remove(id))
-            .collect()
+    pub fn ticks(&self, type_id: TypeId, row: usize) -> Option<ComponentTicks> {
+        self.ticks[self.column_index(type_id)?].get(row)
     }
 
-    pub fn get<T: Component>(&self, id: usize) -> Option<MapRead<'_, Data>> {
-        let type_id = std::any::TypeId::of::<T>();
+    pub fn has_component<T: Component>(&self) -> bool {
+        self.has_component_by_type_id(TypeId::of::<T>())
+    }
 
-        let index = self.type_ids.iter().position(|&id| id == type_id)?;
+    pub fn has_component_by_type_id(&self, type_id: TypeId) -> bool {
+        self.signature.binary_search(&type_id).is_ok()
+    }
 
-        if self.columns[index].read().contains(id) {
-            Some(self.columns[index].map_read(|column| column.get(id).unwrap()))
-        } else {
-            None
-        }
+    /// The archetype's component types, in column order.
+    pub fn type_ids(&self) -> &[TypeId] {
+        &self.type_ids
     }
 
-    pub fn get_mut<T: Component>(&mut self, id: usize) -> Option<MapWrite<'_, Data>> {
-        let type_id = std::any::TypeId::of::<T>();
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
 
-        let index = self.type_ids.iter().position(|&id| id == type_id)?;
+    pub fn contains_entity(&self, entity: Entity) -> bool {
+        self.entities.contains(&entity)
+    }
 
-        if self.columns[index].read().contains(id) {
-            Some(self.columns[index].map_write(|column| column.get_mut(id).unwrap()))
-        } else {
-            None
+    pub fn clear(&mut self) {
+        for column in &mut self.columns {
+            column.get_mut().clear();
         }
+        for ticks in &mut self.ticks {
+            ticks.clear();
+        }
+        self.entities.clear();
     }
 
-    pub fn has_component<T: Component>(&self, id: usize) -> bool {
-        let type_id = std::any::TypeId::of::<T>();
-
-        self.type_ids
-            .iter()
-            .position(|&id| id == type_id)
-            .map(|index| self.columns[index].read().contains(id))
-            .unwrap_or(false)
+    pub fn len(&self) -> usize {
+        self.entities.len()
     }
 
-    pub fn has_component_by_type_id(&self, type_id: std::any::TypeId) -> bool {
-        self.type_ids.contains(&type_id)
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
     }
 
-    pub fn contains_entity(&self, id: usize) -> bool {
-        self.columns.iter().any(|column| column.read().contains(id))
+    pub fn exclusively_contains_types(&self, type_ids: &[TypeId]) -> bool {
+        *self.signature == *signature(type_ids.iter().copied())
     }
 
-    pub fn clear(&mut self) {
-        self.columns.clear();
-        self.type_ids.clear();
+    /// Moves the value at `ptr` into a new row at the end of the column at `index`.
+    ///
+    /// # Safety
+    /// Same as [`Column::push_raw`].
+    unsafe fn push_raw(&mut self, index: usize, ptr: *mut u8, ticks: ComponentTicks) {
+        self.columns[index].get_mut().push_raw(ptr);
+        self.ticks[index].push(ticks);
     }
 
-    pub fn len(&self) -> usize {
-        self.columns.iter().map(|column| column.read().len()).sum()
+    /// Moves the value at `ptr` into `row` of the column for `type_id`, replacing and marking as
+    /// changed any value already there, or adding it at the end if the column is shorter.
+    ///
+    /// # Safety
+    /// Same as [`Column::push_raw`].
+    unsafe fn write_raw(&mut self, type_id: TypeId, row: usize, ptr: *mut u8, change_tick: Tick) {
+        let index = self.column_index(type_id).unwrap();
+        let column = self.columns[index].get_mut();
+        if row < column.len() {
+            column.replace_raw(row, ptr);
+            self.ticks[index].set_changed(row, change_tick);
+        } else {
+            self.push_raw(index, ptr, ComponentTicks::new(change_tick));
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.columns.iter().all(|column| column.read().is_empty())
+    /// Removes `row` from the row table and every column, moving the last row into its place.
+    ///
+    /// `take` is called with each component's info, a pointer to its value and its ticks, and
+    /// must either move the value out or drop it. Returns the entity that was moved into `row`,
+    /// if any.
+    fn swap_remove_row(
+        &mut self,
+        row: usize,
+        mut take: impl FnMut(&ComponentInfo, *mut u8, ComponentTicks),
+    ) -> Option<Entity> {
+        for (column, ticks) in self.columns.iter_mut().zip(&mut self.ticks) {
+            let column = column.get_mut();
+            let ticks = ticks.swap_remove(row);
+            unsafe {
+                take(column.info(), column.get_ptr_unchecked(row), ticks);
+                column.swap_remove_forget(row);
+            }
+        }
+
+        self.entities.swap_remove(row);
+        self.entities.get(row).copied()
     }
+}
 
-    pub fn exclusively_contains_types(&self, type_ids: &[std::any::TypeId]) -> bool {
-        type_ids
-            .iter()
-            .all(|type_id| self.type_ids.contains(type_id))
-            && self.type_ids.len() == type_ids.len()
+fn bundle_infos<B: Bundle>() -> Vec<ComponentInfo> {
+    let mut infos = Vec::new();
+    B::component_infos(&mut infos);
+    assert_eq!(
+        signature(infos.iter().map(|info| info.type_id())).len(),
+        infos.len(),
+        "{} contains the same component type more than once",
+        std::any::type_name::<B>()
+    );
+    infos
+}
+
+fn signature(type_ids: impl IntoIterator<Item = TypeId>) -> Box<[TypeId]> {
+    let mut signature = type_ids.into_iter().collect::<Vec<_>>();
+    signature.sort_unstable();
+    signature.dedup();
+    signature.into_boxed_slice()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ArchetypeId(usize);
+
+impl ArchetypeId {
+    /// The archetype with no components, which always exists.
+    pub const EMPTY: Self = Self(0);
+
+    pub fn index(self) -> usize {
+        self.0
     }
 }
 
+/// Where an entity's components live: its archetype, and its row in that archetype's columns.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct ArchetypeId(usize);
+pub struct EntityLocation {
+    pub archetype_id: ArchetypeId,
+    pub row: usize,
+}
+
+/// The location of whichever generation of an entity id is currently stored.
+#[derive(Clone, Copy, Debug)]
+struct EntitySlot {
+    generation: u32,
+    location: EntityLocation,
+}
+
+/// What [`Storage`] does with an archetype once its last entity leaves it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyArchetypePolicy {
+    /// Keep empty archetypes and their cached edges around, so an [`ArchetypeId`] always refers
+    /// to the same set of component types and entities moving back and forth never reallocate.
+    #[default]
+    Keep,
+    /// Delete archetypes as soon as they become empty. Their ids are never reused.
+    Remove,
+}
 
-#[derive(Default)]
 pub struct Storage {
-    next_archetype_id: usize,
-    archetypes: HashMap<ArchetypeId, Archetype>,
-    entity_archetype: HashMap<Entity, ArchetypeId>,
+    archetypes: Vec<Option<Archetype>>,
+    archetype_index: HashMap<Box<[TypeId]>, ArchetypeId>,
+    entity_locations: Vec<Option<EntitySlot>>,
+    empty_archetype_policy: EmptyArchetypePolicy,
+    change_tick: AtomicU32,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        let empty = Archetype::new(ArchetypeId::EMPTY, []);
+        let mut archetype_index = HashMap::new();
+        archetype_index.insert(empty.signature.clone(), ArchetypeId::EMPTY);
+
+        Self {
+            archetypes: vec![Some(empty)],
+            archetype_index,
+            entity_locations: Vec::new(),
+            empty_archetype_policy: EmptyArchetypePolicy::default(),
+            change_tick: AtomicU32::new(1),
+        }
+    }
 }
 
 impl Storage {
@@ -264,240 +412,515 @@ impl Storage {
         Self::default()
     }
 
-    pub fn insert_component<T: Component>(&mut self, entity: Entity, new_data: T) {
-        let old_archetype_id = self.entity_archetype.remove(&entity);
-        let old_archetype = old_archetype_id.and_then(|id| self.archetypes.get_mut(&id));
-
-        let data = if let Some(old_archetype) = old_archetype {
-            let mut data = old_archetype.remove(entity.id() as usize);
-            data.push(Data::new(new_data));
-            data
-        } else {
-            vec![Data::new(new_data)]
-        };
+    pub fn with_empty_archetype_policy(policy: EmptyArchetypePolicy) -> Self {
+        Self {
+            empty_archetype_policy: policy,
+            ..Self::default()
+        }
+    }
 
-        let existing = self
-            .archetypes
-            .iter_mut()
-            .find(|(_, archetype)| {
-                archetype.exclusively_contains_types(
-                    &data.iter().map(|data| data.type_id()).collect::<Vec<_>>(),
-                )
-            })
-            .map(|(id, archetype)| (archetype, *id));
+    pub fn empty_archetype_policy(&self) -> EmptyArchetypePolicy {
+        self.empty_archetype_policy
+    }
 
-        let (archetype, archetype_id) = if let Some((archetype, existing_archetype_id)) = existing {
-            (archetype, existing_archetype_id)
-        } else {
-            let archetype_id = ArchetypeId(self.next_archetype_id);
-            self.next_archetype_id += 1;
+    /// Changes the policy. Switching to [`EmptyArchetypePolicy::Remove`] also removes every
+    /// archetype that is currently empty.
+    pub fn set_empty_archetype_policy(&mut self, policy: EmptyArchetypePolicy) {
+        self.empty_archetype_policy = policy;
+        if policy == EmptyArchetypePolicy::Remove {
+            self.remove_empty_archetypes();
+        }
+    }
 
-            let archetype = Archetype::new();
+    /// Removes every empty archetype except [`ArchetypeId::EMPTY`], regardless of the policy.
+    pub fn remove_empty_archetypes(&mut self) {
+        let empty = self
+            .archetypes()
+            .filter(|archetype| archetype.is_empty() && archetype.id != ArchetypeId::EMPTY)
+            .map(|archetype| archetype.id)
+            .collect::<Vec<_>>();
 
-            self.archetypes.insert(archetype_id, archetype);
+        for id in empty {
+            self.remove_archetype(id);
+        }
+    }
 
-            let archetype = self.archetypes.get_mut(&archetype_id).unwrap();
+    /// The current change tick, which components added or changed right now are marked with.
+    pub fn change_tick(&self) -> Tick {
+        Tick::new(self.change_tick.load(Ordering::Acquire))
+    }
 
-            (archetype, archetype_id)
-        };
+    /// Advances the change tick, returning the tick it was at before. Changes made after this
+    /// call are marked with a later tick than the one returned.
+    pub fn increment_change_tick(&self) -> Tick {
+        Tick::new(self.change_tick.fetch_add(1, Ordering::AcqRel))
+    }
 
-        for data in data {
-            archetype.insert(entity.id() as usize, data);
+    /// Clamps every component tick older than [`Tick::MAX_AGE`], relative to `this_run`.
+    pub fn check_change_ticks(&mut self, this_run: Tick) {
+        for archetype in self.archetypes.iter_mut().flatten() {
+            for ticks in &mut archetype.ticks {
+                ticks.check(this_run);
+            }
         }
+    }
 
-        self.entity_archetype.insert(entity, archetype_id);
-
-        if let Some(old_archetype_id) = old_archetype_id {
-            if self.archetypes[&old_archetype_id].is_empty() {
-                self.archetypes.remove(&old_archetype_id);
-            }
+    /// Inserts a component, replacing the entity's `T` if it has one. Only for entities that are
+    /// alive, which the storage can't check: go through
+    /// [`World::insert_component`](crate::world::World::insert_component) instead.
+    pub(crate) fn insert_component<T: Component>(&mut self, entity: Entity, new_data: T) {
+        let mut new_data = std::mem::ManuallyDrop::new(new_data);
+        let new_data = (&mut *new_data as *mut T).cast::<u8>();
+        let info = ComponentInfo::of::<T>();
+        let change_tick = self.change_tick();
+
+        let mut location = self.location_or_empty(entity);
+        let archetype = self.archetypes[location.archetype_id.0].as_ref().unwrap();
+
+        // if the entity already has a `T`, it stays where it is
+        if !archetype.has_component_by_type_id(info.type_id()) {
+            let archetype_id = self.add_edge(location.archetype_id, info);
+            location = self.move_entity(entity, location, archetype_id, |info, ptr| unsafe {
+                info.drop_in_place(ptr)
+            });
         }
+
+        let archetype = self.archetypes[location.archetype_id.0].as_mut().unwrap();
+        unsafe { archetype.write_raw(info.type_id(), location.row, new_data, change_tick) };
     }
 
     pub fn remove_component<T: Component>(&mut self, entity: Entity) -> Option<T> {
-        // remove the entity from its current archetype
+        let location = self.location_of(entity)?;
+        let archetype_id = self.remove_edge(location.archetype_id, TypeId::of::<T>())?;
+
+        let mut component = None;
+        self.move_entity(entity, location, archetype_id, |_, ptr| unsafe {
+            component = Some(ptr.cast::<T>().read())
+        });
 
-        let old_archetype_id = self.entity_archetype.get(&entity)?;
+        component
+    }
 
-        let old_archetype = self.archetypes.get_mut(old_archetype_id)?;
+    /// Inserts every component in `bundle`, replacing any the entity already has. Only for
+    /// entities that are alive, like [`insert_component`](Self::insert_component).
+    pub(crate) fn insert_bundle<B: Bundle>(&mut self, entity: Entity, bundle: B) {
+        let location = self.location_or_empty(entity);
+        let archetype_id = self.add_bundle_edge::<B>(location.archetype_id);
+
+        let location = if archetype_id == location.archetype_id {
+            location
+        } else {
+            // the destination has every type the source has, so nothing gets dropped here
+            self.move_entity(entity, location, archetype_id, |info, ptr| unsafe {
+                info.drop_in_place(ptr)
+            })
+        };
+
+        let change_tick = self.change_tick();
+        let archetype = self.archetypes[location.archetype_id.0].as_mut().unwrap();
+        bundle.take_components(&mut |info, ptr| unsafe {
+            archetype.write_raw(info.type_id(), location.row, ptr, change_tick)
+        });
+    }
 
-        let mut data = old_archetype.remove(entity.id() as usize);
+    /// Removes and returns every component in `B`, or does nothing and returns `None` if the
+    /// entity is missing any of them.
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: Entity) -> Option<B> {
+        let location = self.location_of(entity)?;
+        let archetype_id = self.remove_bundle_edge::<B>(location.archetype_id)?;
 
-        if old_archetype.is_empty() {
-            self.archetypes.remove(old_archetype_id);
+        let mut removed = Vec::new();
+        if archetype_id != location.archetype_id {
+            self.move_entity(entity, location, archetype_id, |info, ptr| unsafe {
+                removed.push(Data::from_raw(info, ptr))
+            });
         }
 
-        self.entity_archetype.remove(&entity);
+        Some(B::from_components(&mut |type_id| {
+            let index = removed
+                .iter()
+                .position(|data| data.type_id() == type_id)
+                .unwrap();
+            removed.swap_remove(index).into_data()
+        }))
+    }
 
-        // remove the component from the data
-        let component = data.remove(data.iter().position(|data| data.is::<T>())?);
+    pub fn remove_entity(&mut self, entity: Entity) -> Option<Vec<Data>> {
+        let location = self.take_location(entity)?;
 
-        // find the new archetype for the entity
-        let new_archetype = self
-            .archetypes
-            .iter_mut()
-            .find(|(_, archetype)| {
-                archetype.exclusively_contains_types(
-                    &data.iter().map(|data| data.type_id()).collect::<Vec<_>>(),
-                )
-            })
-            .map(|(id, archetype)| (archetype, *id));
+        let mut data = Vec::new();
+        let archetype = self.archetypes[location.archetype_id.0].as_mut().unwrap();
+        let swapped = archetype.swap_remove_row(location.row, |info, ptr, _| unsafe {
+            data.push(Data::from_raw(info, ptr))
+        });
 
-        let (new_archetype, new_archetype_id) =
-            if let Some((archetype, existing_archetype_id)) = new_archetype {
-                (archetype, existing_archetype_id)
-            } else {
-                let archetype_id = ArchetypeId(self.next_archetype_id);
-                self.next_archetype_id += 1;
+        if let Some(swapped) = swapped {
+            self.set_row(swapped, location.row);
+        }
 
-                let archetype = Archetype::new();
+        self.on_archetype_emptied(location.archetype_id);
 
-                self.archetypes.insert(archetype_id, archetype);
+        Some(data)
+    }
+
+    pub fn get_data(&self, entity: Entity, type_id: TypeId) -> Option<MapRead<'_, dyn Component>> {
+        let (archetype, row) = self.archetype_row(entity)?;
+        let column = archetype.column(type_id)?;
+        column.filter_map_read(|column| column.get_dyn(row))
+    }
 
-                let archetype = self.archetypes.get_mut(&archetype_id).unwrap();
+    /// Borrows the component mutably, marking it as changed.
+    pub fn get_data_mut(
+        &self,
+        entity: Entity,
+        type_id: TypeId,
+    ) -> Option<MapWrite<'_, dyn Component>> {
+        let (archetype, row) = self.archetype_row(entity)?;
+        let index = archetype.column_index(type_id)?;
+        let data = archetype.columns[index].filter_map_write(|column| column.get_dyn_mut(row))?;
+        archetype.ticks[index].set_changed(row, self.change_tick());
+        Some(data)
+    }
 
-                (archetype, archetype_id)
-            };
+    pub fn get_component<T: Component>(&self, entity: Entity) -> Option<MapRead<'_, T>> {
+        let (archetype, row) = self.archetype_row(entity)?;
+        archetype.get::<T>(row)
+    }
 
-        for data in data {
-            new_archetype.insert(entity.id() as usize, data);
-        }
+    /// Borrows the component mutably, marking it as changed at the current change tick.
+    pub fn get_component_mut<T: Component>(&self, entity: Entity) -> Option<MapWrite<'_, T>> {
+        self.get_component_mut_at(entity, self.change_tick())
+    }
 
-        self.entity_archetype.insert(entity, new_archetype_id);
+    /// Borrows the component mutably, marking it as changed at `change_tick`.
+    pub fn get_component_mut_at<T: Component>(
+        &self,
+        entity: Entity,
+        change_tick: Tick,
+    ) -> Option<MapWrite<'_, T>> {
+        let (archetype, row) = self.archetype_row(entity)?;
+        archetype.get_mut::<T>(row, change_tick)
+    }
 
-        Some(*component.into_data().as_any_box().downcast::<T>().unwrap())
+    pub fn component_ticks<T: Component>(&self, entity: Entity) -> Option<ComponentTicks> {
+        self.component_ticks_by_type_id(entity, TypeId::of::<T>())
     }
 
-    pub fn remove_entity(&mut self, entity: Entity) -> Option<Vec<Data>> {
-        let archetype_id = self.entity_archetype.get(&entity)?;
+    pub fn component_ticks_by_type_id(
+        &self,
+        entity: Entity,
+        type_id: TypeId,
+    ) -> Option<ComponentTicks> {
+        let (archetype, row) = self.archetype_row(entity)?;
+        archetype.ticks(type_id, row)
+    }
 
-        let archetype = self.archetypes.get_mut(archetype_id)?;
+    pub fn has_component<T: Component>(&self, entity: Entity) -> bool {
+        self.has_component_by_type_id(entity, TypeId::of::<T>())
+    }
 
-        let data = archetype.remove(entity.id() as usize);
+    pub fn has_component_by_type_id(&self, entity: Entity, type_id: TypeId) -> bool {
+        self.archetype_of(entity)
+            .map(|archetype| archetype.has_component_by_type_id(type_id))
+            .unwrap_or(false)
+    }
 
-        if archetype.is_empty() {
-            self.archetypes.remove(archetype_id);
-        }
+    pub fn entity_iter(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entity_locations
+            .iter()
+            .enumerate()
+            .filter_map(|(id, slot)| Some(Entity::new(id as u32, slot.as_ref()?.generation)))
+    }
 
-        self.entity_archetype.remove(&entity);
+    pub fn archetypes(&self) -> impl Iterator<Item = &Archetype> + '_ {
+        self.archetypes.iter().flatten()
+    }
 
-        Some(data)
+    pub fn archetype(&self, id: ArchetypeId) -> Option<&Archetype> {
+        self.archetypes.get(id.0)?.as_ref()
+    }
+
+    /// Looks up the archetype with exactly the given component types.
+    pub fn archetype_id_of(&self, type_ids: &[TypeId]) -> Option<ArchetypeId> {
+        self.archetype_index
+            .get(&signature(type_ids.iter().copied()))
+            .copied()
     }
 
-    pub fn get_data(&self, entity: Entity, type_id: TypeId) -> Option<MapRead<'_, Data>> {
-        let archetype_id = self.entity_archetype.get(&entity)?;
+    pub fn archetype_of(&self, entity: Entity) -> Option<&Archetype> {
+        self.archetype(self.location_of(entity)?.archetype_id)
+    }
 
-        let archetype = self.archetypes.get(archetype_id)?;
+    /// Returns where the entity's components are stored. Stale handles, whose id has since been
+    /// reused by a newer generation, have no location.
+    pub fn location_of(&self, entity: Entity) -> Option<EntityLocation> {
+        let slot = self.entity_locations.get(entity.id() as usize)?.as_ref()?;
+        (slot.generation == entity.generation()).then_some(slot.location)
+    }
 
-        let column_index = archetype.type_ids.iter().position(|id| *id == type_id)?;
+    fn set_location(&mut self, entity: Entity, location: EntityLocation) {
+        let id = entity.id() as usize;
+        if id >= self.entity_locations.len() {
+            self.entity_locations.resize(id + 1, None);
+        }
 
-        if archetype.columns[column_index]
-            .read()
-            .contains(entity.id() as usize)
-        {
-            Some(
-                archetype.columns[column_index]
-                    .map_read(|column| column.get(entity.id() as usize).unwrap()),
-            )
-        } else {
-            None
+        if let Some(slot) = &self.entity_locations[id] {
+            assert_eq!(
+                slot.generation,
+                entity.generation(),
+                "Storage: {:?} is stale, its id is in use by generation {}",
+                entity,
+                slot.generation
+            );
         }
+
+        self.entity_locations[id] = Some(EntitySlot {
+            generation: entity.generation(),
+            location,
+        });
     }
 
-    pub fn get_data_mut(&self, entity: Entity, type_id: TypeId) -> Option<MapWrite<'_, Data>> {
-        let archetype_id = self.entity_archetype.get(&entity)?;
+    fn take_location(&mut self, entity: Entity) -> Option<EntityLocation> {
+        let location = self.location_of(entity)?;
+        self.entity_locations[entity.id() as usize] = None;
+        Some(location)
+    }
 
-        let archetype = self.archetypes.get(archetype_id)?;
+    fn set_row(&mut self, entity: Entity, row: usize) {
+        let slot = self.entity_locations[entity.id() as usize]
+            .as_mut()
+            .unwrap();
+        debug_assert_eq!(slot.generation, entity.generation());
+        slot.location.row = row;
+    }
 
-        let column_index = archetype.type_ids.iter().position(|id| *id == type_id)?;
+    fn archetype_row(&self, entity: Entity) -> Option<(&Archetype, usize)> {
+        let location = self.location_of(entity)?;
+        Some((self.archetype(location.archetype_id)?, location.row))
+    }
 
-        if archetype.columns[column_index]
-            .read()
-            .contains(entity.id() as usize)
-        {
-            Some(
-                archetype.columns[column_index]
-                    .map_write(|column| column.get_mut(entity.id() as usize).unwrap()),
-            )
-        } else {
-            None
+    /// Returns the entity's location, first placing it in the empty archetype if it has none.
+    ///
+    /// Panics if `entity` is stale, before anything is changed.
+    fn location_or_empty(&mut self, entity: Entity) -> EntityLocation {
+        if let Some(location) = self.location_of(entity) {
+            return location;
+        }
+        if let Some(Some(slot)) = self.entity_locations.get(entity.id() as usize) {
+            panic!(
+                "Storage: {:?} is stale, its id is in use by generation {}",
+                entity, slot.generation
+            );
         }
+
+        let empty = self.archetypes[ArchetypeId::EMPTY.0].as_mut().unwrap();
+        empty.entities.push(entity);
+        let location = EntityLocation {
+            archetype_id: ArchetypeId::EMPTY,
+            row: empty.len() - 1,
+        };
+        self.set_location(entity, location);
+        location
     }
 
-    pub fn get_component<T: Component>(&self, entity: Entity) -> Option<MapRead<'_, T>> {
-        let archetype_id = self.entity_archetype.get(&entity)?;
+    fn find_or_create_archetype(&mut self, infos: Vec<ComponentInfo>) -> ArchetypeId {
+        let signature = signature(infos.iter().map(|info| info.type_id()));
 
-        let archetype = self.archetypes.get(archetype_id)?;
+        if let Some(id) = self.archetype_index.get(&signature) {
+            return *id;
+        }
 
-        let column_index = archetype
-            .type_ids
-            .iter()
-            .position(|id| *id == std::any::TypeId::of::<T>())?;
+        let id = ArchetypeId(self.archetypes.len());
+        self.archetypes.push(Some(Archetype::new(id, infos)));
+        self.archetype_index.insert(signature, id);
+        id
+    }
 
-        if archetype.columns[column_index]
-            .read()
-            .contains(entity.id() as usize)
-        {
-            Some(archetype.columns[column_index].map_read(|column| {
-                column
-                    .get(entity.id() as usize)
-                    .unwrap()
-                    .downcast_ref()
-                    .unwrap()
-            }))
-        } else {
-            None
+    /// Returns the archetype reached by adding `info`'s type to `src`, caching the edge.
+    fn add_edge(&mut self, src: ArchetypeId, info: ComponentInfo) -> ArchetypeId {
+        let archetype = self.archetypes[src.0].as_ref().unwrap();
+        if let Some(dst) = archetype.edges.add.get(&info.type_id()) {
+            return *dst;
         }
-    }
 
-    pub fn get_component_mut<T: Component>(&self, entity: Entity) -> Option<MapWrite<'_, T>> {
-        let archetype_id = self.entity_archetype.get(&entity)?;
+        let mut infos = archetype.component_infos().collect::<Vec<_>>();
+        infos.push(info);
+        let dst = self.find_or_create_archetype(infos);
 
-        let archetype = self.archetypes.get(archetype_id)?;
+        self.cache_edge(src, dst, info.type_id());
+        dst
+    }
 
-        let column_index = archetype
-            .type_ids
-            .iter()
-            .position(|id| *id == std::any::TypeId::of::<T>())?;
+    /// Returns the archetype reached by removing `type_id` from `src`, caching the edge.
+    /// Returns `None` if `src` doesn't have that type.
+    fn remove_edge(&mut self, src: ArchetypeId, type_id: TypeId) -> Option<ArchetypeId> {
+        let archetype = self.archetypes[src.0].as_ref().unwrap();
+        if let Some(dst) = archetype.edges.remove.get(&type_id) {
+            return Some(*dst);
+        }
 
-        if archetype.columns[column_index]
-            .read()
-            .contains(entity.id() as usize)
-        {
-            Some(archetype.columns[column_index].map_write(|column| {
-                column
-                    .get_mut(entity.id() as usize)
-                    .unwrap()
-                    .downcast_mut()
-                    .unwrap()
-            }))
-        } else {
-            None
+        if !archetype.has_component_by_type_id(type_id) {
+            return None;
         }
+
+        let infos = archetype
+            .component_infos()
+            .filter(|info| info.type_id() != type_id)
+            .collect::<Vec<_>>();
+        let dst = self.find_or_create_archetype(infos);
+
+        self.cache_edge(dst, src, type_id);
+        Some(dst)
     }
 
-    pub fn has_component<T: Component>(&self, entity: Entity) -> bool {
-        if let Some(archetype_id) = self.entity_archetype.get(&entity) {
-            if let Some(archetype) = self.archetypes.get(archetype_id) {
-                return archetype.has_component::<T>(entity.id() as usize);
+    /// Returns the archetype reached by adding every type in `B` to `src`, caching the edge.
+    ///
+    /// # Panics
+    /// Panics if `B` contains the same component type more than once.
+    fn add_bundle_edge<B: Bundle>(&mut self, src: ArchetypeId) -> ArchetypeId {
+        let archetype = self.archetypes[src.0].as_ref().unwrap();
+        if let Some(dst) = archetype.edges.add_bundle.get(&TypeId::of::<B>()) {
+            return *dst;
+        }
+
+        let bundle_infos = bundle_infos::<B>();
+        let mut infos = archetype.component_infos().collect::<Vec<_>>();
+        for info in bundle_infos {
+            if !archetype.has_component_by_type_id(info.type_id()) {
+                infos.push(info);
             }
         }
+        let dst = self.find_or_create_archetype(infos);
+
+        self.archetypes[src.0]
+            .as_mut()
+            .unwrap()
+            .edges
+            .add_bundle
+            .insert(TypeId::of::<B>(), dst);
+        dst
+    }
+
+    /// Returns the archetype reached by removing every type in `B` from `src`, caching the edge.
+    /// Returns `None` if `src` is missing any of them.
+    fn remove_bundle_edge<B: Bundle>(&mut self, src: ArchetypeId) -> Option<ArchetypeId> {
+        let archetype = self.archetypes[src.0].as_ref().unwrap();
+        if let Some(dst) = archetype.edges.remove_bundle.get(&TypeId::of::<B>()) {
+            return Some(*dst);
+        }
+
+        let bundle_infos = bundle_infos::<B>();
+        if !bundle_infos
+            .iter()
+            .all(|info| archetype.has_component_by_type_id(info.type_id()))
+        {
+            return None;
+        }
 
-        false
+        let infos = archetype
+            .component_infos()
+            .filter(|info| {
+                !bundle_infos
+                    .iter()
+                    .any(|removed| removed.type_id() == info.type_id())
+            })
+            .collect::<Vec<_>>();
+        let dst = self.find_or_create_archetype(infos);
+
+        self.archetypes[src.0]
+            .as_mut()
+            .unwrap()
+            .edges
+            .remove_bundle
+            .insert(TypeId::of::<B>(), dst);
+        Some(dst)
     }
 
-    pub fn has_component_by_type_id(&self, entity: Entity, type_id: TypeId) -> bool {
-        if let Some(archetype_id) = self.entity_archetype.get(&entity) {
-            if let Some(archetype) = self.archetypes.get(archetype_id) {
-                return archetype.has_component_by_type_id(type_id);
+    /// Records that adding `type_id` to `without` leads to `with`, and removing it leads back.
+    fn cache_edge(&mut self, without: ArchetypeId, with: ArchetypeId, type_id: TypeId) {
+        if let Some(archetype) = &mut self.archetypes[without.0] {
+            archetype.edges.add.insert(type_id, with);
+        }
+        if let Some(archetype) = &mut self.archetypes[with.0] {
+            archetype.edges.remove.insert(type_id, without);
+        }
+    }
+
+    /// Moves an entity's components into another archetype.
+    ///
+    /// Components the destination has no column for are passed to `take`, which must move
+    /// them out or drop them. Returns the entity's new location.
+    fn move_entity(
+        &mut self,
+        entity: Entity,
+        location: EntityLocation,
+        archetype_id: ArchetypeId,
+        mut take: impl FnMut(&ComponentInfo, *mut u8),
+    ) -> EntityLocation {
+        let (src, dst) = self.archetype_pair_mut(location.archetype_id, archetype_id);
+
+        let swapped = src.swap_remove_row(location.row, |info, ptr, ticks| {
+            match dst.column_index(info.type_id()) {
+                Some(index) => unsafe { dst.push_raw(index, ptr, ticks) },
+                None => take(info, ptr),
             }
+        });
+
+        dst.entities.push(entity);
+        let new_location = EntityLocation {
+            archetype_id,
+            row: dst.len() - 1,
+        };
+        self.set_location(entity, new_location);
+
+        if let Some(swapped) = swapped {
+            self.set_row(swapped, location.row);
         }
 
-        false
+        self.on_archetype_emptied(location.archetype_id);
+
+        new_location
     }
 
-    pub fn entity_iter(&self) -> impl Iterator<Item = Entity> + '_ {
-        self.entity_archetype.keys().copied()
+    fn archetype_pair_mut(
+        &mut self,
+        a: ArchetypeId,
+        b: ArchetypeId,
+    ) -> (&mut Archetype, &mut Archetype) {
+        assert_ne!(a, b, "Storage: can't borrow an archetype twice");
+        let (a, b) = if a < b {
+            let (left, right) = self.archetypes.split_at_mut(b.0);
+            (&mut left[a.0], &mut right[0])
+        } else {
+            let (left, right) = self.archetypes.split_at_mut(a.0);
+            (&mut right[0], &mut left[b.0])
+        };
+        (a.as_mut().unwrap(), b.as_mut().unwrap())
+    }
+
+    /// Applies the [`EmptyArchetypePolicy`] to an archetype an entity just left.
+    fn on_archetype_emptied(&mut self, id: ArchetypeId) {
+        if self.empty_archetype_policy == EmptyArchetypePolicy::Remove
+            && id != ArchetypeId::EMPTY
+            && self
+                .archetype(id)
+                .is_some_and(|archetype| archetype.is_empty())
+        {
+            self.remove_archetype(id);
+        }
+    }
+
+    fn remove_archetype(&mut self, id: ArchetypeId) {
+        let Some(archetype) = self.archetypes[id.0].take() else {
+            return;
+        };
+        debug_assert!(archetype.is_empty());
+
+        self.archetype_index.remove(&archetype.signature);
+
+        for other in self.archetypes.iter_mut().flatten() {
+            other.edges.remove_target(id);
+        }
     }
 }
 
@@ -544,6 +967,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_insert_stale_entity() {
+        let mut storage = Storage::new();
+        storage.insert_component(Entity::new(0, 1), Position { x: 0.0, y: 0.0 });
+
+        let stale = Entity::new(0, 0);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            storage.insert_component(stale, Velocity { dx: 1.0, dy: 1.0 })
+        }));
+        assert!(result.is_err());
+        // nothing was left behind in the empty archetype
+        let empty = storage.archetype(ArchetypeId::EMPTY).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(storage.entity_iter().count(), 1);
+    }
+
     #[test]
     fn test_remove_component() {
         let mut storage = Storage::new();
@@ -633,6 +1072,55 @@ mod tests {
         assert_eq!(storage.get_component::<Acceleration>(entity), None);
     }
 
+    #[test]
+    fn test_component_ticks() {
+        let mut storage = Storage::new();
+
+        let a = Entity::new(0, 0);
+        let b = Entity::new(1, 0);
+        storage.insert_component(a, Position { x: 0.0, y: 0.0 });
+        storage.insert_component(b, Position { x: 1.0, y: 1.0 });
+        let added = storage.change_tick();
+
+        storage.increment_change_tick();
+        let tick = storage.change_tick();
+        storage.get_component_mut::<Position>(a).unwrap().x = 2.0;
+
+        // the ticks move along with the component, even when another entity's row is swapped in
+        storage.increment_change_tick();
+        let tick2 = storage.change_tick();
+        storage.insert_component(a, Velocity { dx: 1.0, dy: 1.0 });
+        storage.insert_component(b, Velocity { dx: 0.0, dy: 0.0 });
+
+        assert_eq!(
+            storage.component_ticks::<Position>(a),
+            Some(ComponentTicks {
+                added,
+                changed: tick
+            })
+        );
+        assert_eq!(
+            storage.component_ticks::<Position>(b),
+            Some(ComponentTicks::new(added))
+        );
+        assert_eq!(
+            storage.component_ticks::<Velocity>(a),
+            Some(ComponentTicks::new(tick2))
+        );
+
+        // replacing a component changes it without re-adding it
+        storage.increment_change_tick();
+        let tick3 = storage.change_tick();
+        storage.insert_component(a, Position { x: 3.0, y: 3.0 });
+        assert_eq!(
+            storage.component_ticks::<Position>(a),
+            Some(ComponentTicks {
+                added,
+                changed: tick3
+            })
+        );
+    }
+
     #[test]
     fn test_contains() {
         let mut storage = Storage::new();
@@ -646,4 +1134,145 @@ mod tests {
         assert!(storage.has_component::<Velocity>(entity));
         assert!(!storage.has_component::<Acceleration>(entity));
     }
+
+    #[test]
+    fn test_many_entities() {
+        let mut storage = Storage::new();
+
+        let entities = (0..100).map(|i| Entity::new(i, 0)).collect::<Vec<_>>();
+
+        for (i, entity) in entities.iter().enumerate() {
+            storage.insert_component(
+                *entity,
+                Position {
+                    x: i as f32,
+                    y: 0.0,
+                },
+            );
+            if i % 2 == 0 {
+                storage.insert_component(
+                    *entity,
+                    Velocity {
+                        dx: i as f32,
+                        dy: 0.0,
+                    },
+                );
+            }
+        }
+
+        for entity in entities.iter().step_by(3) {
+            storage.remove_component::<Position>(*entity);
+        }
+
+        for (i, entity) in entities.iter().enumerate() {
+            let position = storage
+                .get_component::<Position>(*entity)
+                .map(|data| data.x);
+            let velocity = storage
+                .get_component::<Velocity>(*entity)
+                .map(|data| data.dx);
+
+            assert_eq!(position, (i % 3 != 0).then_some(i as f32));
+            assert_eq!(velocity, (i % 2 == 0).then_some(i as f32));
+        }
+    }
+
+    #[test]
+    fn test_drop_components() {
+        let arc = std::sync::Arc::new(());
+        let mut storage = Storage::new();
+
+        let entity1 = Entity::new(0, 0);
+        let entity2 = Entity::new(1, 0);
+
+        storage.insert_component(entity1, arc.clone());
+        storage.insert_component(entity2, arc.clone());
+        storage.insert_component(entity2, Position { x: 0.0, y: 0.0 });
+        assert_eq!(std::sync::Arc::strong_count(&arc), 3);
+
+        // replacing a component drops the old value
+        storage.insert_component(entity1, arc.clone());
+        assert_eq!(std::sync::Arc::strong_count(&arc), 3);
+
+        let removed = storage.remove_component::<std::sync::Arc<()>>(entity2);
+        assert!(removed.is_some());
+        drop(removed);
+        assert_eq!(std::sync::Arc::strong_count(&arc), 2);
+
+        drop(storage);
+        assert_eq!(std::sync::Arc::strong_count(&arc), 1);
+    }
+
+    #[test]
+    fn test_archetype_edges() {
+        let mut storage = Storage::new();
+
+        let entity1 = Entity::new(0, 0);
+        let entity2 = Entity::new(1, 0);
+
+        storage.insert_component(entity1, Position { x: 0.0, y: 0.0 });
+        storage.insert_component(entity1, Velocity { dx: 1.0, dy: 1.0 });
+        let with_both = storage.location_of(entity1).unwrap().archetype_id;
+
+        // the same types in a different order end up in the same archetype
+        storage.insert_component(entity2, Velocity { dx: 1.0, dy: 1.0 });
+        storage.insert_component(entity2, Position { x: 0.0, y: 0.0 });
+        assert_eq!(
+            storage.location_of(entity2).unwrap().archetype_id,
+            with_both
+        );
+
+        let archetype_count = storage.archetypes().count();
+        storage.remove_component::<Velocity>(entity1);
+        let with_position = storage.location_of(entity1).unwrap().archetype_id;
+        storage.insert_component(entity1, Velocity { dx: 1.0, dy: 1.0 });
+        assert_eq!(
+            storage.location_of(entity1).unwrap().archetype_id,
+            with_both
+        );
+        storage.remove_component::<Velocity>(entity1);
+        assert_eq!(
+            storage.location_of(entity1).unwrap().archetype_id,
+            with_position
+        );
+        assert_eq!(storage.archetypes().count(), archetype_count);
+
+        assert_eq!(
+            storage.archetype_id_of(&[TypeId::of::<Velocity>(), TypeId::of::<Position>()]),
+            Some(with_both)
+        );
+    }
+
+    #[test]
+    fn test_empty_archetype_policy() {
+        let mut storage = Storage::new();
+        assert_eq!(storage.empty_archetype_policy(), EmptyArchetypePolicy::Keep);
+
+        let entity = Entity::new(0, 0);
+        storage.insert_component(entity, Position { x: 0.0, y: 0.0 });
+        let with_position = storage.location_of(entity).unwrap().archetype_id;
+        storage.insert_component(entity, Velocity { dx: 1.0, dy: 1.0 });
+
+        // kept: the id still refers to the same archetype
+        let archetype = storage.archetype(with_position).unwrap();
+        assert!(archetype.is_empty());
+        assert_eq!(archetype.signature(), &[TypeId::of::<Position>()]);
+
+        storage.set_empty_archetype_policy(EmptyArchetypePolicy::Remove);
+        assert!(storage.archetype(with_position).is_none());
+        assert_eq!(storage.archetype_id_of(&[TypeId::of::<Position>()]), None);
+
+        // removed archetypes are recreated under a new id
+        storage.remove_component::<Velocity>(entity);
+        let recreated = storage.location_of(entity).unwrap().archetype_id;
+        assert_ne!(recreated, with_position);
+        assert_eq!(
+            storage.get_component::<Position>(entity).map(|data| data.x),
+            Some(0.0)
+        );
+
+        storage.remove_entity(entity);
+        assert!(storage.archetype(recreated).is_none());
+        assert!(storage.archetype(ArchetypeId::EMPTY).is_some());
+    }
 }
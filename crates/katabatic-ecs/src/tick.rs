@@ -0,0 +1,83 @@
+/// A point in a [`World`](crate::world::World)'s history, used to tell when components were
+/// added or changed.
+///
+/// The world's change tick goes up every time a system runs. Ticks wrap around, so they're
+/// only ever compared relative to a more recent tick, and ticks older than [`Tick::MAX_AGE`]
+/// are periodically [clamped](Tick::check) so they're never mistaken for recent ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Tick(u32);
+
+impl Tick {
+    /// How far behind the current tick a tick can fall before it gets clamped.
+    pub const MAX_AGE: u32 = u32::MAX / 4 * 3;
+
+    pub const fn new(tick: u32) -> Self {
+        Self(tick)
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0
+    }
+
+    /// Returns `true` if this tick is after `last_run`, and not after `this_run`.
+    pub fn is_newer_than(self, last_run: Tick, this_run: Tick) -> bool {
+        let age = this_run.0.wrapping_sub(self.0);
+        let last_run_age = this_run.0.wrapping_sub(last_run.0);
+        age < last_run_age
+    }
+
+    /// Moves the tick forward if it's more than [`MAX_AGE`](Self::MAX_AGE) older than
+    /// `this_run`, so it can't wrap around and look new again.
+    pub fn check(&mut self, this_run: Tick) {
+        if this_run.0.wrapping_sub(self.0) > Self::MAX_AGE {
+            self.0 = this_run.0.wrapping_sub(Self::MAX_AGE);
+        }
+    }
+}
+
+/// When a component was added to its entity, and when it was last changed.
+///
+/// Adding a component also counts as changing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComponentTicks {
+    pub added: Tick,
+    pub changed: Tick,
+}
+
+impl ComponentTicks {
+    pub fn new(tick: Tick) -> Self {
+        Self {
+            added: tick,
+            changed: tick,
+        }
+    }
+
+    pub fn is_added(&self, last_run: Tick, this_run: Tick) -> bool {
+        self.added.is_newer_than(last_run, this_run)
+    }
+
+    pub fn is_changed(&self, last_run: Tick, this_run: Tick) -> bool {
+        self.changed.is_newer_than(last_run, this_run)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_wrapping() {
+        let last_run = Tick::new(u32::MAX - 1);
+        let this_run = Tick::new(2);
+
+        assert!(Tick::new(u32::MAX).is_newer_than(last_run, this_run));
+        assert!(Tick::new(1).is_newer_than(last_run, this_run));
+        assert!(Tick::new(2).is_newer_than(last_run, this_run));
+        assert!(!Tick::new(u32::MAX - 1).is_newer_than(last_run, this_run));
+        assert!(!Tick::new(3).is_newer_than(last_run, this_run));
+
+        let mut old = Tick::new(5);
+        old.check(Tick::new(5 + Tick::MAX_AGE + 10));
+        assert_eq!(old, Tick::new(15));
+    }
+}
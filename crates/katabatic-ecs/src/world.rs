@@ -1,14 +1,38 @@
-use std::sync::atomic::{AtomicU32, Ordering};
+use std::any::TypeId;
 
-use katabatic_util::lock::{Lock, MapRead, MapWrite};
+use katabatic_util::{
+    error::{KError, KResult},
+    kensure, kerror,
+    lock::{Lock, MapRead, MapWrite},
+};
 
-use crate::{component::Component, entity::Entity, query::Query, storage::Storage};
+use crate::{
+    bundle::Bundle,
+    command::CommandQueue,
+    component::Component,
+    entity::{Entities, Entity},
+    lifecycle::{ComponentHooks, Hooks, RemovedComponentsLog},
+    query::{Query, QueryData, QueryFilter},
+    reflect::{TypeRegistration, TypeRegistry, Value},
+    resource::{Resource, Resources},
+    snapshot::Snapshot,
+    storage::Storage,
+    tick::{ComponentTicks, Tick},
+};
+
+/// How many ticks can pass between checks for component ticks that are about to wrap around.
+const CHECK_TICK_THRESHOLD: u32 = 1 << 29;
 
 #[derive(Default)]
 pub struct World {
-    next_entity: AtomicU32,
-    free_entities: Lock<Vec<Entity>>,
+    entities: Entities,
     storage: Storage,
+    resources: Resources,
+    deferred: Lock<CommandQueue>,
+    hooks: Hooks,
+    removed: RemovedComponentsLog,
+    last_change_tick: Tick,
+    last_check_tick: Tick,
 }
 
 impl World {
@@ -24,32 +48,195 @@ impl World {
         &mut self.storage
     }
 
+    pub fn entities(&self) -> &Entities {
+        &self.entities
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.entities.is_alive(entity)
+    }
+
     pub fn create_entity(&mut self) -> Entity {
-        if let Some(entity) = self.free_entities.write().pop() {
-            Entity::new(entity.id(), entity.generation() + 1)
-        } else {
-            let id = self.next_entity.fetch_add(1, Ordering::Relaxed);
-            Entity::new(id, 0)
+        self.entities.alloc()
+    }
+
+    /// Reserves an entity through a shared reference. It becomes alive at the next
+    /// [`flush_entities`](Self::flush_entities), or when any entity is created or destroyed.
+    pub fn reserve_entity(&self) -> Entity {
+        self.entities.reserve()
+    }
+
+    pub fn flush_entities(&mut self) {
+        self.entities.flush();
+    }
+
+    /// Queues commands to be applied at the next [`apply_deferred`](Self::apply_deferred).
+    pub fn defer(&self, queue: &mut CommandQueue) {
+        self.deferred.write().append(queue);
+    }
+
+    /// Applies every command queued with [`defer`](Self::defer).
+    pub fn apply_deferred(&mut self) -> KResult<()> {
+        let mut queue = std::mem::take(self.deferred.get_mut());
+        queue.apply(self)
+    }
+
+    /// Destroys the entity and drops all of its components, after running their `on_remove`
+    /// hooks.
+    ///
+    /// Fails if the entity was already destroyed, or if the handle is stale.
+    pub fn destroy_entity(&mut self, entity: Entity) -> KResult<()> {
+        if let Some(archetype) = self.storage.archetype_of(entity) {
+            for &type_id in archetype.type_ids() {
+                self.hooks.on_remove(self, entity, type_id);
+            }
         }
+
+        self.entities.free(entity)?;
+        for data in self.storage.remove_entity(entity).unwrap_or_default() {
+            self.removed.record(data.type_id(), entity);
+        }
+        Ok(())
+    }
+
+    /// Creates an entity with every component in `bundle`.
+    pub fn spawn<B: Bundle>(&mut self, bundle: B) -> Entity {
+        let entity = self.create_entity();
+        self.insert_bundle_with_hooks(entity, bundle);
+        entity
     }
 
-    pub fn destroy_entity(&mut self, entity: Entity) {
-        self.storage.remove_entity(entity);
-        self.free_entities.write().push(entity);
+    pub fn insert_bundle<B: Bundle>(&mut self, entity: Entity, bundle: B) -> KResult<()> {
+        self.ensure_alive(entity, "World::insert_bundle()")?;
+        self.insert_bundle_with_hooks(entity, bundle);
+        Ok(())
+    }
+
+    fn insert_bundle_with_hooks<B: Bundle>(&mut self, entity: Entity, bundle: B) {
+        if self.hooks.is_empty() {
+            self.storage.insert_bundle(entity, bundle);
+            return;
+        }
+
+        let inserted = bundle_type_ids::<B>()
+            .map(|type_id| {
+                let added = !self.storage.has_component_by_type_id(entity, type_id);
+                (type_id, added)
+            })
+            .collect::<Vec<_>>();
+        self.storage.insert_bundle(entity, bundle);
+        self.run_insert_hooks(entity, &inserted);
     }
 
-    pub fn insert_component<T: Component>(&mut self, entity: Entity, component: T) {
-        self.storage.insert_component(entity, component)
+    /// Removes every component in `B`, after running their `on_remove` hooks. Does nothing if
+    /// the entity is missing any of them.
+    pub fn remove_bundle<B: Bundle>(&mut self, entity: Entity) -> Option<B> {
+        let has_bundle = bundle_type_ids::<B>()
+            .all(|type_id| self.storage.has_component_by_type_id(entity, type_id));
+        if !has_bundle {
+            return None;
+        }
+
+        for type_id in bundle_type_ids::<B>() {
+            self.hooks.on_remove(self, entity, type_id);
+        }
+
+        let bundle = self.storage.remove_bundle::<B>(entity)?;
+        for type_id in bundle_type_ids::<B>() {
+            self.removed.record(type_id, entity);
+        }
+        Some(bundle)
     }
 
+    pub fn insert_component<T: Component>(&mut self, entity: Entity, component: T) -> KResult<()> {
+        self.ensure_alive(entity, "World::insert_component()")?;
+        let added = !self.storage.has_component::<T>(entity);
+        self.storage.insert_component(entity, component);
+        self.run_insert_hooks(entity, &[(TypeId::of::<T>(), added)]);
+        Ok(())
+    }
+
+    /// Removes the component, after running its `on_remove` hooks.
     pub fn remove_component<T: Component>(&mut self, entity: Entity) -> Option<T> {
-        self.storage.remove_component::<T>(entity)
+        if !self.storage.has_component::<T>(entity) {
+            return None;
+        }
+
+        self.hooks.on_remove(self, entity, TypeId::of::<T>());
+        let component = self.storage.remove_component::<T>(entity)?;
+        self.removed.record(TypeId::of::<T>(), entity);
+        Some(component)
+    }
+
+    /// Runs the `on_add` hooks of the newly added types, then the `on_insert` hooks of all of
+    /// them.
+    fn run_insert_hooks(&self, entity: Entity, inserted: &[(TypeId, bool)]) {
+        for &(type_id, added) in inserted {
+            if added {
+                self.hooks.on_add(self, entity, type_id);
+            }
+        }
+        for &(type_id, _) in inserted {
+            self.hooks.on_insert(self, entity, type_id);
+        }
+    }
+
+    /// Returns the hooks for `T`, which can be used to register new ones.
+    ///
+    /// Hooks run for changes made through the world, but not for ones made directly through
+    /// [`storage_mut`](Self::storage_mut).
+    pub fn register_component_hooks<T: Component>(&mut self) -> &mut ComponentHooks {
+        self.hooks.register(TypeId::of::<T>())
+    }
+
+    pub(crate) fn removed_components(&self) -> &RemovedComponentsLog {
+        &self.removed
+    }
+
+    /// The current change tick, which components added or changed right now are marked with.
+    pub fn change_tick(&self) -> Tick {
+        self.storage.change_tick()
+    }
+
+    /// Advances the change tick, returning the tick it was at before. Called every time a
+    /// system runs, which uses the returned tick for the changes it makes.
+    pub fn increment_change_tick(&self) -> Tick {
+        self.storage.increment_change_tick()
+    }
+
+    /// The change tick at the last [`clear_trackers`](Self::clear_trackers). Queries made
+    /// directly on the world detect changes made since then.
+    pub fn last_change_tick(&self) -> Tick {
+        self.last_change_tick
+    }
+
+    /// Marks the end of a frame, so that changes made so far are no longer detected by queries
+    /// made directly on the world. Also drops removals older than the previous frame from
+    /// [`RemovedComponents`](crate::lifecycle::RemovedComponents).
+    pub fn clear_trackers(&mut self) {
+        self.last_change_tick = self.increment_change_tick();
+        self.removed.update();
+
+        if self
+            .last_change_tick
+            .get()
+            .wrapping_sub(self.last_check_tick.get())
+            >= CHECK_TICK_THRESHOLD
+        {
+            self.storage.check_change_ticks(self.last_change_tick);
+            self.last_check_tick = self.last_change_tick;
+        }
+    }
+
+    pub fn component_ticks<T: Component>(&self, entity: Entity) -> Option<ComponentTicks> {
+        self.storage.component_ticks::<T>(entity)
     }
 
     pub fn get_component<T: Component>(&self, entity: Entity) -> Option<MapRead<'_, T>> {
         self.storage.get_component::<T>(entity)
     }
 
+    /// Borrows the component mutably, marking it as changed.
     pub fn get_component_mut<T: Component>(&self, entity: Entity) -> Option<MapWrite<'_, T>> {
         self.storage.get_component_mut::<T>(entity)
     }
@@ -58,7 +245,247 @@ impl World {
         self.storage.has_component::<T>(entity)
     }
 
-    pub fn query<T: Component>(&self) -> Query<T> {
+    /// Reads a reflected component field by path, e.g. `Transform.translation.x`.
+    ///
+    /// The first segment of the path names the component type, by its full or short name in the
+    /// world's [`TypeRegistry`] resource. The rest is a path of fields inside the component; with
+    /// no fields the whole component is read.
+    pub fn reflect_path(&self, entity: Entity, path: &str) -> KResult<Value> {
+        let (registration, field_path) =
+            self.resolve_reflect_path(entity, path, "World::reflect_path()")?;
+        let component = self
+            .storage
+            .get_data(entity, registration.type_id())
+            .ok_or_else(|| missing_component(entity, &registration, "World::reflect_path()"))?;
+        let reflect = registration.as_reflect(&*component).ok_or_else(|| {
+            kerror!(format!(
+                "World::reflect_path(): {} is not reflected",
+                registration.type_name()
+            ))
+        })?;
+        Ok(reflect.path(field_path)?.to_value())
+    }
+
+    /// Overwrites a reflected component field by path, marking the component as changed. See
+    /// [`reflect_path`](Self::reflect_path) for the path format.
+    pub fn set_reflect_path(&self, entity: Entity, path: &str, value: &Value) -> KResult<()> {
+        let (registration, field_path) =
+            self.resolve_reflect_path(entity, path, "World::set_reflect_path()")?;
+        let mut component = self
+            .storage
+            .get_data_mut(entity, registration.type_id())
+            .ok_or_else(|| missing_component(entity, &registration, "World::set_reflect_path()"))?;
+        let reflect = registration
+            .as_reflect_mut(&mut *component)
+            .ok_or_else(|| {
+                kerror!(format!(
+                    "World::set_reflect_path(): {} is not reflected",
+                    registration.type_name()
+                ))
+            })?;
+        reflect.path_mut(field_path)?.set_value(value)
+    }
+
+    fn resolve_reflect_path<'a>(
+        &self,
+        entity: Entity,
+        path: &'a str,
+        caller: &str,
+    ) -> KResult<(TypeRegistration, &'a str)> {
+        self.ensure_alive(entity, caller)?;
+        let (type_name, field_path) = path.split_once('.').unwrap_or((path, ""));
+        let registry = self.resource::<TypeRegistry>().ok_or_else(|| {
+            kerror!(format!(
+                "{}: the world has no TypeRegistry resource",
+                caller
+            ))
+        })?;
+        let registration = registry.get_with_name(type_name).cloned().ok_or_else(|| {
+            kerror!(format!(
+                "{}: no registered type is named {} (or the name is ambiguous)",
+                caller, type_name
+            ))
+        })?;
+        Ok((registration, field_path))
+    }
+
+    /// Copies every entity, including dead ones' generations and the free list, and every
+    /// component whose type is in the world's [`TypeRegistry`] resource, into a binary
+    /// [`Snapshot`].
+    ///
+    /// Components of unregistered types, resources, and entities reserved since the last flush
+    /// are left out.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::capture(self)
+    }
+
+    /// Replaces every entity and component with the contents of `snapshot`, so entity handles
+    /// from when the snapshot was made are valid again. Resources are left alone.
+    ///
+    /// Existing entities are destroyed as if with [`destroy_entity`](Self::destroy_entity), and
+    /// the snapshot's components are inserted as if with
+    /// [`insert_component`](Self::insert_component), so hooks run for both.
+    ///
+    /// Fails, leaving the world as it was, if the snapshot is corrupt, was made with another
+    /// snapshot version, or holds components that aren't registered or whose layout has changed.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> KResult<()> {
+        let decoded = {
+            let empty = TypeRegistry::new();
+            let registry = self.resource::<TypeRegistry>();
+            snapshot.decode(registry.as_deref().unwrap_or(&empty))?
+        };
+        let mut entities = Entities::new();
+        entities.restore(&decoded.generations, &decoded.free_entities)?;
+
+        self.flush_entities();
+        for entity in self.entities.iter().collect::<Vec<_>>() {
+            self.destroy_entity(entity)?;
+        }
+        self.entities = entities;
+
+        for (registration, entity, component) in decoded.components {
+            registration.insert(self, entity, component)?;
+        }
+        Ok(())
+    }
+
+    fn ensure_alive(&self, entity: Entity, caller: &str) -> KResult<()> {
+        kensure!(
+            self.is_alive(entity),
+            format!("{}: {:?} is not alive", caller, entity)
+        );
+        Ok(())
+    }
+
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    /// Inserts a global resource, returning the previous value of the same type, if any.
+    pub fn insert_resource<R: Resource>(&mut self, resource: R) -> Option<R> {
+        self.resources.insert(resource)
+    }
+
+    pub fn remove_resource<R: Resource>(&mut self) -> Option<R> {
+        self.resources.remove()
+    }
+
+    pub fn contains_resource<R: Resource>(&self) -> bool {
+        self.resources.contains::<R>()
+    }
+
+    pub fn resource<R: Resource>(&self) -> Option<MapRead<'_, R>> {
+        self.resources.get()
+    }
+
+    pub fn resource_mut<R: Resource>(&self) -> Option<MapWrite<'_, R>> {
+        self.resources.get_mut()
+    }
+
+    /// Inserts a resource that isn't `Send`, which can then only be used on the current thread.
+    pub fn insert_non_send_resource<R: 'static>(&mut self, resource: R) -> Option<R> {
+        self.resources.insert_non_send(resource)
+    }
+
+    pub fn remove_non_send_resource<R: 'static>(&mut self) -> Option<R> {
+        self.resources.remove_non_send()
+    }
+
+    pub fn contains_non_send_resource<R: 'static>(&self) -> bool {
+        self.resources.contains_non_send::<R>()
+    }
+
+    /// # Panics
+    /// Panics if called from a thread other than the one the resource was inserted on.
+    pub fn non_send_resource<R: 'static>(&self) -> Option<MapRead<'_, R>> {
+        self.resources.get_non_send()
+    }
+
+    /// # Panics
+    /// Panics if called from a thread other than the one the resource was inserted on.
+    pub fn non_send_resource_mut<R: 'static>(&self) -> Option<MapWrite<'_, R>> {
+        self.resources.get_non_send_mut()
+    }
+
+    pub fn query<Q: QueryData>(&self) -> Query<'_, Q> {
+        Query::new(self)
+    }
+
+    pub fn query_filtered<Q: QueryData, F: QueryFilter>(&self) -> Query<'_, Q, F> {
         Query::new(self)
     }
 }
+
+fn missing_component(entity: Entity, registration: &TypeRegistration, caller: &str) -> KError {
+    kerror!(format!(
+        "{}: {:?} has no {} component",
+        caller,
+        entity,
+        registration.type_name()
+    ))
+}
+
+fn bundle_type_ids<B: Bundle>() -> impl Iterator<Item = TypeId> {
+    let mut infos = Vec::new();
+    B::component_infos(&mut infos);
+    infos.into_iter().map(|info| info.type_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[test]
+    fn test_stale_handles() {
+        let mut world = World::new();
+
+        let old = world.spawn((Position { x: 1.0, y: 2.0 },));
+        world.destroy_entity(old).unwrap();
+        assert!(!world.is_alive(old));
+
+        let new = world.spawn((Position { x: 3.0, y: 4.0 },));
+        assert_eq!(new.id(), old.id());
+        assert!(world.is_alive(new));
+
+        assert!(world.get_component::<Position>(old).is_none());
+        assert!(!world.has_component::<Position>(old));
+        assert!(world.remove_component::<Position>(old).is_none());
+        assert!(world
+            .insert_component(old, Position { x: 5.0, y: 6.0 })
+            .is_err());
+
+        assert_eq!(
+            *world.get_component::<Position>(new).unwrap(),
+            Position { x: 3.0, y: 4.0 }
+        );
+    }
+
+    #[test]
+    fn test_double_destroy() {
+        let mut world = World::new();
+
+        let entity = world.create_entity();
+        assert!(world.destroy_entity(entity).is_ok());
+        assert!(world.destroy_entity(entity).is_err());
+        assert!(world.destroy_entity(Entity::new(100, 0)).is_err());
+    }
+
+    #[test]
+    fn test_entities() {
+        let mut world = World::new();
+
+        let a = world.create_entity();
+        let b = world.spawn((Position { x: 1.0, y: 2.0 },));
+        let c = world.create_entity();
+        world.destroy_entity(a).unwrap();
+
+        assert_eq!(world.entities().len(), 2);
+        assert_eq!(world.entities().iter().collect::<Vec<_>>(), vec![b, c]);
+    }
+}
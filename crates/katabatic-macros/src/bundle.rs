@@ -0,0 +1,80 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Fields, Index, Member};
+
+pub fn derive_bundle(input: DeriveInput) -> syn::Result<TokenStream> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(
+            &input,
+            "Bundle can only be derived for structs",
+        ));
+    };
+
+    let ecs = crate::ecs_path();
+
+    let mut component_infos = Vec::new();
+    let mut take_components = Vec::new();
+    let mut from_components = Vec::new();
+
+    for (index, field) in data.fields.iter().enumerate() {
+        let ty = &field.ty;
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index)),
+        };
+        let is_bundle = field
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("bundle"));
+
+        if is_bundle {
+            component_infos.push(quote! {
+                <#ty as #ecs::bundle::Bundle>::component_infos(infos);
+            });
+            take_components.push(quote! {
+                #ecs::bundle::Bundle::take_components(self.#member, f);
+            });
+            from_components.push(quote! {
+                #member: <#ty as #ecs::bundle::Bundle>::from_components(take)
+            });
+        } else {
+            component_infos.push(quote! {
+                infos.push(#ecs::component::ComponentInfo::of::<#ty>());
+            });
+            take_components.push(quote! {
+                #ecs::bundle::take_component(self.#member, f);
+            });
+            from_components.push(quote! {
+                #member: #ecs::bundle::from_component::<#ty>(take)
+            });
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let construct = match &data.fields {
+        Fields::Unit => quote!(Self),
+        _ => quote!(Self { #(#from_components,)* }),
+    };
+
+    Ok(quote! {
+        unsafe impl #impl_generics #ecs::bundle::Bundle for #name #ty_generics #where_clause {
+            fn component_infos(infos: &mut ::std::vec::Vec<#ecs::component::ComponentInfo>) {
+                #(#component_infos)*
+            }
+
+            #[allow(unused_variables)]
+            fn take_components(self, f: &mut dyn FnMut(#ecs::component::ComponentInfo, *mut u8)) {
+                #(#take_components)*
+            }
+
+            #[allow(unused_variables)]
+            fn from_components(
+                take: &mut dyn FnMut(::std::any::TypeId) -> ::std::boxed::Box<dyn #ecs::component::Component>,
+            ) -> Self {
+                #construct
+            }
+        }
+    })
+}
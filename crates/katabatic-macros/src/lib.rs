@@ -0,0 +1,51 @@
+use proc_macro::TokenStream;
+use proc_macro_crate::{crate_name, FoundCrate};
+use quote::{format_ident, quote};
+
+mod bundle;
+mod reflect;
+
+/// The path to `katabatic_ecs` from the crate using a derive: the crate itself if it depends on
+/// it directly, or its re-export as `katabatic::ecs` otherwise.
+fn ecs_path() -> proc_macro2::TokenStream {
+    match crate_name("katabatic-ecs") {
+        // `katabatic_ecs` names itself with `extern crate self`
+        Ok(FoundCrate::Itself) => quote!(::katabatic_ecs),
+        Ok(FoundCrate::Name(name)) => {
+            let name = format_ident!("{}", name);
+            quote!(::#name)
+        }
+        Err(_) => match crate_name("katabatic") {
+            Ok(FoundCrate::Name(name)) => {
+                let name = format_ident!("{}", name);
+                quote!(::#name::ecs)
+            }
+            Ok(FoundCrate::Itself) => quote!(crate::ecs),
+            Err(_) => quote!(::katabatic_ecs),
+        },
+    }
+}
+
+/// Implements `Bundle` for a struct whose fields are all components.
+///
+/// Works in crates that depend on `katabatic-ecs` or on `katabatic`.
+///
+/// Fields marked `#[bundle]` are treated as nested bundles instead.
+#[proc_macro_derive(Bundle, attributes(bundle))]
+pub fn derive_bundle(input: TokenStream) -> TokenStream {
+    bundle::derive_bundle(syn::parse_macro_input!(input as syn::DeriveInput))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Implements `Reflect` for a struct or enum whose fields all implement `Reflect`.
+///
+/// Works in crates that depend on `katabatic-ecs` or on `katabatic`.
+///
+/// Fields marked `#[reflect(ignore)]` are skipped, and must implement `Default`.
+#[proc_macro_derive(Reflect, attributes(reflect))]
+pub fn derive_reflect(input: TokenStream) -> TokenStream {
+    reflect::derive_reflect(syn::parse_macro_input!(input as syn::DeriveInput))
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
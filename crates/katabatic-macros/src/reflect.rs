@@ -0,0 +1,374 @@
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    parse_quote, Data, DeriveInput, Field, Fields, GenericParam, Ident, Index, LitStr, Member,
+};
+
+pub fn derive_reflect(mut input: DeriveInput) -> syn::Result<TokenStream> {
+    let ecs = crate::ecs_path();
+
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(#ecs::reflect::Reflect));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let schema = match &input.data {
+        Data::Struct(data) => schema(&data.fields)?,
+        Data::Enum(data) => {
+            let mut schema = Vec::new();
+            for variant in &data.variants {
+                let name = LitStr::new(&variant.ident.to_string(), variant.ident.span());
+                let fields = self::schema(&variant.fields)?;
+                schema.push(quote! {
+                    hasher.write_str(#name);
+                    #fields
+                });
+            }
+            quote!(#(#schema)*)
+        }
+        Data::Union(_) => TokenStream::new(),
+    };
+
+    let (kind, reflect_body, kind_impl) = match &input.data {
+        Data::Struct(data) => {
+            let (reflect_body, struct_body) = derive_struct(&data.fields)?;
+            (
+                quote!(Struct),
+                reflect_body,
+                quote! {
+                    impl #impl_generics #ecs::reflect::Struct for #name #ty_generics #where_clause {
+                        #struct_body
+                    }
+                },
+            )
+        }
+        Data::Enum(data) => {
+            let variants = data
+                .variants
+                .iter()
+                .map(|variant| (&variant.ident, &variant.fields))
+                .collect::<Vec<_>>();
+            let (reflect_body, enum_body) = derive_enum(&variants)?;
+            (
+                quote!(Enum),
+                reflect_body,
+                quote! {
+                    impl #impl_generics #ecs::reflect::Enum for #name #ty_generics #where_clause {
+                        #enum_body
+                    }
+                },
+            )
+        }
+        Data::Union(_) => {
+            return Err(syn::Error::new_spanned(
+                &input,
+                "Reflect can only be derived for structs and enums",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics #ecs::reflect::Reflect for #name #ty_generics #where_clause {
+            fn reflect_ref(&self) -> #ecs::reflect::ReflectRef<'_> {
+                #ecs::reflect::ReflectRef::#kind(self)
+            }
+
+            fn reflect_mut(&mut self) -> #ecs::reflect::ReflectMut<'_> {
+                #ecs::reflect::ReflectMut::#kind(self)
+            }
+
+            #reflect_body
+
+            fn schema_hash() -> u64 {
+                let mut hasher = #ecs::reflect::SchemaHasher::new();
+                hasher.write_str(::std::stringify!(#name));
+                #schema
+                hasher.finish()
+            }
+        }
+
+        #kind_impl
+    })
+}
+
+/// A field that isn't `#[reflect(ignore)]`d, and how to name it.
+struct ReflectedField<'a> {
+    field: &'a Field,
+    member: Member,
+    name: LitStr,
+    // what the field is bound to when matching on an enum variant
+    binding: Ident,
+}
+
+/// Splits fields into reflected ones, and the members of ignored ones.
+fn reflected_fields(fields: &Fields) -> syn::Result<(Vec<ReflectedField<'_>>, Vec<Member>)> {
+    let mut reflected = Vec::new();
+    let mut ignored = Vec::new();
+
+    for (index, field) in fields.iter().enumerate() {
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(index)),
+        };
+
+        let mut ignore = false;
+        for attr in &field.attrs {
+            if attr.path().is_ident("reflect") {
+                attr.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("ignore") {
+                        ignore = true;
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `ignore`"))
+                    }
+                })?;
+            }
+        }
+
+        if ignore {
+            ignored.push(member);
+        } else {
+            let (name, binding) = match &field.ident {
+                Some(ident) => (ident.to_string(), format_ident!("__{}", ident)),
+                None => (index.to_string(), format_ident!("__{}", index)),
+            };
+            reflected.push(ReflectedField {
+                field,
+                member,
+                name: LitStr::new(&name, proc_macro2::Span::call_site()),
+                binding,
+            });
+        }
+    }
+
+    Ok((reflected, ignored))
+}
+
+/// Feeds the names and schema hashes of the reflected fields to a `SchemaHasher` named `hasher`.
+fn schema(fields: &Fields) -> syn::Result<TokenStream> {
+    let ecs = crate::ecs_path();
+    let (reflected, _) = reflected_fields(fields)?;
+    let count = reflected.len() as u64;
+    let names = reflected.iter().map(|field| &field.name);
+    let tys = reflected.iter().map(|field| &field.field.ty);
+    Ok(quote! {
+        hasher.write_u64(#count);
+        #(
+            hasher.write_str(#names);
+            hasher.write_u64(<#tys as #ecs::reflect::Reflect>::schema_hash());
+        )*
+    })
+}
+
+/// Builds `Self` or a variant from the fields of a `Value` in scope as `fields`.
+fn construct(path: TokenStream, fields: &Fields) -> syn::Result<TokenStream> {
+    let ecs = crate::ecs_path();
+    let (reflected, ignored) = reflected_fields(fields)?;
+
+    let reflected = reflected.iter().map(|field| {
+        let ReflectedField {
+            field,
+            member,
+            name,
+            ..
+        } = field;
+        let ty = &field.ty;
+        quote! {
+            #member: <#ty as #ecs::reflect::Reflect>::from_value(
+                #ecs::reflect::field_value(fields, #name, type_name)?,
+            )?
+        }
+    });
+    let ignored = ignored
+        .iter()
+        .map(|member| quote!(#member: ::std::default::Default::default()));
+
+    Ok(quote!(#path { #(#reflected,)* #(#ignored,)* }))
+}
+
+fn derive_struct(fields: &Fields) -> syn::Result<(TokenStream, TokenStream)> {
+    let ecs = crate::ecs_path();
+    let (reflected, _) = reflected_fields(fields)?;
+
+    let names = reflected
+        .iter()
+        .map(|field| &field.name)
+        .collect::<Vec<_>>();
+    let members = reflected
+        .iter()
+        .map(|field| &field.member)
+        .collect::<Vec<_>>();
+    let construct = construct(quote!(Self), fields)?;
+
+    let reflect_body = quote! {
+        fn to_value(&self) -> #ecs::reflect::Value {
+            #ecs::reflect::Value::Struct(::std::vec![
+                #((
+                    ::std::string::ToString::to_string(#names),
+                    #ecs::reflect::Reflect::to_value(&self.#members),
+                ),)*
+            ])
+        }
+
+        fn set_value(&mut self, value: &#ecs::reflect::Value) -> #ecs::reflect::__KResult<()> {
+            #ecs::reflect::set_struct(self, value)
+        }
+
+        #[allow(unused_variables)]
+        fn from_value(value: &#ecs::reflect::Value) -> #ecs::reflect::__KResult<Self> {
+            let type_name = ::std::any::type_name::<Self>();
+            let fields = value.as_struct(type_name)?;
+            ::std::result::Result::Ok(#construct)
+        }
+    };
+
+    let struct_body = quote! {
+        fn field(&self, name: &str) -> ::std::option::Option<&dyn #ecs::reflect::Reflect> {
+            match name {
+                #(#names => ::std::option::Option::Some(&self.#members),)*
+                _ => ::std::option::Option::None,
+            }
+        }
+
+        fn field_mut(
+            &mut self,
+            name: &str,
+        ) -> ::std::option::Option<&mut dyn #ecs::reflect::Reflect> {
+            match name {
+                #(#names => ::std::option::Option::Some(&mut self.#members),)*
+                _ => ::std::option::Option::None,
+            }
+        }
+
+        fn field_names(&self) -> &'static [&'static str] {
+            &[#(#names),*]
+        }
+    };
+
+    Ok((reflect_body, struct_body))
+}
+
+fn derive_enum(variants: &[(&Ident, &Fields)]) -> syn::Result<(TokenStream, TokenStream)> {
+    let ecs = crate::ecs_path();
+
+    let variant_names = variants
+        .iter()
+        .map(|(ident, _)| LitStr::new(&ident.to_string(), ident.span()))
+        .collect::<Vec<_>>();
+
+    let mut patterns = Vec::new();
+    let mut to_values = Vec::new();
+    let mut constructs = Vec::new();
+    let mut fields_by_name = Vec::new();
+    let mut fields_by_name_mut = Vec::new();
+    let mut field_names = Vec::new();
+
+    for ((ident, fields), variant_name) in variants.iter().zip(&variant_names) {
+        let (reflected, _) = reflected_fields(fields)?;
+        let members = reflected
+            .iter()
+            .map(|field| &field.member)
+            .collect::<Vec<_>>();
+        let bindings = reflected
+            .iter()
+            .map(|field| &field.binding)
+            .collect::<Vec<_>>();
+        let names = reflected
+            .iter()
+            .map(|field| &field.name)
+            .collect::<Vec<_>>();
+
+        let pattern = quote!(Self::#ident { #(#members: #bindings,)* .. });
+
+        to_values.push(quote! {
+            #pattern => #ecs::reflect::Value::Enum {
+                variant: ::std::string::ToString::to_string(#variant_name),
+                fields: ::std::vec![
+                    #((
+                        ::std::string::ToString::to_string(#names),
+                        #ecs::reflect::Reflect::to_value(#bindings),
+                    ),)*
+                ],
+            },
+        });
+        let construct = construct(quote!(Self::#ident), fields)?;
+        constructs.push(quote!(#variant_name => ::std::result::Result::Ok(#construct),));
+        fields_by_name.push(quote! {
+            #((#pattern, #names) => ::std::option::Option::Some(#bindings),)*
+        });
+        fields_by_name_mut.push(quote! {
+            #((#pattern, #names) => ::std::option::Option::Some(#bindings),)*
+        });
+        field_names.push(quote!(#pattern => &[#(#names),*],));
+        patterns.push(pattern);
+    }
+
+    let reflect_body = quote! {
+        #[allow(unused_variables)]
+        fn to_value(&self) -> #ecs::reflect::Value {
+            match self {
+                #(#to_values)*
+            }
+        }
+
+        fn set_value(&mut self, value: &#ecs::reflect::Value) -> #ecs::reflect::__KResult<()> {
+            *self = <Self as #ecs::reflect::Reflect>::from_value(value)?;
+            ::std::result::Result::Ok(())
+        }
+
+        #[allow(unused_variables)]
+        fn from_value(value: &#ecs::reflect::Value) -> #ecs::reflect::__KResult<Self> {
+            let type_name = ::std::any::type_name::<Self>();
+            let (variant, fields) = value.as_enum(type_name)?;
+            match variant {
+                #(#constructs)*
+                _ => ::std::result::Result::Err(#ecs::reflect::unknown_variant(type_name, variant)),
+            }
+        }
+    };
+
+    let enum_body = quote! {
+        #[allow(unused_variables)]
+        fn variant_name(&self) -> &'static str {
+            match self {
+                #(#patterns => #variant_names,)*
+            }
+        }
+
+        fn variant_names(&self) -> &'static [&'static str] {
+            &[#(#variant_names),*]
+        }
+
+        #[allow(unused_variables, unreachable_patterns)]
+        fn field(&self, name: &str) -> ::std::option::Option<&dyn #ecs::reflect::Reflect> {
+            match (self, name) {
+                #(#fields_by_name)*
+                _ => ::std::option::Option::None,
+            }
+        }
+
+        #[allow(unused_variables, unreachable_patterns)]
+        fn field_mut(
+            &mut self,
+            name: &str,
+        ) -> ::std::option::Option<&mut dyn #ecs::reflect::Reflect> {
+            match (self, name) {
+                #(#fields_by_name_mut)*
+                _ => ::std::option::Option::None,
+            }
+        }
+
+        #[allow(unused_variables)]
+        fn field_names(&self) -> &'static [&'static str] {
+            match self {
+                #(#field_names)*
+            }
+        }
+    };
+
+    Ok((reflect_body, enum_body))
+}
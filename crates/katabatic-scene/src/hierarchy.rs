@@ -0,0 +1,326 @@
+//! Walking the hierarchy of a [`Scene`], made of its [`ChildOf`] relationships.
+//!
+//! Children are visited in the order they were added. Every walk visits a node at most once, so
+//! none of them loop forever on a cycle made through [`Scene::graph_mut`]. Walks from a node that
+//! was removed from the scene are empty.
+
+use std::collections::{HashSet, VecDeque};
+
+use katabatic_ecs::reflect::Reflect;
+use petgraph::{algo::kosaraju_scc, graph::NodeIndex, visit::EdgeFiltered};
+
+use crate::{node::Node, relationship::ChildOf, scene::Scene};
+
+/// A node's name, used to find it with [`Scene::find_by_path`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Reflect)]
+pub struct Name(pub String);
+
+impl Name {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<String> for Name {
+    fn from(name: String) -> Self {
+        Self(name)
+    }
+}
+
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Visits a subtree depth-first, each node before its children. See [`Scene::iter_depth_first`].
+pub struct DepthFirst<'a> {
+    scene: &'a Scene,
+    stack: Vec<NodeIndex>,
+    visited: HashSet<NodeIndex>,
+}
+
+impl Iterator for DepthFirst<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        loop {
+            let index = self.stack.pop()?;
+            if !self.visited.insert(index) {
+                continue;
+            }
+            let &node = self.scene.graph().node_weight(index)?;
+            // the last child added comes first, so the first one ends up on top of the stack
+            self.stack.extend(
+                self.scene
+                    .related::<ChildOf>(node)
+                    .map(|child| child.scene_index),
+            );
+            return Some(node);
+        }
+    }
+}
+
+/// Visits a subtree breadth-first, level by level. See [`Scene::iter_breadth_first`].
+pub struct BreadthFirst<'a> {
+    scene: &'a Scene,
+    queue: VecDeque<NodeIndex>,
+    visited: HashSet<NodeIndex>,
+}
+
+impl Iterator for BreadthFirst<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let index = self.queue.pop_front()?;
+        let &node = self.scene.graph().node_weight(index)?;
+        let mut children = self
+            .scene
+            .related::<ChildOf>(node)
+            .map(|child| child.scene_index)
+            .filter(|&child| self.visited.insert(child))
+            .collect::<Vec<_>>();
+        children.reverse();
+        self.queue.extend(children);
+        Some(node)
+    }
+}
+
+/// Walks up from a node through its [`ChildOf`] parents. See [`Scene::ancestors_of`].
+pub struct Ancestors<'a> {
+    scene: &'a Scene,
+    current: Option<NodeIndex>,
+    visited: HashSet<NodeIndex>,
+}
+
+impl Iterator for Ancestors<'_> {
+    type Item = Node;
+
+    fn next(&mut self) -> Option<Node> {
+        let &current = self.scene.graph().node_weight(self.current?)?;
+        let parent = self.scene.sources_of::<ChildOf>(current).next()?;
+        if !self.visited.insert(parent.scene_index) {
+            return None;
+        }
+        self.current = Some(parent.scene_index);
+        Some(parent)
+    }
+}
+
+impl Scene {
+    /// The subtree under `node`, depth-first and starting with `node` itself.
+    pub fn iter_depth_first(&self, node: Node) -> DepthFirst<'_> {
+        DepthFirst {
+            scene: self,
+            stack: self.check_index(node).into_iter().collect(),
+            visited: HashSet::new(),
+        }
+    }
+
+    /// The subtree under `node`, breadth-first and starting with `node` itself.
+    pub fn iter_breadth_first(&self, node: Node) -> BreadthFirst<'_> {
+        BreadthFirst {
+            scene: self,
+            queue: self.check_index(node).into_iter().collect(),
+            visited: HashSet::from([node.scene_index]),
+        }
+    }
+
+    /// The [`ChildOf`] descendants of `node`, depth-first, not including `node`.
+    pub fn descendants_of(&self, node: Node) -> impl Iterator<Item = Node> + '_ {
+        self.iter_depth_first(node).skip(1)
+    }
+
+    /// The [`ChildOf`] parent of `node`, then its parent, and so on up to the top of the
+    /// hierarchy.
+    pub fn ancestors_of(&self, node: Node) -> Ancestors<'_> {
+        Ancestors {
+            scene: self,
+            current: self.check_index(node).ok(),
+            visited: HashSet::from([node.scene_index]),
+        }
+    }
+
+    /// Whether `ancestor` is `node` itself or one of its ancestors.
+    pub fn is_ancestor_of(&self, ancestor: Node, node: Node) -> bool {
+        ancestor == node || self.ancestors_of(node).any(|found| found == ancestor)
+    }
+
+    /// The nodes of a [`ChildOf`] cycle, if the hierarchy has one.
+    ///
+    /// [`add_child`](Scene::add_child) refuses to make cycles, but they can still be made
+    /// through [`graph_mut`](Scene::graph_mut).
+    pub fn find_hierarchy_cycle(&self) -> Option<Vec<Node>> {
+        let hierarchy =
+            EdgeFiltered::from_fn(self.graph(), |edge| edge.weight().weight.is::<ChildOf>());
+        kosaraju_scc(&hierarchy)
+            .into_iter()
+            .find(|component| {
+                component.len() > 1
+                    || self
+                        .graph()
+                        .edges_connecting(component[0], component[0])
+                        .any(|edge| edge.weight().weight.is::<ChildOf>())
+            })
+            .map(|component| {
+                component
+                    .into_iter()
+                    .map(|index| self.graph()[index])
+                    .collect()
+            })
+    }
+
+    /// Finds a node by the [`Name`]s along its path from the root, separated by `/`. The first
+    /// name is the root's own.
+    ///
+    /// Where several children share a name, the first one added is used.
+    pub fn find_by_path(&self, path: &str) -> Option<Node> {
+        let (first, rest) = match path.split_once('/') {
+            Some((first, rest)) => (first, Some(rest)),
+            None => (path, None),
+        };
+        let root = *self.root();
+        if !self.has_name(root, first) {
+            return None;
+        }
+        match rest {
+            Some(rest) => self.find_child_by_path(root, rest),
+            None => Some(root),
+        }
+    }
+
+    /// Finds a descendant of `node` by the [`Name`]s along its path down from `node`, separated
+    /// by `/`.
+    pub fn find_child_by_path(&self, node: Node, path: &str) -> Option<Node> {
+        let mut current = node;
+        for name in path.split('/') {
+            let mut children = self.related::<ChildOf>(current).collect::<Vec<_>>();
+            children.reverse();
+            current = children
+                .into_iter()
+                .find(|&child| self.has_name(child, name))?;
+        }
+        Some(current)
+    }
+
+    fn has_name(&self, node: Node, name: &str) -> bool {
+        self.world()
+            .read()
+            .get_component::<Name>(node.entity)
+            .is_some_and(|found| found.as_str() == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_ecs::world::World;
+    use katabatic_util::lock::SharedLock;
+
+    use super::*;
+    use crate::relationship::RelationshipConnection;
+
+    /// root -> (a -> (c, d), b)
+    fn new_scene() -> (Scene, [Node; 5]) {
+        let mut scene = Scene::new(SharedLock::new(World::new()));
+        let root = *scene.root();
+        scene
+            .world()
+            .write()
+            .insert_component(root.entity, Name::new("root"))
+            .unwrap();
+        let a = scene.create_node_with(Name::new("a"));
+        let b = scene.create_node_with(Name::new("b"));
+        let c = scene.create_node_with(Name::new("c"));
+        let d = scene.create_node_with(Name::new("d"));
+        scene.add_child(root, a).unwrap();
+        scene.add_child(root, b).unwrap();
+        scene.add_child(a, c).unwrap();
+        scene.add_child(a, d).unwrap();
+        (scene, [root, a, b, c, d])
+    }
+
+    #[test]
+    fn test_traversal() {
+        let (scene, [root, a, b, c, d]) = new_scene();
+        assert_eq!(
+            scene.iter_depth_first(root).collect::<Vec<_>>(),
+            vec![root, a, c, d, b]
+        );
+        assert_eq!(
+            scene.iter_breadth_first(root).collect::<Vec<_>>(),
+            vec![root, a, b, c, d]
+        );
+        assert_eq!(scene.descendants_of(a).collect::<Vec<_>>(), vec![c, d]);
+        assert_eq!(scene.ancestors_of(d).collect::<Vec<_>>(), vec![a, root]);
+        assert_eq!(scene.ancestors_of(root).count(), 0);
+        assert!(scene.is_ancestor_of(root, d));
+        assert!(!scene.is_ancestor_of(b, d));
+    }
+
+    #[test]
+    fn test_hierarchy_cycles() {
+        let (mut scene, [root, a, _, c, _]) = new_scene();
+        assert!(scene.find_hierarchy_cycle().is_none());
+        assert!(scene.add_child(c, a).is_err());
+        assert!(scene.add_child(c, c).is_err());
+        assert!(scene.add_child(c, root).is_err());
+
+        scene.graph_mut().add_edge(
+            c.scene_index,
+            a.scene_index,
+            RelationshipConnection::new(c, a, ChildOf),
+        );
+        let mut cycle = scene.find_hierarchy_cycle().unwrap();
+        cycle.sort();
+        assert_eq!(cycle, vec![a, c]);
+        // walks still end
+        assert_eq!(scene.iter_depth_first(root).count(), 5);
+        assert_eq!(scene.ancestors_of(c).collect::<Vec<_>>(), vec![a]);
+    }
+
+    #[test]
+    fn test_removed_nodes() {
+        let (mut scene, [root, a, b, c, d]) = new_scene();
+        scene.remove_node(a).unwrap();
+        assert_eq!(scene.iter_depth_first(a).count(), 0);
+        assert_eq!(scene.iter_breadth_first(a).count(), 0);
+        assert_eq!(scene.descendants_of(a).count(), 0);
+        assert_eq!(scene.ancestors_of(a).count(), 0);
+        assert!(!scene.is_ancestor_of(root, a));
+        // `c` and `d` are orphaned, but still there
+        assert_eq!(scene.ancestors_of(c).count(), 0);
+        assert_eq!(scene.iter_depth_first(c).collect::<Vec<_>>(), vec![c]);
+        assert_eq!(
+            scene.iter_depth_first(root).collect::<Vec<_>>(),
+            vec![root, b]
+        );
+
+        // a new node can take the removed one's index
+        let e = scene.create_node();
+        scene.add_child(d, e).unwrap();
+        assert_eq!(e.scene_index, a.scene_index);
+        assert_eq!(scene.iter_depth_first(a).count(), 0);
+        assert_eq!(scene.ancestors_of(a).count(), 0);
+    }
+
+    #[test]
+    fn test_find_by_path() {
+        let (scene, [root, a, b, _, d]) = new_scene();
+        assert_eq!(scene.find_by_path("root"), Some(root));
+        assert_eq!(scene.find_by_path("root/b"), Some(b));
+        assert_eq!(scene.find_by_path("root/a/d"), Some(d));
+        assert_eq!(scene.find_by_path("root/b/d"), None);
+        assert_eq!(scene.find_by_path("a/d"), None);
+        assert_eq!(scene.find_child_by_path(a, "d"), Some(d));
+    }
+}
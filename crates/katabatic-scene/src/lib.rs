@@ -1,3 +1,8 @@
+pub mod hierarchy;
 pub mod node;
+pub mod prefab;
 pub mod relationship;
 pub mod scene;
+pub mod serialize;
+pub mod text;
+pub mod transform;
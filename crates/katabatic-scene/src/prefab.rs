@@ -0,0 +1,824 @@
+//! Prefabs: saved scenes that can be instantiated any number of times under a node of another
+//! scene.
+//!
+//! Each instance remembers which of its nodes came from which node of the prefab, so it can be
+//! updated in place with [`Scene::sync_prefab`] when the prefab is [reloaded](Prefab::reload).
+//! Property overrides set with [`Scene::set_override`] are applied again after every update.
+
+use std::{
+    any::TypeId,
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use katabatic_ecs::{
+    entity::Entity,
+    reflect::{Reflect, TypeRegistry, Value},
+};
+use katabatic_util::{error::KResult, kensure, kerror};
+use petgraph::{prelude::*, visit::IntoEdgeReferences};
+
+use crate::{
+    node::Node,
+    relationship::{ChildOf, Relationship},
+    scene::Scene,
+    serialize::{build_relationship, check_hierarchy, parse_scene, populate, remap, SceneData},
+};
+
+static NEXT_PREFAB_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifies a [`Prefab`], and stays the same when it's reloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PrefabId(u64);
+
+/// A scene in the text format of [`Scene::save`], ready to be instantiated with
+/// [`Scene::instantiate`].
+#[derive(Clone)]
+pub struct Prefab {
+    id: PrefabId,
+    data: Arc<SceneData>,
+}
+
+impl Prefab {
+    pub fn parse(text: &str) -> KResult<Self> {
+        Ok(Self {
+            id: PrefabId(NEXT_PREFAB_ID.fetch_add(1, Ordering::Relaxed)),
+            data: Arc::new(parse_scene(text)?),
+        })
+    }
+
+    pub fn load_from_file(path: impl AsRef<Path>) -> KResult<Self> {
+        Self::parse(&read_file(path.as_ref(), "Prefab::load_from_file()")?)
+    }
+
+    /// Makes a prefab out of the current contents of a scene.
+    pub fn from_scene(scene: &Scene) -> KResult<Self> {
+        Self::parse(&scene.save()?)
+    }
+
+    pub fn id(&self) -> PrefabId {
+        self.id
+    }
+
+    /// Replaces the prefab's contents, keeping its id. Existing instances are updated by
+    /// [`Scene::sync_prefab`].
+    ///
+    /// The prefab is left unchanged if the text can't be parsed.
+    pub fn reload(&mut self, text: &str) -> KResult<()> {
+        self.data = Arc::new(parse_scene(text)?);
+        Ok(())
+    }
+
+    pub fn reload_from_file(&mut self, path: impl AsRef<Path>) -> KResult<()> {
+        self.reload(&read_file(path.as_ref(), "Prefab::reload_from_file()")?)
+    }
+}
+
+fn read_file(path: &Path, context: &str) -> KResult<String> {
+    std::fs::read_to_string(path).map_err(|e| {
+        kerror!(format!(
+            "{}: could not read {}: {}",
+            context,
+            path.display(),
+            e
+        ))
+    })
+}
+
+/// Identifies an instance of a prefab within a [`Scene`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InstanceId(u64);
+
+/// The nodes a prefab was instantiated as, and the overrides set on them.
+pub struct PrefabInstance {
+    prefab: PrefabId,
+    data: Arc<SceneData>,
+    parent: Node,
+    // keyed by the node's id in the prefab
+    nodes: HashMap<u64, Node>,
+    overrides: Vec<Override>,
+}
+
+struct Override {
+    node: u64,
+    path: String,
+    value: Value,
+}
+
+impl PrefabInstance {
+    pub fn prefab(&self) -> PrefabId {
+        self.prefab
+    }
+
+    /// The node the instance was placed under.
+    pub fn parent(&self) -> Node {
+        self.parent
+    }
+
+    /// The node made from the prefab's root, a [`ChildOf`] child of the [parent](Self::parent).
+    pub fn root(&self) -> Node {
+        self.nodes[&self.data.root]
+    }
+
+    pub fn nodes(&self) -> impl Iterator<Item = Node> + '_ {
+        self.nodes.values().copied()
+    }
+
+    /// The node made from the prefab node with the given id.
+    pub fn node(&self, id: u64) -> Option<Node> {
+        self.nodes.get(&id).copied()
+    }
+
+    fn local_id(&self, node: Node) -> Option<u64> {
+        self.nodes
+            .iter()
+            .find(|(_, &found)| found == node)
+            .map(|(&id, _)| id)
+    }
+}
+
+impl Scene {
+    /// Spawns a copy of every node of `prefab`, and makes the copy of its root a [`ChildOf`]
+    /// child of `parent`.
+    ///
+    /// Fails if the prefab's [`ChildOf`] relationships give a node more than one parent or make
+    /// a cycle. Nothing is added to the scene or the world if instantiating fails.
+    pub fn instantiate(&mut self, prefab: &Prefab, parent: Node) -> KResult<InstanceId> {
+        self.check_node(parent)?;
+        let data = &prefab.data;
+        let registry = self.registry()?;
+
+        let mut entities = HashMap::new();
+        {
+            let world = self.world().clone();
+            let mut world = world.write();
+            for node in &data.nodes {
+                kensure!(
+                    !entities.contains_key(&node.id),
+                    format!("Scene::instantiate(): node id {} is used twice", node.id)
+                );
+                entities.insert(node.id, world.create_entity());
+            }
+
+            let populated = check_prefab_hierarchy(&registry, data, "Scene::instantiate()")
+                .and_then(|()| populate(&mut world, data, &entities));
+            if let Err(e) = populated {
+                for &entity in entities.values() {
+                    world.destroy_entity(entity)?;
+                }
+                return Err(e);
+            }
+        }
+
+        let nodes = data
+            .nodes
+            .iter()
+            .map(|node| (node.id, self.insert_node(entities[&node.id])))
+            .collect::<HashMap<_, _>>();
+        for relationship in &data.relationships {
+            let weight = build_relationship(&registry, relationship, &entities)?;
+            self.insert_edge(
+                nodes[&relationship.from].scene_index,
+                nodes[&relationship.to].scene_index,
+                weight,
+            );
+        }
+        self.insert_edge(
+            parent.scene_index,
+            nodes[&data.root].scene_index,
+            Box::new(ChildOf),
+        );
+
+        let id = InstanceId(self.next_instance);
+        self.next_instance += 1;
+        self.instances.insert(
+            id,
+            PrefabInstance {
+                prefab: prefab.id,
+                data: data.clone(),
+                parent,
+                nodes,
+                overrides: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    pub fn instance(&self, id: InstanceId) -> Option<&PrefabInstance> {
+        self.instances.get(&id)
+    }
+
+    /// The instances of `prefab` in this scene.
+    pub fn instances_of(&self, prefab: PrefabId) -> impl Iterator<Item = InstanceId> + '_ {
+        self.instances
+            .iter()
+            .filter(move |(_, instance)| instance.prefab == prefab)
+            .map(|(&id, _)| id)
+    }
+
+    /// Sets a field of a component on one of an instance's nodes, with
+    /// [`World::set_reflect_path`](katabatic_ecs::world::World::set_reflect_path), and keeps it
+    /// set when the instance is updated by [`sync_prefab`](Self::sync_prefab).
+    pub fn set_override(
+        &mut self,
+        instance: InstanceId,
+        node: Node,
+        path: &str,
+        value: Value,
+    ) -> KResult<()> {
+        let found = self.instances.get(&instance).ok_or_else(|| {
+            kerror!(format!(
+                "Scene::set_override(): no prefab instance {:?}",
+                instance
+            ))
+        })?;
+        let local = found.local_id(node).ok_or_else(|| {
+            kerror!(format!(
+                "Scene::set_override(): node {:?} is not part of prefab instance {:?}",
+                node.scene_index, instance
+            ))
+        })?;
+        self.check_node(node)?;
+        self.world()
+            .read()
+            .set_reflect_path(node.entity, path, &value)?;
+
+        let overrides = &mut self.instances.get_mut(&instance).unwrap().overrides;
+        overrides.retain(|o| !(o.node == local && o.path == path));
+        overrides.push(Override {
+            node: local,
+            path: path.to_string(),
+            value,
+        });
+        Ok(())
+    }
+
+    /// Despawns every node of an instance, along with their [`ChildOf`] descendants.
+    pub fn unload_instance(&mut self, id: InstanceId) -> KResult<()> {
+        let instance = self.instances.remove(&id).ok_or_else(|| {
+            kerror!(format!(
+                "Scene::unload_instance(): no prefab instance {:?}",
+                id
+            ))
+        })?;
+
+        for node in instance.nodes() {
+            // already gone as a descendant of another of the instance's nodes
+            if self.graph().node_weight(node.scene_index) != Some(&node) {
+                continue;
+            }
+            if self.world().read().is_alive(node.entity) {
+                self.despawn_recursive(node)?;
+            } else {
+                self.remove_node(node)?;
+            }
+        }
+
+        // instances nested inside this one went with it
+        let graph = self.graph();
+        let gone = self
+            .instances
+            .iter()
+            .filter(|(_, instance)| {
+                let root = instance.root();
+                graph.node_weight(root.scene_index) != Some(&root)
+            })
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+        for id in gone {
+            self.instances.remove(&id);
+        }
+        Ok(())
+    }
+
+    /// Updates every instance of `prefab` to its current contents, then applies their overrides
+    /// again.
+    ///
+    /// Nodes that are still in the prefab keep their entities: their components are replaced by
+    /// the prefab's, and components the prefab no longer has are removed. Nodes removed from the
+    /// prefab are despawned, along with the overrides set on them. Nodes whose entity was
+    /// destroyed are spawned again.
+    ///
+    /// An instance that can't be updated, because the prefab is broken or the instance's parent
+    /// is gone, is left as it was.
+    pub fn sync_prefab(&mut self, prefab: &Prefab) -> KResult<()> {
+        let mut ids = self.instances_of(prefab.id).collect::<Vec<_>>();
+        ids.sort();
+        for id in ids {
+            if !Arc::ptr_eq(&self.instances[&id].data, &prefab.data) {
+                self.sync_instance(id, prefab.data.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_instance(&mut self, id: InstanceId, data: Arc<SceneData>) -> KResult<()> {
+        let registry = self.registry()?;
+        let instance = self.instances.remove(&id).unwrap();
+
+        // build everything first, so that a bad prefab leaves the instance as it was; nothing
+        // after this can fail
+        let (entities, components, relationships) =
+            match self.prepare_sync(&registry, &instance, &data) {
+                Ok(prepared) => prepared,
+                Err(e) => {
+                    self.instances.insert(id, instance);
+                    return Err(e);
+                }
+            };
+
+        // edges within the instance are replaced by the prefab's, and the parent edge moved to
+        // the new root
+        let old = instance
+            .nodes
+            .values()
+            .map(|node| node.scene_index)
+            .collect::<HashSet<_>>();
+        let old_root = instance.root();
+        let edges = self
+            .graph()
+            .edge_references()
+            .filter(|edge| {
+                (old.contains(&edge.source()) && old.contains(&edge.target()))
+                    || (edge.source() == instance.parent.scene_index
+                        && edge.target() == old_root.scene_index
+                        && edge.weight().weight.is::<ChildOf>())
+            })
+            .map(|edge| edge.id())
+            .collect::<Vec<_>>();
+        for edge in edges {
+            self.graph_mut().remove_edge(edge);
+        }
+
+        {
+            let world = self.world().clone();
+            let mut world = world.write();
+            for old_node in &instance.data.nodes {
+                let node = instance.nodes[&old_node.id];
+                let Some(new_node) = data.nodes.iter().find(|n| n.id == old_node.id) else {
+                    if world.is_alive(node.entity) {
+                        world.destroy_entity(node.entity)?;
+                    }
+                    continue;
+                };
+                if entities[&old_node.id] != node.entity {
+                    continue;
+                }
+                for (type_name, _) in &old_node.components {
+                    let still_there = new_node
+                        .components
+                        .iter()
+                        .any(|(new_name, _)| new_name == type_name);
+                    if !still_there {
+                        if let Some(registration) = registry.get_with_name(type_name) {
+                            registration.remove(&mut world, node.entity);
+                        }
+                    }
+                }
+            }
+            for (entity, registration, component) in components {
+                registry
+                    .get(registration)
+                    .unwrap()
+                    .insert(&mut world, entity, component)?;
+            }
+        }
+
+        let mut nodes = HashMap::new();
+        for node in &data.nodes {
+            let found = match instance.nodes.get(&node.id) {
+                Some(&existing) if existing.entity == entities[&node.id] => existing,
+                _ => self.insert_node(entities[&node.id]),
+            };
+            nodes.insert(node.id, found);
+        }
+        for (local, node) in &instance.nodes {
+            let in_scene = self.graph().node_weight(node.scene_index) == Some(node);
+            if nodes.get(local) != Some(node) && in_scene {
+                self.remove_node(*node)?;
+            }
+        }
+        for (from, to, weight) in relationships {
+            self.insert_edge(nodes[&from].scene_index, nodes[&to].scene_index, weight);
+        }
+        self.insert_edge(
+            instance.parent.scene_index,
+            nodes[&data.root].scene_index,
+            Box::new(ChildOf),
+        );
+
+        let PrefabInstance {
+            prefab,
+            parent,
+            mut overrides,
+            ..
+        } = instance;
+        overrides.retain(|o| nodes.contains_key(&o.node));
+        let mut result = Ok(());
+        {
+            let world = self.world().read();
+            for o in &overrides {
+                if let Err(e) = world.set_reflect_path(nodes[&o.node].entity, &o.path, &o.value) {
+                    if result.is_ok() {
+                        result = Err(e);
+                    }
+                }
+            }
+        }
+        self.instances.insert(
+            id,
+            PrefabInstance {
+                prefab,
+                data,
+                parent,
+                nodes,
+                overrides,
+            },
+        );
+        result
+    }
+
+    /// Picks the entities an instance's nodes will have after it's synced, and builds their
+    /// components and relationships. Nodes that were removed from the scene or whose entity is
+    /// gone get a new entity.
+    ///
+    /// The new entities are destroyed again if this fails.
+    fn prepare_sync(
+        &self,
+        registry: &TypeRegistry,
+        instance: &PrefabInstance,
+        data: &SceneData,
+    ) -> KResult<(HashMap<u64, Entity>, BuiltComponents, BuiltRelationships)> {
+        self.check_node(instance.parent)?;
+
+        let world = self.world().clone();
+        let mut world = world.write();
+        let mut entities = HashMap::new();
+        let mut created = Vec::new();
+        for node in &data.nodes {
+            let entity = match instance.nodes.get(&node.id) {
+                Some(existing)
+                    if world.is_alive(existing.entity)
+                        && self.graph().node_weight(existing.scene_index) == Some(existing) =>
+                {
+                    existing.entity
+                }
+                _ => {
+                    let entity = world.create_entity();
+                    created.push(entity);
+                    entity
+                }
+            };
+            entities.insert(node.id, entity);
+        }
+
+        let built = check_prefab_hierarchy(registry, data, "Scene::sync_prefab()")
+            .and_then(|()| build_instance(registry, data, &entities));
+        match built {
+            Ok((components, relationships)) => Ok((entities, components, relationships)),
+            Err(e) => {
+                for entity in created {
+                    world.destroy_entity(entity)?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn registry(&self) -> KResult<TypeRegistry> {
+        Ok(self
+            .world()
+            .read()
+            .resource::<TypeRegistry>()
+            .ok_or_else(|| kerror!("Scene: the world has no TypeRegistry resource"))?
+            .clone())
+    }
+}
+
+/// Fails if the prefab's [`ChildOf`] relationships give a node more than one parent or make a
+/// cycle, or give its root a parent.
+fn check_prefab_hierarchy(registry: &TypeRegistry, data: &SceneData, context: &str) -> KResult<()> {
+    check_hierarchy(registry, data, context)?;
+    let child_of = TypeId::of::<ChildOf>();
+    let root_has_parent = data.relationships.iter().any(|relationship| {
+        relationship.to == data.root
+            && registry
+                .get_with_name(&relationship.type_name)
+                .is_some_and(|registration| registration.type_id() == child_of)
+    });
+    kensure!(
+        !root_has_parent,
+        format!("{}: root node {} has a parent", context, data.root)
+    );
+    Ok(())
+}
+
+type BuiltComponents = Vec<(Entity, TypeId, Box<dyn Reflect>)>;
+type BuiltRelationships = Vec<(u64, u64, Box<dyn Relationship>)>;
+
+/// Builds every component and relationship of a prefab without adding them to anything.
+fn build_instance(
+    registry: &TypeRegistry,
+    data: &SceneData,
+    entities: &HashMap<u64, Entity>,
+) -> KResult<(BuiltComponents, BuiltRelationships)> {
+    kensure!(
+        entities.contains_key(&data.root),
+        format!(
+            "Scene::sync_prefab(): root node {} does not exist",
+            data.root
+        )
+    );
+
+    let mut relationships = Vec::new();
+    for relationship in &data.relationships {
+        for id in [relationship.from, relationship.to] {
+            kensure!(
+                entities.contains_key(&id),
+                format!(
+                    "Scene::sync_prefab(): a relationship refers to node {}, which does not exist",
+                    id
+                )
+            );
+        }
+        relationships.push((
+            relationship.from,
+            relationship.to,
+            build_relationship(registry, relationship, entities)?,
+        ));
+    }
+
+    let mut components = Vec::new();
+    for node in &data.nodes {
+        for (type_name, value) in &node.components {
+            let registration = registry.get_with_name(type_name).ok_or_else(|| {
+                kerror!(format!(
+                    "Scene::sync_prefab(): component type {} is not registered",
+                    type_name
+                ))
+            })?;
+            components.push((
+                entities[&node.id],
+                registration.type_id(),
+                registration.from_value(&remap(value, entities)?)?,
+            ));
+        }
+    }
+
+    Ok((components, relationships))
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_ecs::world::World;
+    use katabatic_util::lock::SharedLock;
+
+    use super::*;
+    use crate::relationship::register_relationship;
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Health(u32);
+
+    const PREFAB: &str = r#"(
+        root: 0,
+        nodes: [
+            (id: 0, components: {"Position": (x: 1.0, y: 2.0)}),
+            (id: 1, components: {"Health": (0: 10)}),
+        ],
+        relationships: [(from: 0, to: 1, type: "ChildOf", value: ())],
+    )"#;
+
+    fn new_scene() -> Scene {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Position>();
+        registry.register::<Health>();
+        register_relationship::<ChildOf>(&mut registry);
+        let mut world = World::new();
+        world.insert_resource(registry);
+        Scene::new(SharedLock::new(world))
+    }
+
+    #[test]
+    fn test_instantiate() {
+        let mut scene = new_scene();
+        let root = *scene.root();
+        let prefab = Prefab::parse(PREFAB).unwrap();
+        let a = scene.instantiate(&prefab, root).unwrap();
+        let b = scene.instantiate(&prefab, root).unwrap();
+        assert_eq!(scene.instances_of(prefab.id()).count(), 2);
+        assert_eq!(scene.graph().node_count(), 5);
+
+        let a = scene.instance(a).unwrap();
+        let b = scene.instance(b).unwrap();
+        assert_ne!(a.root(), b.root());
+        assert_eq!(scene.related::<ChildOf>(a.root()).next(), a.node(1));
+        assert_eq!(scene.sources_of::<ChildOf>(a.root()).next(), Some(root));
+        let world = scene.world().read();
+        assert_eq!(
+            *world
+                .get_component::<Health>(b.node(1).unwrap().entity)
+                .unwrap(),
+            Health(10)
+        );
+    }
+
+    #[test]
+    fn test_sync_prefab_keeps_overrides() {
+        let mut scene = new_scene();
+        let root = *scene.root();
+        let mut prefab = Prefab::parse(PREFAB).unwrap();
+        let id = scene.instantiate(&prefab, root).unwrap();
+        let instance_root = scene.instance(id).unwrap().root();
+        let child = scene.instance(id).unwrap().node(1).unwrap();
+        scene
+            .set_override(id, instance_root, "Position.x", Value::Float(5.0))
+            .unwrap();
+
+        prefab
+            .reload(
+                r#"(
+                    root: 0,
+                    nodes: [
+                        (id: 0, components: {"Position": (x: 1.0, y: 3.0), "Health": (0: 1)}),
+                        (id: 2, components: {}),
+                    ],
+                    relationships: [(from: 0, to: 2, type: "ChildOf", value: ())],
+                )"#,
+            )
+            .unwrap();
+        scene.sync_prefab(&prefab).unwrap();
+
+        let instance = scene.instance(id).unwrap();
+        assert_eq!(instance.root(), instance_root);
+        assert_eq!(instance.node(1), None);
+        let new_child = instance.node(2).unwrap();
+        assert_eq!(
+            scene.related::<ChildOf>(instance_root).collect::<Vec<_>>(),
+            vec![new_child]
+        );
+        assert_eq!(scene.find_node(child.entity), None);
+        let world = scene.world().read();
+        assert!(!world.is_alive(child.entity));
+        assert_eq!(
+            *world
+                .get_component::<Position>(instance_root.entity)
+                .unwrap(),
+            Position { x: 5.0, y: 3.0 }
+        );
+        assert_eq!(
+            *world.get_component::<Health>(instance_root.entity).unwrap(),
+            Health(1)
+        );
+    }
+
+    #[test]
+    fn test_sync_prefab_removes_components() {
+        let mut scene = new_scene();
+        let root = *scene.root();
+        let mut prefab = Prefab::parse(PREFAB).unwrap();
+        let id = scene.instantiate(&prefab, root).unwrap();
+        prefab
+            .reload(PREFAB.replace(r#""Health": (0: 10)"#, "").as_str())
+            .unwrap();
+        scene.sync_prefab(&prefab).unwrap();
+
+        let child = scene.instance(id).unwrap().node(1).unwrap();
+        assert!(!scene.world().read().has_component::<Health>(child.entity));
+
+        // a broken prefab leaves the instance untouched
+        prefab.reload(&PREFAB.replace("Health", "Missing")).unwrap();
+        assert!(scene.sync_prefab(&prefab).is_err());
+        assert_eq!(scene.instance(id).unwrap().node(1), Some(child));
+    }
+
+    #[test]
+    fn test_sync_prefab_dangling_nodes() {
+        let mut scene = new_scene();
+        let root = *scene.root();
+        let mut prefab = Prefab::parse(PREFAB).unwrap();
+        let id = scene.instantiate(&prefab, root).unwrap();
+        let instance_root = scene.instance(id).unwrap().root();
+        let child = scene.instance(id).unwrap().node(1).unwrap();
+        scene.world().write().destroy_entity(child.entity).unwrap();
+
+        prefab
+            .reload(&PREFAB.replace("(0: 10)", "(0: 20)"))
+            .unwrap();
+        scene.sync_prefab(&prefab).unwrap();
+
+        // the dangling node is replaced by a fresh one
+        let new_child = scene.instance(id).unwrap().node(1).unwrap();
+        assert_ne!(new_child, child);
+        assert_eq!(scene.find_node(child.entity), None);
+        assert_eq!(
+            scene.related::<ChildOf>(instance_root).collect::<Vec<_>>(),
+            vec![new_child]
+        );
+        assert_eq!(
+            *scene
+                .world()
+                .read()
+                .get_component::<Health>(new_child.entity)
+                .unwrap(),
+            Health(20)
+        );
+
+        // an instance whose parent is gone can't be synced, and is left alone
+        let parent = scene.create_node();
+        scene.add_child(root, parent).unwrap();
+        let nested = scene.instantiate(&prefab, parent).unwrap();
+        let nested_root = scene.instance(nested).unwrap().root();
+        scene.world().write().destroy_entity(parent.entity).unwrap();
+        prefab.reload(PREFAB).unwrap();
+        assert!(scene.sync_prefab(&prefab).is_err());
+        assert_eq!(scene.instance(nested).unwrap().root(), nested_root);
+        assert_eq!(
+            scene.sources_of::<ChildOf>(nested_root).next(),
+            Some(parent)
+        );
+    }
+
+    #[test]
+    fn test_prefab_cycles() {
+        let mut scene = new_scene();
+        let root = *scene.root();
+        let cyclic = PREFAB.replace(
+            r#"relationships: [(from: 0, to: 1, type: "ChildOf", value: ())]"#,
+            r#"relationships: [
+                (from: 0, to: 1, type: "ChildOf", value: ()),
+                (from: 1, to: 1, type: "ChildOf", value: ()),
+            ]"#,
+        );
+        let prefab = Prefab::parse(&cyclic).unwrap();
+        let entities = scene.world().read().entities().len();
+        assert!(scene.instantiate(&prefab, root).is_err());
+        assert_eq!(scene.world().read().entities().len(), entities);
+        assert_eq!(scene.graph().node_count(), 1);
+
+        let mut prefab = Prefab::parse(PREFAB).unwrap();
+        let id = scene.instantiate(&prefab, root).unwrap();
+        let nodes = scene.instance(id).unwrap().nodes().collect::<HashSet<_>>();
+        let edges = scene.graph().edge_count();
+        prefab
+            .reload(&PREFAB.replace(
+                r#"relationships: [(from: 0, to: 1, type: "ChildOf", value: ())]"#,
+                r#"relationships: [
+                    (from: 0, to: 1, type: "ChildOf", value: ()),
+                    (from: 1, to: 0, type: "ChildOf", value: ()),
+                ]"#,
+            ))
+            .unwrap();
+        assert!(scene.sync_prefab(&prefab).is_err());
+        assert_eq!(
+            scene.instance(id).unwrap().nodes().collect::<HashSet<_>>(),
+            nodes
+        );
+        assert_eq!(scene.graph().edge_count(), edges);
+        assert_eq!(scene.world().read().entities().len(), entities + 2);
+        assert!(scene.find_hierarchy_cycle().is_none());
+
+        let two_parents = PREFAB.replace(
+            r#"relationships: [(from: 0, to: 1, type: "ChildOf", value: ())]"#,
+            r#"relationships: [
+                (from: 0, to: 1, type: "ChildOf", value: ()),
+                (from: 0, to: 1, type: "ChildOf", value: ()),
+            ]"#,
+        );
+        assert!(scene
+            .instantiate(&Prefab::parse(&two_parents).unwrap(), root)
+            .is_err());
+        prefab.reload(&two_parents).unwrap();
+        assert!(scene.sync_prefab(&prefab).is_err());
+        assert_eq!(scene.world().read().entities().len(), entities + 2);
+    }
+
+    #[test]
+    fn test_unload_instance() {
+        let mut scene = new_scene();
+        let root = *scene.root();
+        let prefab = Prefab::parse(PREFAB).unwrap();
+        let a = scene.instantiate(&prefab, root).unwrap();
+        let b = scene.instantiate(&prefab, root).unwrap();
+        let nodes = scene.instance(a).unwrap().nodes().collect::<Vec<_>>();
+
+        scene.unload_instance(a).unwrap();
+        assert!(scene.instance(a).is_none());
+        assert!(scene.unload_instance(a).is_err());
+        for node in nodes {
+            assert!(!scene.world().read().is_alive(node.entity));
+            assert_eq!(scene.find_node(node.entity), None);
+        }
+        assert_eq!(scene.instance(b).unwrap().nodes().count(), 2);
+        assert_eq!(scene.graph().node_count(), 3);
+    }
+}
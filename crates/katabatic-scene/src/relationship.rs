@@ -1,28 +1,57 @@
 use std::{any::Any, fmt::Debug};
 
+use katabatic_ecs::reflect::{Reflect, TypeRegistry};
+
 use crate::node::Node;
 
-pub trait Relationship: Any + Debug {
-    fn as_any(&self) -> &dyn Any
-    where
-        Self: Sized,
-    {
+/// Conversions to [`Any`], implemented for every type so they're available on
+/// `dyn Relationship`.
+pub trait AsAny: Any {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn as_any_box(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
         self
     }
-    fn as_any_mut(&mut self) -> &mut dyn Any
-    where
-        Self: Sized,
-    {
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
         self
     }
-    fn as_any_box(self: Box<Self>) -> Box<dyn Any>
-    where
-        Self: Sized,
-    {
+
+    fn as_any_box(self: Box<Self>) -> Box<dyn Any> {
         self
     }
 }
 
+/// The payload of an edge between two nodes of a [`Scene`](crate::scene::Scene).
+pub trait Relationship: AsAny + Debug {}
+
+impl dyn Relationship {
+    pub fn is<T: Relationship>(&self) -> bool {
+        self.as_any().is::<T>()
+    }
+
+    pub fn downcast_ref<T: Relationship>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    pub fn downcast_mut<T: Relationship>(&mut self) -> Option<&mut T> {
+        self.as_any_mut().downcast_mut()
+    }
+}
+
+/// Makes the target node a child of the source node, so it inherits the source's
+/// [`GlobalTransform`](crate::transform::GlobalTransform).
+///
+/// Added with [`Scene::add_child`](crate::scene::Scene::add_child).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Reflect)]
+pub struct ChildOf;
+
+impl Relationship for ChildOf {}
+
 #[derive(Debug)]
 pub struct RelationshipConnection {
     pub from: Node,
@@ -39,3 +68,48 @@ impl RelationshipConnection {
         }
     }
 }
+
+type FromReflectFn = fn(Box<dyn Reflect>) -> Option<Box<dyn Relationship>>;
+
+/// Type data for a reflected [`Relationship`], letting scenes save and load it.
+///
+/// Registered with [`register_relationship`].
+#[derive(Clone, Copy)]
+pub struct ReflectRelationship {
+    as_reflect: fn(&dyn Relationship) -> Option<&dyn Reflect>,
+    from_reflect: FromReflectFn,
+}
+
+impl ReflectRelationship {
+    pub fn of<T: Relationship + Reflect>() -> Self {
+        Self {
+            as_reflect: |relationship| {
+                let relationship = relationship.downcast_ref::<T>()?;
+                Some(relationship)
+            },
+            from_reflect: |value| {
+                let value = value.as_any_box().downcast::<T>().ok()?;
+                Some(value)
+            },
+        }
+    }
+
+    /// Views a relationship of this type as [`Reflect`]. Returns `None` for other types.
+    pub fn as_reflect<'a>(&self, relationship: &'a dyn Relationship) -> Option<&'a dyn Reflect> {
+        (self.as_reflect)(relationship)
+    }
+
+    /// Turns a value of this type back into a relationship. Returns `None` for other types.
+    pub fn from_reflect(&self, value: Box<dyn Reflect>) -> Option<Box<dyn Relationship>> {
+        (self.from_reflect)(value)
+    }
+}
+
+/// Registers `T` in `registry` as both a reflected type and a relationship.
+pub fn register_relationship<T: Relationship + Reflect>(registry: &mut TypeRegistry) {
+    registry.register::<T>();
+    registry
+        .get_mut(std::any::TypeId::of::<T>())
+        .unwrap()
+        .insert_data(ReflectRelationship::of::<T>());
+}
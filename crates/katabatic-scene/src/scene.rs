@@ -1,10 +1,13 @@
-use katabatic_ecs::{component::Component, entity::Entity, world::World};
-use katabatic_util::lock::SharedLock;
+use std::collections::{HashMap, HashSet};
+
+use katabatic_ecs::{component::Component, entity::Entity, tick::Tick, world::World};
+use katabatic_util::{error::KResult, kbail, kensure, lock::SharedLock};
 use petgraph::prelude::*;
 
 use crate::{
     node::Node,
-    relationship::{Relationship, RelationshipConnection},
+    prefab::{InstanceId, PrefabInstance},
+    relationship::{ChildOf, Relationship, RelationshipConnection},
 };
 
 pub struct Scene {
@@ -12,11 +15,22 @@ pub struct Scene {
     root_entity: Entity,
     root: NodeIndex,
     graph: StableDiGraph<Node, RelationshipConnection>,
+    index: HashMap<Entity, NodeIndex>,
+    // nodes given a new parent since the last transform propagation
+    pub(crate) transforms_dirty: HashSet<NodeIndex>,
+    pub(crate) last_propagation: Tick,
+    pub(crate) instances: HashMap<InstanceId, PrefabInstance>,
+    pub(crate) next_instance: u64,
 }
 
 impl Scene {
     pub fn new(world: SharedLock<World>) -> Self {
         let root_entity = world.write().create_entity();
+        Self::with_root_entity(world, root_entity)
+    }
+
+    /// Creates a scene whose root node is an existing entity.
+    pub fn with_root_entity(world: SharedLock<World>, root_entity: Entity) -> Self {
         let mut graph: StableGraph<Node, RelationshipConnection> = StableDiGraph::new();
         let root = graph.add_node(Node {
             entity: root_entity,
@@ -28,6 +42,11 @@ impl Scene {
             root_entity,
             root,
             graph,
+            index: HashMap::from([(root_entity, root)]),
+            transforms_dirty: HashSet::new(),
+            last_propagation: Tick::default(),
+            instances: HashMap::new(),
+            next_instance: 0,
         }
     }
 
@@ -47,57 +66,214 @@ impl Scene {
         &self.graph
     }
 
+    /// Direct access to the graph.
+    ///
+    /// Nodes added or removed through here bypass the scene's entity index, so
+    /// [`find_node`](Scene::find_node) won't see them.
     pub fn graph_mut(&mut self) -> &mut StableDiGraph<Node, RelationshipConnection> {
         &mut self.graph
     }
 
     pub fn create_node(&mut self) -> Node {
         let entity = self.world.write().create_entity();
-        self.add_node(entity)
+        self.insert_node(entity)
     }
 
     pub fn create_node_with<T: Component>(&mut self, component: T) -> Node {
-        let entity = self.world.write().create_entity();
-        self.world.write().insert_component(entity, component);
-        self.add_node(entity)
+        let entity = self.world.write().spawn((component,));
+        self.insert_node(entity)
     }
 
-    pub fn add_node(&mut self, entity: Entity) -> Node {
+    /// Adds a node for an existing entity.
+    ///
+    /// Fails if the entity is dead, or already has a node in this scene.
+    pub fn add_node(&mut self, entity: Entity) -> KResult<Node> {
+        kensure!(
+            self.world.read().is_alive(entity),
+            format!("Scene::add_node(): entity {:?} is not alive", entity)
+        );
+        kensure!(
+            !self.index.contains_key(&entity),
+            format!(
+                "Scene::add_node(): entity {:?} is already in the scene",
+                entity
+            )
+        );
+        Ok(self.insert_node(entity))
+    }
+
+    pub(crate) fn insert_node(&mut self, entity: Entity) -> Node {
         let node = self.graph.add_node(Node {
             entity,
             scene_index: NodeIndex::new(0),
         });
         self.graph[node].scene_index = node;
+        self.index.insert(entity, node);
         self.graph[node]
     }
 
-    pub fn add_relationship<T: Relationship>(&mut self, from: Node, to: Node, weight: T) {
-        let from = from.scene_index;
-        let to = to.scene_index;
-        let connection = RelationshipConnection::new(self.graph[from], self.graph[to], weight);
-        self.graph.add_edge(from, to, connection);
+    /// Relates `from` to `to`. A [`ChildOf`] relationship fails if `to` already has a parent, or
+    /// if it would make a cycle.
+    pub fn add_relationship<T: Relationship>(
+        &mut self,
+        from: Node,
+        to: Node,
+        weight: T,
+    ) -> KResult<()> {
+        let from_index = self.check_node(from)?;
+        let to_index = self.check_node(to)?;
+        let weight: Box<dyn Relationship> = Box::new(weight);
+        if weight.is::<ChildOf>() {
+            kensure!(
+                self.sources_of::<ChildOf>(to).next().is_none(),
+                format!(
+                    "Scene::add_relationship(): {:?} already has a parent",
+                    to.scene_index
+                )
+            );
+            kensure!(
+                !self.is_ancestor_of(to, from),
+                format!(
+                    "Scene::add_relationship(): making {:?} a child of {:?} would create a cycle",
+                    to.scene_index, from.scene_index
+                )
+            );
+        }
+        self.insert_edge(from_index, to_index, weight);
+        Ok(())
+    }
+
+    pub(crate) fn insert_edge(
+        &mut self,
+        from: NodeIndex,
+        to: NodeIndex,
+        weight: Box<dyn Relationship>,
+    ) -> EdgeIndex {
+        if weight.is::<ChildOf>() {
+            self.transforms_dirty.insert(to);
+        }
+        let connection = RelationshipConnection {
+            from: self.graph[from],
+            to: self.graph[to],
+            weight,
+        };
+        self.graph.add_edge(from, to, connection)
+    }
+
+    /// Makes `child` a child of `parent` with a [`ChildOf`] relationship.
+    ///
+    /// Fails if `child` already has a parent, or if it is `parent` or one of its ancestors. To
+    /// reparent a node, [remove](Scene::remove_relationship) its old `ChildOf` first.
+    pub fn add_child(&mut self, parent: Node, child: Node) -> KResult<()> {
+        self.add_relationship(parent, child, ChildOf)
+    }
+
+    /// Removes a node and its relationships from the scene, leaving its entity alive in the
+    /// world. Use [`despawn_recursive`](Scene::despawn_recursive) to destroy it as well.
+    pub fn remove_node(&mut self, node: Node) -> KResult<()> {
+        let index = self.check_index(node)?;
+        kensure!(
+            index != self.root,
+            "Scene::remove_node(): cannot remove the root node"
+        );
+        // its children are left without a parent
+        let children = self
+            .related::<ChildOf>(node)
+            .map(|child| child.scene_index)
+            .collect::<Vec<_>>();
+        self.transforms_dirty.extend(children);
+        self.graph.remove_node(index);
+        self.index.remove(&node.entity);
+        self.transforms_dirty.remove(&index);
+        Ok(())
     }
 
-    pub fn remove_node(&mut self, node: Node) {
-        self.graph.remove_node(node.scene_index);
+    /// Removes a node and all of its [`ChildOf`] descendants from the scene, and destroys their
+    /// entities.
+    ///
+    /// Nothing is removed if any node in the subtree is dangling.
+    pub fn despawn_recursive(&mut self, node: Node) -> KResult<()> {
+        let index = self.check_node(node)?;
+        kensure!(
+            index != self.root,
+            "Scene::despawn_recursive(): cannot despawn the root node"
+        );
+
+        let subtree = self.iter_breadth_first(node).collect::<Vec<_>>();
+        for &node in &subtree {
+            let index = self.check_node(node)?;
+            kensure!(
+                index != self.root,
+                "Scene::despawn_recursive(): the root node is a descendant of the node"
+            );
+        }
+
+        let world = self.world.clone();
+        let mut world = world.write();
+        for node in subtree {
+            self.graph.remove_node(node.scene_index);
+            self.index.remove(&node.entity);
+            self.transforms_dirty.remove(&node.scene_index);
+            world.destroy_entity(node.entity)?;
+        }
+        Ok(())
     }
 
-    pub fn remove_relationship(&mut self, from: Node, to: Node) -> Option<Box<dyn Relationship>> {
-        let from = from.scene_index;
-        let to = to.scene_index;
-        if let Some(edge) = self.graph.find_edge(from, to) {
-            let connection = self.graph.remove_edge(edge)?;
-            Some(connection.weight)
-        } else {
-            None
+    /// Destroys the entities of every node in the scene, including the root.
+    pub fn unload(self) -> KResult<()> {
+        let mut world = self.world.write();
+        for node in self.graph.node_weights() {
+            if world.is_alive(node.entity) {
+                world.destroy_entity(node.entity)?;
+            }
         }
+        Ok(())
+    }
+
+    pub fn remove_relationship(
+        &mut self,
+        from: Node,
+        to: Node,
+    ) -> KResult<Option<Box<dyn Relationship>>> {
+        let from = self.check_index(from)?;
+        let to = self.check_index(to)?;
+        let Some(edge) = self.graph.find_edge(from, to) else {
+            return Ok(None);
+        };
+        let weight = self.graph.remove_edge(edge).unwrap().weight;
+        if weight.is::<ChildOf>() {
+            self.transforms_dirty.insert(to);
+        }
+        Ok(Some(weight))
     }
 
     pub fn find_node(&self, entity: Entity) -> Option<Node> {
-        self.graph
-            .node_indices()
-            .find(|&node| self.graph[node].entity == entity)
-            .map(|node| self.graph[node])
+        self.index.get(&entity).map(|&index| self.graph[index])
+    }
+
+    /// Checks that `node` is still in the scene, returning its index.
+    pub(crate) fn check_index(&self, node: Node) -> KResult<NodeIndex> {
+        // indices of removed nodes get reused, so the entity has to match too
+        match self.graph.node_weight(node.scene_index) {
+            Some(found) if found.entity == node.entity => Ok(node.scene_index),
+            _ => kbail!(format!(
+                "Scene: dangling node {:?} for entity {:?}",
+                node.scene_index, node.entity
+            )),
+        }
+    }
+
+    /// Checks that `node` is still in the scene and its entity is still alive.
+    pub(crate) fn check_node(&self, node: Node) -> KResult<NodeIndex> {
+        let index = self.check_index(node)?;
+        kensure!(
+            self.world.read().is_alive(node.entity),
+            format!(
+                "Scene: node {:?} has dangling entity {:?}",
+                node.scene_index, node.entity
+            )
+        );
+        Ok(index)
     }
 
     pub fn children_of(&self, node: Node) -> impl Iterator<Item = Node> + '_ {
@@ -121,6 +297,38 @@ impl Scene {
         )
     }
 
+    /// The nodes `node` has a `T` relationship to.
+    pub fn related<T: Relationship>(&self, node: Node) -> impl Iterator<Item = Node> + '_ {
+        self.graph
+            .edges_directed(node.scene_index, Direction::Outgoing)
+            .filter(|edge| edge.weight().weight.is::<T>())
+            .map(|edge| self.graph[edge.target()])
+    }
+
+    /// The nodes that have a `T` relationship to `node`.
+    pub fn sources_of<T: Relationship>(&self, node: Node) -> impl Iterator<Item = Node> + '_ {
+        self.graph
+            .edges_directed(node.scene_index, Direction::Incoming)
+            .filter(|edge| edge.weight().weight.is::<T>())
+            .map(|edge| self.graph[edge.source()])
+    }
+
+    /// The `T` relationship from `from` to `to`, if there is one.
+    pub fn relationship<T: Relationship>(&self, from: Node, to: Node) -> Option<&T> {
+        self.graph
+            .edges_connecting(from.scene_index, to.scene_index)
+            .find_map(|edge| edge.weight().weight.downcast_ref::<T>())
+    }
+
+    pub fn relationship_mut<T: Relationship>(&mut self, from: Node, to: Node) -> Option<&mut T> {
+        let edge = self
+            .graph
+            .edges_connecting(from.scene_index, to.scene_index)
+            .find(|edge| edge.weight().weight.is::<T>())?
+            .id();
+        self.graph[edge].weight.downcast_mut::<T>()
+    }
+
     pub fn child_relationships_of(
         &self,
         node: Node,
@@ -137,3 +345,158 @@ impl Scene {
             .next()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Owns {
+        count: u32,
+    }
+
+    impl Relationship for Owns {}
+
+    #[derive(Debug)]
+    struct Targets;
+
+    impl Relationship for Targets {}
+
+    #[test]
+    fn test_typed_relationships() {
+        let mut scene = Scene::new(SharedLock::new(World::new()));
+        let player = scene.create_node();
+        let enemy = scene.create_node();
+        let sword = scene.create_node();
+        let potion = scene.create_node();
+        scene
+            .add_relationship(player, sword, Owns { count: 1 })
+            .unwrap();
+        scene
+            .add_relationship(player, potion, Owns { count: 3 })
+            .unwrap();
+        scene
+            .add_relationship(enemy, potion, Owns { count: 1 })
+            .unwrap();
+        scene.add_relationship(player, enemy, Targets).unwrap();
+        scene.add_relationship(enemy, player, Targets).unwrap();
+
+        assert_eq!(
+            scene.related::<Owns>(player).collect::<HashSet<_>>(),
+            HashSet::from([sword, potion])
+        );
+        assert_eq!(
+            scene.related::<Targets>(player).collect::<Vec<_>>(),
+            vec![enemy]
+        );
+        assert_eq!(
+            scene.sources_of::<Owns>(potion).collect::<HashSet<_>>(),
+            HashSet::from([player, enemy])
+        );
+        assert_eq!(scene.sources_of::<Targets>(potion).count(), 0);
+
+        assert_eq!(
+            scene.relationship::<Owns>(player, potion),
+            Some(&Owns { count: 3 })
+        );
+        assert!(scene.relationship::<Targets>(player, potion).is_none());
+        scene
+            .relationship_mut::<Owns>(player, potion)
+            .unwrap()
+            .count -= 1;
+        assert_eq!(
+            scene.relationship::<Owns>(player, potion),
+            Some(&Owns { count: 2 })
+        );
+
+        let relationship = scene.child_relationships_of(enemy).next().unwrap();
+        assert!(relationship.is::<Owns>() || relationship.is::<Targets>());
+    }
+
+    #[test]
+    fn test_single_parent() {
+        let mut scene = Scene::new(SharedLock::new(World::new()));
+        let root = *scene.root();
+        let a = scene.create_node();
+        let b = scene.create_node();
+        let child = scene.create_node();
+        scene.add_child(root, a).unwrap();
+        scene.add_child(root, b).unwrap();
+        scene.add_child(a, child).unwrap();
+
+        assert!(scene.add_child(b, child).is_err());
+        assert_eq!(scene.ancestors_of(child).collect::<Vec<_>>(), vec![a, root]);
+
+        // reparenting takes removing the old parent first
+        scene.remove_relationship(a, child).unwrap();
+        scene.add_child(b, child).unwrap();
+        assert_eq!(scene.ancestors_of(child).collect::<Vec<_>>(), vec![b, root]);
+        scene.despawn_recursive(a).unwrap();
+        assert!(scene.world().read().is_alive(child.entity));
+    }
+
+    #[test]
+    fn test_find_node() {
+        let world = SharedLock::new(World::new());
+        let mut scene = Scene::new(world.clone());
+        let node = scene.create_node();
+        assert_eq!(scene.find_node(node.entity), Some(node));
+        assert_eq!(scene.find_node(scene.root_entity()), Some(*scene.root()));
+        assert!(scene.add_node(node.entity).is_err());
+
+        let outside = world.write().create_entity();
+        assert_eq!(scene.find_node(outside), None);
+        let added = scene.add_node(outside).unwrap();
+        assert_eq!(scene.find_node(outside), Some(added));
+
+        scene.remove_node(added).unwrap();
+        assert_eq!(scene.find_node(outside), None);
+        assert!(world.read().is_alive(outside));
+        assert!(scene.remove_node(added).is_err());
+    }
+
+    #[test]
+    fn test_despawn_recursive() {
+        let world = SharedLock::new(World::new());
+        let mut scene = Scene::new(world.clone());
+        let root = *scene.root();
+        let parent = scene.create_node();
+        let child = scene.create_node();
+        let grandchild = scene.create_node();
+        let owned = scene.create_node();
+        scene.add_child(root, parent).unwrap();
+        scene.add_child(parent, child).unwrap();
+        scene.add_child(child, grandchild).unwrap();
+        // only `ChildOf` descendants are part of the subtree
+        scene
+            .add_relationship(parent, owned, Owns { count: 1 })
+            .unwrap();
+
+        scene.despawn_recursive(parent).unwrap();
+        for node in [parent, child, grandchild] {
+            assert!(!world.read().is_alive(node.entity));
+            assert_eq!(scene.find_node(node.entity), None);
+        }
+        assert_eq!(scene.find_node(owned.entity), Some(owned));
+        assert_eq!(scene.graph().node_count(), 2);
+
+        assert!(scene.despawn_recursive(parent).is_err());
+        assert!(scene.add_child(root, child).is_err());
+        assert!(scene.despawn_recursive(root).is_err());
+    }
+
+    #[test]
+    fn test_dangling_entity() {
+        let world = SharedLock::new(World::new());
+        let mut scene = Scene::new(world.clone());
+        let root = *scene.root();
+        let node = scene.create_node();
+        world.write().destroy_entity(node.entity).unwrap();
+
+        assert!(scene.add_child(root, node).is_err());
+        assert!(scene.despawn_recursive(node).is_err());
+        // the node can still be removed from the scene
+        scene.remove_node(node).unwrap();
+        assert_eq!(scene.find_node(node.entity), None);
+    }
+}
@@ -0,0 +1,618 @@
+//! Saving [`Scene`]s to text, and loading them back.
+//!
+//! A saved scene lists its nodes, each with a file-local id and every reflected component of the
+//! node's entity, followed by its relationships. Component and relationship types are written by
+//! their full type name, and read by their full or short name from the world's [`TypeRegistry`].
+//! Types missing from the registry are skipped when saving, and are an error when loading.
+//!
+//! ```text
+//! (
+//!     root: 0,
+//!     nodes: [
+//!         (
+//!             id: 0,
+//!             components: {
+//!                 "game::Position": (x: 1.0, y: 2.0),
+//!             },
+//!         ),
+//!         (
+//!             id: 1,
+//!             components: {
+//!                 "game::Target": (0: Entity(0)),
+//!             },
+//!         ),
+//!     ],
+//!     relationships: [
+//!         (from: 0, to: 1, type: "game::Parent", value: ()),
+//!     ],
+//! )
+//! ```
+//!
+//! Entities in component and relationship values are written as the ids of the nodes they refer
+//! to, and point to the new entities once loaded. Saving fails if a value refers to an entity
+//! outside of the scene.
+
+use std::{any::TypeId, collections::HashMap, path::Path};
+
+use katabatic_ecs::{
+    entity::Entity,
+    reflect::{TypeRegistry, Value},
+    world::World,
+};
+use katabatic_util::{error::KResult, kbail, kensure, kerror, lock::SharedLock};
+use petgraph::{algo::kosaraju_scc, prelude::*, visit::IntoEdgeReferences};
+
+use crate::{
+    relationship::{ChildOf, ReflectRelationship},
+    scene::Scene,
+    text::{Parser, Writer},
+};
+
+/// A scene as read from text, before anything is added to a world.
+pub(crate) struct SceneData {
+    pub(crate) root: u64,
+    pub(crate) nodes: Vec<NodeData>,
+    pub(crate) relationships: Vec<RelationshipData>,
+}
+
+pub(crate) struct NodeData {
+    pub(crate) id: u64,
+    pub(crate) components: Vec<(String, Value)>,
+}
+
+pub(crate) struct RelationshipData {
+    pub(crate) from: u64,
+    pub(crate) to: u64,
+    pub(crate) type_name: String,
+    pub(crate) value: Value,
+}
+
+impl Scene {
+    /// Writes the scene's nodes, their reflected components and the relationships between them as
+    /// text. See the [module docs](crate::serialize) for the format.
+    pub fn save(&self) -> KResult<String> {
+        let world = self.world().read();
+        let registry = world
+            .resource::<TypeRegistry>()
+            .ok_or_else(|| kerror!("Scene::save(): the world has no TypeRegistry resource"))?;
+
+        // the root is always written first
+        let mut indices = vec![self.root().scene_index];
+        indices.extend(
+            self.graph()
+                .node_indices()
+                .filter(|&index| index != self.root().scene_index),
+        );
+        let ids = indices
+            .iter()
+            .enumerate()
+            .map(|(id, &index)| (self.graph()[index].entity, id as u32))
+            .collect::<HashMap<_, _>>();
+        let remap = |mut value: Value| -> KResult<Value> {
+            let mut outside = None;
+            value.map_entities(&mut |entity| match ids.get(&entity) {
+                Some(&id) => Entity::new(id, 0),
+                None => {
+                    outside = Some(entity);
+                    entity
+                }
+            });
+            match outside {
+                Some(entity) => Err(kerror!(format!(
+                    "Scene::save(): a value refers to {:?}, which is not in the scene",
+                    entity
+                ))),
+                None => Ok(value),
+            }
+        };
+
+        let mut nodes = Vec::new();
+        for &index in &indices {
+            let entity = self.graph()[index].entity;
+            let type_ids = world
+                .storage()
+                .archetype_of(entity)
+                .map_or(&[][..], |archetype| archetype.type_ids());
+
+            let mut components = Vec::new();
+            for registration in type_ids.iter().filter_map(|&id| registry.get(id)) {
+                let component = world
+                    .storage()
+                    .get_data(entity, registration.type_id())
+                    .unwrap();
+                if let Some(reflect) = registration.as_reflect(&*component) {
+                    components.push((registration.type_name(), remap(reflect.to_value())?));
+                }
+            }
+            components.sort_by_key(|(type_name, _)| *type_name);
+            nodes.push(components);
+        }
+
+        let mut relationships = Vec::new();
+        for edge in self.graph().edge_references() {
+            let relationship = &*edge.weight().weight;
+            let registration = registry
+                .get(relationship.as_any().type_id())
+                .filter(|registration| registration.data::<ReflectRelationship>().is_some())
+                .ok_or_else(|| {
+                    kerror!(format!(
+                        "Scene::save(): relationship {:?} is not registered",
+                        relationship
+                    ))
+                })?;
+            let reflect = registration
+                .data::<ReflectRelationship>()
+                .and_then(|data| data.as_reflect(relationship))
+                .unwrap();
+            relationships.push((
+                ids[&self.graph()[edge.source()].entity],
+                ids[&self.graph()[edge.target()].entity],
+                registration.type_name(),
+                remap(reflect.to_value())?,
+            ));
+        }
+
+        let mut writer = Writer::new();
+        writer.open('(');
+        writer.newline();
+        writer.raw("root: 0,");
+        writer.newline();
+        writer.raw("nodes: ");
+        writer.open('[');
+        for (id, components) in nodes.iter().enumerate() {
+            writer.newline();
+            writer.open('(');
+            writer.newline();
+            writer.raw(&format!("id: {},", id));
+            writer.newline();
+            writer.raw("components: ");
+            writer.open('{');
+            for (type_name, value) in components {
+                writer.newline();
+                writer.string(type_name);
+                writer.raw(": ");
+                writer.value(value);
+                writer.raw(",");
+            }
+            writer.close('}');
+            writer.raw(",");
+            writer.close(')');
+            writer.raw(",");
+        }
+        writer.close(']');
+        writer.raw(",");
+        writer.newline();
+        writer.raw("relationships: ");
+        writer.open('[');
+        for (from, to, type_name, value) in &relationships {
+            writer.newline();
+            writer.raw(&format!("(from: {}, to: {}, type: ", from, to));
+            writer.string(type_name);
+            writer.raw(", value: ");
+            writer.value(value);
+            writer.raw("),");
+        }
+        writer.close(']');
+        writer.raw(",");
+        writer.close(')');
+        writer.raw("\n");
+        Ok(writer.finish())
+    }
+
+    pub fn save_to_file(&self, path: impl AsRef<Path>) -> KResult<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.save()?).map_err(|e| {
+            kerror!(format!(
+                "Scene::save_to_file(): could not write {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Reads a scene saved with [`save`](Self::save), spawning a new entity for each of its nodes
+    /// in `world`.
+    ///
+    /// Fails if the scene's [`ChildOf`] relationships give a node more than one parent or make a
+    /// cycle. Nothing is left in the world if loading fails.
+    pub fn load(world: SharedLock<World>, text: &str) -> KResult<Scene> {
+        let data = parse_scene(text)?;
+        let registry = world
+            .read()
+            .resource::<TypeRegistry>()
+            .ok_or_else(|| kerror!("Scene::load(): the world has no TypeRegistry resource"))?
+            .clone();
+        check_hierarchy(&registry, &data, "Scene::load()")?;
+
+        let mut entities = HashMap::new();
+        {
+            let mut world = world.write();
+            for node in &data.nodes {
+                kensure!(
+                    !entities.contains_key(&node.id),
+                    format!("Scene::load(): node id {} is used twice", node.id)
+                );
+                entities.insert(node.id, world.create_entity());
+            }
+
+            if let Err(e) = populate(&mut world, &data, &entities) {
+                for &entity in entities.values() {
+                    world.destroy_entity(entity)?;
+                }
+                return Err(e);
+            }
+        }
+
+        let root = entities[&data.root];
+        let mut scene = Scene::with_root_entity(world, root);
+        let mut nodes = HashMap::new();
+        nodes.insert(data.root, *scene.root());
+        for node in &data.nodes {
+            if node.id != data.root {
+                nodes.insert(node.id, scene.add_node(entities[&node.id])?);
+            }
+        }
+
+        for relationship in &data.relationships {
+            let (from, to) = (nodes[&relationship.from], nodes[&relationship.to]);
+            let weight = build_relationship(&registry, relationship, &entities)?;
+            scene.insert_edge(from.scene_index, to.scene_index, weight);
+        }
+
+        Ok(scene)
+    }
+
+    pub fn load_from_file(world: SharedLock<World>, path: impl AsRef<Path>) -> KResult<Scene> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            kerror!(format!(
+                "Scene::load_from_file(): could not read {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Self::load(world, &text)
+    }
+}
+
+/// Fails if the [`ChildOf`] relationships in `data` give a node more than one parent, or make a
+/// cycle. Relationships of unregistered types are left for loading to report.
+pub(crate) fn check_hierarchy(
+    registry: &TypeRegistry,
+    data: &SceneData,
+    context: &str,
+) -> KResult<()> {
+    let child_of = TypeId::of::<ChildOf>();
+    let mut hierarchy = DiGraphMap::<u64, ()>::new();
+    let mut parents = HashMap::<u64, usize>::new();
+    for relationship in &data.relationships {
+        let type_id = registry
+            .get_with_name(&relationship.type_name)
+            .map(|registration| registration.type_id());
+        if type_id == Some(child_of) {
+            hierarchy.add_edge(relationship.from, relationship.to, ());
+            *parents.entry(relationship.to).or_default() += 1;
+        }
+    }
+
+    let mut shared = parents
+        .into_iter()
+        .filter(|&(_, count)| count > 1)
+        .map(|(id, _)| id)
+        .collect::<Vec<_>>();
+    shared.sort();
+    kensure!(
+        shared.is_empty(),
+        format!("{}: nodes {:?} have more than one parent", context, shared)
+    );
+
+    let cycle = kosaraju_scc(&hierarchy).into_iter().find(|component| {
+        component.len() > 1 || hierarchy.contains_edge(component[0], component[0])
+    });
+    if let Some(mut cycle) = cycle {
+        cycle.sort();
+        kbail!(format!(
+            "{}: nodes {:?} are each other's ancestors",
+            context, cycle
+        ));
+    }
+    Ok(())
+}
+
+/// Points entity references at the newly spawned entities.
+pub(crate) fn remap(value: &Value, entities: &HashMap<u64, Entity>) -> KResult<Value> {
+    let mut value = value.clone();
+    let mut missing = None;
+    value.map_entities(&mut |entity| match entities.get(&(entity.id() as u64)) {
+        Some(&entity) => entity,
+        None => {
+            missing = Some(entity.id());
+            entity
+        }
+    });
+    match missing {
+        Some(id) => Err(kerror!(format!(
+            "Scene::load(): a value refers to node {}, which does not exist",
+            id
+        ))),
+        None => Ok(value),
+    }
+}
+
+/// Checks the relationships, and inserts every node's components.
+pub(crate) fn populate(
+    world: &mut World,
+    data: &SceneData,
+    entities: &HashMap<u64, Entity>,
+) -> KResult<()> {
+    let registry = world
+        .resource::<TypeRegistry>()
+        .ok_or_else(|| kerror!("Scene::load(): the world has no TypeRegistry resource"))?
+        .clone();
+
+    kensure!(
+        entities.contains_key(&data.root),
+        format!("Scene::load(): root node {} does not exist", data.root)
+    );
+    for relationship in &data.relationships {
+        for id in [relationship.from, relationship.to] {
+            kensure!(
+                entities.contains_key(&id),
+                format!(
+                    "Scene::load(): a relationship refers to node {}, which does not exist",
+                    id
+                )
+            );
+        }
+        // build one now so bad relationships fail before anything is added
+        build_relationship(&registry, relationship, entities)?;
+    }
+
+    for node in &data.nodes {
+        for (type_name, value) in &node.components {
+            let registration = registry.get_with_name(type_name).ok_or_else(|| {
+                kerror!(format!(
+                    "Scene::load(): component type {} is not registered",
+                    type_name
+                ))
+            })?;
+            let component = registration.from_value(&remap(value, entities)?)?;
+            registration.insert(world, entities[&node.id], component)?;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn build_relationship(
+    registry: &TypeRegistry,
+    relationship: &RelationshipData,
+    entities: &HashMap<u64, Entity>,
+) -> KResult<Box<dyn crate::relationship::Relationship>> {
+    let registration = registry
+        .get_with_name(&relationship.type_name)
+        .ok_or_else(|| {
+            kerror!(format!(
+                "Scene::load(): relationship type {} is not registered",
+                relationship.type_name
+            ))
+        })?;
+    let data = registration.data::<ReflectRelationship>().ok_or_else(|| {
+        kerror!(format!(
+            "Scene::load(): {} is not registered as a relationship",
+            relationship.type_name
+        ))
+    })?;
+    let value = registration.from_value(&remap(&relationship.value, entities)?)?;
+    Ok(data.from_reflect(value).unwrap())
+}
+
+pub(crate) fn parse_scene(text: &str) -> KResult<SceneData> {
+    let mut parser = Parser::new(text);
+    let mut nodes = Vec::new();
+    let mut relationships = Vec::new();
+
+    parser.expect("(")?;
+    field(&mut parser, "root")?;
+    let root = parser.uint()?;
+    parser.expect(",")?;
+
+    field(&mut parser, "nodes")?;
+    parser.expect("[")?;
+    parser.list("]", |parser| {
+        parser.expect("(")?;
+        field(parser, "id")?;
+        let id = parser.uint()?;
+        parser.expect(",")?;
+        field(parser, "components")?;
+        parser.expect("{")?;
+        let mut components = Vec::new();
+        parser.list("}", |parser| {
+            let type_name = parser.string()?;
+            parser.expect(":")?;
+            components.push((type_name, parser.value()?));
+            Ok(())
+        })?;
+        parser.eat(",");
+        parser.expect(")")?;
+        nodes.push(NodeData { id, components });
+        Ok(())
+    })?;
+    parser.expect(",")?;
+
+    field(&mut parser, "relationships")?;
+    parser.expect("[")?;
+    parser.list("]", |parser| {
+        parser.expect("(")?;
+        field(parser, "from")?;
+        let from = parser.uint()?;
+        parser.expect(",")?;
+        field(parser, "to")?;
+        let to = parser.uint()?;
+        parser.expect(",")?;
+        field(parser, "type")?;
+        let type_name = parser.string()?;
+        parser.expect(",")?;
+        field(parser, "value")?;
+        let value = parser.value()?;
+        parser.eat(",");
+        parser.expect(")")?;
+        relationships.push(RelationshipData {
+            from,
+            to,
+            type_name,
+            value,
+        });
+        Ok(())
+    })?;
+    parser.eat(",");
+    parser.expect(")")?;
+    parser.end()?;
+
+    Ok(SceneData {
+        root,
+        nodes,
+        relationships,
+    })
+}
+
+fn field(parser: &mut Parser, name: &str) -> KResult<()> {
+    let start = parser.ident()?;
+    if start != name {
+        return Err(parser.error(format!("expected field `{}`, found `{}`", name, start)));
+    }
+    parser.expect(":")
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_ecs::reflect::Reflect;
+
+    use super::*;
+    use crate::relationship::{register_relationship, ChildOf, Relationship};
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Position {
+        x: f32,
+        y: f32,
+    }
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Target(Option<Entity>);
+
+    #[derive(Debug, PartialEq, Reflect)]
+    struct Parent {
+        since: u32,
+    }
+
+    impl Relationship for Parent {}
+
+    fn new_world() -> SharedLock<World> {
+        let mut registry = TypeRegistry::new();
+        registry.register::<Position>();
+        registry.register::<Target>();
+        register_relationship::<Parent>(&mut registry);
+        let mut world = World::new();
+        world.insert_resource(registry);
+        SharedLock::new(world)
+    }
+
+    #[test]
+    fn test_save_load() {
+        let world = new_world();
+        let mut scene = Scene::new(world.clone());
+        let root = *scene.root();
+        world
+            .write()
+            .insert_component(root.entity, Position { x: 1.0, y: 2.0 })
+            .unwrap();
+        let child = scene.create_node_with(Target(Some(root.entity)));
+        scene
+            .add_relationship(root, child, Parent { since: 3 })
+            .unwrap();
+
+        let text = scene.save().unwrap();
+
+        // load into a fresh world, where the entities will be different
+        let other = new_world();
+        other.write().create_entity();
+        let loaded = Scene::load(other.clone(), &text).unwrap();
+        assert_eq!(loaded.save().unwrap(), text);
+
+        let root = *loaded.root();
+        let child = loaded.children_of(root).next().unwrap();
+        let other = other.read();
+        assert_eq!(
+            *other.get_component::<Position>(root.entity).unwrap(),
+            Position { x: 1.0, y: 2.0 }
+        );
+        assert_eq!(
+            *other.get_component::<Target>(child.entity).unwrap(),
+            Target(Some(root.entity))
+        );
+        let relationship = &*loaded.graph().edge_weights().next().unwrap().weight;
+        assert_eq!(
+            relationship.downcast_ref::<Parent>(),
+            Some(&Parent { since: 3 })
+        );
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let world = new_world();
+        let text = r#"(
+            root: 0,
+            nodes: [
+                (id: 0, components: { "Position": (x: 1.0, y: 2.0) }),
+                (id: 1, components: { "Target": (0: Some(0: Entity(5))) }),
+            ],
+            relationships: [],
+        )"#;
+
+        assert!(Scene::load(world.clone(), text).is_err());
+        // nothing from the failed load is left behind
+        assert_eq!(world.read().entities().len(), 0);
+
+        let text = text.replace("Entity(5)", "Entity(0)");
+        let scene = Scene::load(world.clone(), &text).unwrap();
+        assert_eq!(world.read().entities().len(), 2);
+        assert_eq!(scene.graph().node_count(), 2);
+
+        let text = text.replace("\"Position\"", "\"Velocity\"");
+        assert!(Scene::load(world.clone(), &text).is_err());
+        assert!(Scene::load(world.clone(), "(root: 0, nodes: [], relationships: [])").is_err());
+
+        // hierarchy cycles are refused
+        let mut registry = world.read().resource::<TypeRegistry>().unwrap().clone();
+        register_relationship::<ChildOf>(&mut registry);
+        world.write().insert_resource(registry);
+        let cyclic = r#"(
+            root: 0,
+            nodes: [(id: 0, components: {}), (id: 1, components: {}), (id: 2, components: {})],
+            relationships: [
+                (from: 1, to: 2, type: "ChildOf", value: ()),
+                (from: 2, to: 1, type: "ChildOf", value: ()),
+            ],
+        )"#;
+        let before = world.read().entities().len();
+        assert!(Scene::load(world.clone(), cyclic).is_err());
+        assert_eq!(world.read().entities().len(), before);
+
+        // and so are nodes with two parents
+        let two_parents = cyclic.replace("from: 2, to: 1", "from: 0, to: 2");
+        let Err(error) = Scene::load(world.clone(), &two_parents) else {
+            panic!("loaded a node with two parents");
+        };
+        assert!(error.to_string().contains("more than one parent"));
+        assert_eq!(world.read().entities().len(), before);
+    }
+
+    #[test]
+    fn test_save_outside_reference() {
+        let world = new_world();
+        let mut scene = Scene::new(world.clone());
+        let outside = world.write().create_entity();
+        scene.create_node_with(Target(Some(outside)));
+        assert!(scene.save().is_err());
+    }
+}
@@ -0,0 +1,456 @@
+//! A small RON-like text format for reflected [`Value`]s.
+//!
+//! | Value | Text |
+//! |-------|------|
+//! | `Unit` | `()` |
+//! | `Bool` | `true`, `false` |
+//! | `Int`, `UInt` | `-3`, `3` |
+//! | `Float` | `1.5`, `1e-3`, `inf`, `-inf`, `NaN` |
+//! | `Char` | `'a'` |
+//! | `String` | `"hello\n"` |
+//! | `Entity` | `Entity(3)` |
+//! | `Struct` | `(x: 1.0, y: 2.0)`, or `(0: 1.0, 1: 2.0)` for tuple structs |
+//! | `Enum` | `None`, `Some(0: 3)`, `Circle(radius: 1.0)` |
+//!
+//! Whitespace is ignored, trailing commas are allowed, and `//` starts a comment that runs to the
+//! end of the line.
+
+use std::fmt::Write;
+
+use katabatic_ecs::{entity::Entity, reflect::Value};
+use katabatic_util::{
+    error::{KError, KResult},
+    kerror,
+};
+
+/// Writes values, and the punctuation around them, with indentation.
+pub struct Writer {
+    out: String,
+    indent: usize,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self {
+            out: String::new(),
+            indent: 0,
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+
+    pub fn raw(&mut self, text: &str) {
+        self.out.push_str(text);
+    }
+
+    /// Opens a bracket, and indents the lines after it.
+    pub fn open(&mut self, bracket: char) {
+        self.out.push(bracket);
+        self.indent += 1;
+    }
+
+    /// Closes a bracket on a line of its own.
+    pub fn close(&mut self, bracket: char) {
+        self.indent -= 1;
+        self.newline();
+        self.out.push(bracket);
+    }
+
+    pub fn newline(&mut self) {
+        self.out.push('\n');
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+    }
+
+    pub fn string(&mut self, value: &str) {
+        write!(self.out, "{:?}", value).unwrap();
+    }
+
+    /// Writes a value on one line, unless it's a struct or enum with fields.
+    pub fn value(&mut self, value: &Value) {
+        match value {
+            Value::Unit => self.raw("()"),
+            Value::Bool(value) => write!(self.out, "{}", value).unwrap(),
+            Value::Int(value) => write!(self.out, "{}", value).unwrap(),
+            Value::UInt(value) => write!(self.out, "{}", value).unwrap(),
+            // `{:?}` always writes a `.` or an exponent, so floats stay floats
+            Value::Float(value) => write!(self.out, "{:?}", value).unwrap(),
+            Value::Char(value) => write!(self.out, "{:?}", value).unwrap(),
+            Value::String(value) => self.string(value),
+            Value::Entity(value) => write!(self.out, "Entity({})", value.id()).unwrap(),
+            Value::Struct(fields) => self.fields(fields),
+            Value::Enum { variant, fields } => {
+                self.raw(variant);
+                if !fields.is_empty() {
+                    self.fields(fields);
+                }
+            }
+        }
+    }
+
+    fn fields(&mut self, fields: &[(String, Value)]) {
+        if fields.is_empty() {
+            self.raw("()");
+            return;
+        }
+
+        // short runs of primitives stay on one line
+        let nested = fields
+            .iter()
+            .any(|(_, value)| matches!(value, Value::Struct(_) | Value::Enum { .. }));
+        if !nested && fields.len() <= 4 {
+            self.raw("(");
+            for (index, (name, value)) in fields.iter().enumerate() {
+                if index > 0 {
+                    self.raw(", ");
+                }
+                self.raw(name);
+                self.raw(": ");
+                self.value(value);
+            }
+            self.raw(")");
+            return;
+        }
+
+        self.open('(');
+        for (name, value) in fields {
+            self.newline();
+            self.raw(name);
+            self.raw(": ");
+            self.value(value);
+            self.raw(",");
+        }
+        self.close(')');
+    }
+}
+
+/// Reads values, and the punctuation around them.
+pub struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Self { text, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with("//") {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// The error for malformed text, pointing at the current line and column.
+    pub fn error(&self, message: impl std::fmt::Display) -> KError {
+        let before = &self.text[..self.pos];
+        let line = before.matches('\n').count() + 1;
+        let column = before.len() - before.rfind('\n').map_or(0, |index| index + 1) + 1;
+        kerror!(format!("Parser: {} at {}:{}", message, line, column))
+    }
+
+    /// Skips `token` if it's next, returning whether it was.
+    pub fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn expect(&mut self, token: &str) -> KResult<()> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected `{}`", token)))
+        }
+    }
+
+    /// Fails unless only whitespace and comments are left.
+    pub fn end(&mut self) -> KResult<()> {
+        self.skip_whitespace();
+        if self.rest().is_empty() {
+            Ok(())
+        } else {
+            Err(self.error("expected end of input"))
+        }
+    }
+
+    /// Calls `item` for each item of a comma separated list, up to and including `close`.
+    pub fn list(
+        &mut self,
+        close: &str,
+        mut item: impl FnMut(&mut Self) -> KResult<()>,
+    ) -> KResult<()> {
+        loop {
+            if self.eat(close) {
+                return Ok(());
+            }
+            item(self)?;
+            if !self.eat(",") {
+                return self.expect(close);
+            }
+        }
+    }
+
+    /// Reads an identifier, or the digits of a tuple struct's field name.
+    pub fn ident(&mut self) -> KResult<&'a str> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected an identifier"));
+        }
+        self.pos += len;
+        Ok(&rest[..len])
+    }
+
+    pub fn string(&mut self) -> KResult<String> {
+        self.expect("\"")?;
+        let mut value = String::new();
+        loop {
+            match self.next_char()? {
+                '"' => return Ok(value),
+                '\\' => value.push(self.escape()?),
+                c => value.push(c),
+            }
+        }
+    }
+
+    pub fn uint(&mut self) -> KResult<u64> {
+        match self.value()? {
+            Value::UInt(value) => Ok(value),
+            _ => Err(self.error("expected an unsigned integer")),
+        }
+    }
+
+    fn next_char(&mut self) -> KResult<char> {
+        let c = self
+            .rest()
+            .chars()
+            .next()
+            .ok_or_else(|| self.error("unexpected end of input"))?;
+        self.pos += c.len_utf8();
+        Ok(c)
+    }
+
+    fn escape(&mut self) -> KResult<char> {
+        Ok(match self.next_char()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            '\\' => '\\',
+            '\'' => '\'',
+            '"' => '"',
+            'u' => {
+                self.expect("{")?;
+                let rest = self.rest();
+                let len = rest.find('}').unwrap_or(rest.len());
+                let c = u32::from_str_radix(&rest[..len], 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| self.error("invalid unicode escape"))?;
+                self.pos += len;
+                self.expect("}")?;
+                c
+            }
+            c => return Err(self.error(format!("unknown escape `\\{}`", c))),
+        })
+    }
+
+    fn number(&mut self) -> KResult<Value> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')))
+            .unwrap_or(rest.len());
+        let text = &rest[..len];
+        let value = if text.contains(['.', 'e', 'E']) || text.ends_with("inf") {
+            text.parse().ok().map(Value::Float)
+        } else if text.starts_with('-') {
+            text.parse().ok().map(Value::Int)
+        } else {
+            text.parse().ok().map(Value::UInt)
+        };
+        let value = value.ok_or_else(|| self.error(format!("invalid number `{}`", text)))?;
+        self.pos += len;
+        Ok(value)
+    }
+
+    fn fields(&mut self) -> KResult<Vec<(String, Value)>> {
+        let mut fields = Vec::new();
+        self.list(")", |parser| {
+            let name = parser.ident()?.to_string();
+            parser.expect(":")?;
+            fields.push((name, parser.value()?));
+            Ok(())
+        })?;
+        Ok(fields)
+    }
+
+    pub fn value(&mut self) -> KResult<Value> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let Some(c) = rest.chars().next() else {
+            return Err(self.error("expected a value"));
+        };
+
+        if c == '(' {
+            self.pos += 1;
+            let fields = self.fields()?;
+            return Ok(if fields.is_empty() {
+                Value::Unit
+            } else {
+                Value::Struct(fields)
+            });
+        }
+        if c == '"' {
+            return self.string().map(Value::String);
+        }
+        if c == '\'' {
+            self.pos += 1;
+            let value = match self.next_char()? {
+                '\\' => self.escape()?,
+                c => c,
+            };
+            self.expect("'")?;
+            return Ok(Value::Char(value));
+        }
+        if c.is_ascii_digit() || c == '-' || c == '+' {
+            return self.number();
+        }
+
+        let ident = self.ident()?;
+        match ident {
+            "true" => return Ok(Value::Bool(true)),
+            "false" => return Ok(Value::Bool(false)),
+            "inf" => return Ok(Value::Float(f64::INFINITY)),
+            "NaN" => return Ok(Value::Float(f64::NAN)),
+            "Entity" => {
+                // `Entity(3)` is an entity, `Entity(0: 3)` is a variant named `Entity`
+                let start = self.pos;
+                if self.eat("(") {
+                    if let Ok(Value::UInt(id)) = self.number_after_whitespace() {
+                        if self.eat(")") {
+                            let id = u32::try_from(id)
+                                .map_err(|_| self.error("entity id out of range"))?;
+                            return Ok(Value::Entity(Entity::new(id, 0)));
+                        }
+                    }
+                }
+                self.pos = start;
+            }
+            _ => {}
+        }
+
+        let fields = if self.eat("(") {
+            self.fields()?
+        } else {
+            Vec::new()
+        };
+        Ok(Value::Enum {
+            variant: ident.to_string(),
+            fields,
+        })
+    }
+
+    fn number_after_whitespace(&mut self) -> KResult<Value> {
+        self.skip_whitespace();
+        self.number()
+    }
+}
+
+/// Writes a single value.
+pub fn to_string(value: &Value) -> String {
+    let mut writer = Writer::new();
+    writer.value(value);
+    writer.finish()
+}
+
+/// Reads a single value.
+pub fn from_str(text: &str) -> KResult<Value> {
+    let mut parser = Parser::new(text);
+    let value = parser.value()?;
+    parser.end()?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = Value::Struct(vec![
+            ("unit".to_string(), Value::Unit),
+            ("int".to_string(), Value::Int(-3)),
+            ("uint".to_string(), Value::UInt(3)),
+            ("float".to_string(), Value::Float(1.0)),
+            ("small".to_string(), Value::Float(1e-12)),
+            ("inf".to_string(), Value::Float(f64::NEG_INFINITY)),
+            ("char".to_string(), Value::Char('\'')),
+            (
+                "string".to_string(),
+                Value::String("a \"quoted\"\nline é".to_string()),
+            ),
+            ("entity".to_string(), Value::Entity(Entity::new(7, 0))),
+            (
+                "enum".to_string(),
+                Value::Enum {
+                    variant: "Entity".to_string(),
+                    fields: vec![("0".to_string(), Value::Bool(true))],
+                },
+            ),
+            (
+                "none".to_string(),
+                Value::Enum {
+                    variant: "None".to_string(),
+                    fields: Vec::new(),
+                },
+            ),
+        ]);
+
+        let text = to_string(&value);
+        assert_eq!(from_str(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            from_str("// comment\n(x: 1.0, y: 2,)").unwrap(),
+            Value::Struct(vec![
+                ("x".to_string(), Value::Float(1.0)),
+                ("y".to_string(), Value::UInt(2)),
+            ])
+        );
+
+        let error = from_str("(\n    x: 1.0\n    y: 2.0\n)").unwrap_err();
+        assert_eq!(error.desc.as_deref(), Some("Parser: expected `)` at 3:5"));
+        assert!(from_str("\"unterminated").is_err());
+        assert!(from_str("1.0 2.0").is_err());
+    }
+}
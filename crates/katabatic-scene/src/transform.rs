@@ -0,0 +1,430 @@
+use std::collections::HashSet;
+
+use glam::{Mat4, Quat, Vec3};
+use katabatic_ecs::reflect::Reflect;
+use petgraph::graph::NodeIndex;
+
+use crate::{relationship::ChildOf, scene::Scene};
+
+/// A node's position, rotation and scale relative to its parent.
+///
+/// Nodes are parented with [`ChildOf`] relationships. Their position in the world is written to
+/// their [`GlobalTransform`] by [`Scene::propagate_transforms`].
+#[derive(Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: Vec3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Self = Self {
+        translation: Vec3::ZERO,
+        rotation: Quat::IDENTITY,
+        scale: Vec3::ONE,
+    };
+
+    pub fn from_xyz(x: f32, y: f32, z: f32) -> Self {
+        Self::from_translation(Vec3::new(x, y, z))
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Self {
+            rotation,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn from_scale(scale: Vec3) -> Self {
+        Self {
+            scale,
+            ..Self::IDENTITY
+        }
+    }
+
+    pub fn with_translation(mut self, translation: Vec3) -> Self {
+        self.translation = translation;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: Vec3) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The matrix that scales, then rotates, then translates.
+    pub fn compute_matrix(&self) -> Mat4 {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+}
+
+/// A node's transform relative to the world, computed from its own and its ancestors'
+/// [`Transform`]s by [`Scene::propagate_transforms`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalTransform(Mat4);
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl From<Transform> for GlobalTransform {
+    fn from(transform: Transform) -> Self {
+        Self(transform.compute_matrix())
+    }
+}
+
+impl From<Mat4> for GlobalTransform {
+    fn from(matrix: Mat4) -> Self {
+        Self(matrix)
+    }
+}
+
+impl GlobalTransform {
+    pub const IDENTITY: Self = Self(Mat4::IDENTITY);
+
+    pub fn matrix(&self) -> Mat4 {
+        self.0
+    }
+
+    pub fn translation(&self) -> Vec3 {
+        self.0.w_axis.truncate()
+    }
+
+    pub fn to_scale_rotation_translation(&self) -> (Vec3, Quat, Vec3) {
+        self.0.to_scale_rotation_translation()
+    }
+
+    /// The global transform of a child with the given local transform.
+    pub fn mul_transform(&self, transform: &Transform) -> Self {
+        Self(self.0 * transform.compute_matrix())
+    }
+}
+
+impl Scene {
+    /// Updates the [`GlobalTransform`] of every node with a [`Transform`], walking the
+    /// [`ChildOf`] relationships down from the [root](Scene::root).
+    ///
+    /// Only nodes whose `Transform` changed since the last pass, that were given a new parent or
+    /// lost theirs, or that have no `GlobalTransform` yet are recomputed, along with their
+    /// descendants. Subtrees with none of those are skipped entirely. Nodes without a `Transform`
+    /// pass their parent's global transform on to their children unchanged.
+    ///
+    /// Nodes without a `ChildOf` parent other than the root are propagated as if each were a
+    /// root itself, so their global transform is their own.
+    pub fn propagate_transforms(&mut self) {
+        let world = self.world().clone();
+        let mut world = world.write();
+        let this_run = world.increment_change_tick();
+        let last_run = self.last_propagation;
+        self.last_propagation = this_run;
+
+        let mut dirty = std::mem::take(&mut self.transforms_dirty);
+        for index in self.graph().node_indices() {
+            let entity = self.graph()[index].entity;
+            if let Some(ticks) = world.component_ticks::<Transform>(entity) {
+                if ticks.is_changed(last_run, this_run)
+                    || !world.has_component::<GlobalTransform>(entity)
+                {
+                    dirty.insert(index);
+                }
+            }
+        }
+        if dirty.is_empty() {
+            return;
+        }
+
+        // every node on the way down to a dirty node
+        let mut on_path = HashSet::new();
+        for &index in &dirty {
+            let mut index = index;
+            while on_path.insert(index) {
+                match self.child_of_parent(index) {
+                    Some(parent) => index = parent,
+                    None => break,
+                }
+            }
+        }
+
+        let mut visited = HashSet::new();
+        let root = self.root().scene_index;
+        let mut stack = vec![(root, GlobalTransform::IDENTITY, false)];
+        stack.extend(
+            on_path
+                .iter()
+                .filter(|&&index| index != root && self.child_of_parent(index).is_none())
+                .map(|&index| (index, GlobalTransform::IDENTITY, false)),
+        );
+        while let Some((index, parent, parent_dirty)) = stack.pop() {
+            // a `ChildOf` cycle would otherwise be walked forever
+            if !visited.insert(index) {
+                continue;
+            }
+
+            let entity = self.graph()[index].entity;
+            let is_dirty = parent_dirty || dirty.contains(&index);
+            let transform = world.get_component::<Transform>(entity).map(|t| *t);
+            let global = match transform {
+                Some(transform) if is_dirty => {
+                    let global = parent.mul_transform(&transform);
+                    if world.has_component::<GlobalTransform>(entity) {
+                        *world.get_component_mut::<GlobalTransform>(entity).unwrap() = global;
+                    } else {
+                        world.insert_component(entity, global).unwrap();
+                    }
+                    global
+                }
+                Some(_) => *world.get_component::<GlobalTransform>(entity).unwrap(),
+                None => parent,
+            };
+
+            for child in self.child_of_children(index) {
+                if is_dirty || on_path.contains(&child) {
+                    stack.push((child, global, is_dirty));
+                }
+            }
+        }
+    }
+
+    fn child_of_children(&self, index: NodeIndex) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.related::<ChildOf>(self.graph()[index])
+            .map(|node| node.scene_index)
+    }
+
+    fn child_of_parent(&self, index: NodeIndex) -> Option<NodeIndex> {
+        self.sources_of::<ChildOf>(self.graph()[index])
+            .next()
+            .map(|node| node.scene_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use katabatic_ecs::{reflect::TypeRegistry, world::World};
+    use katabatic_util::lock::SharedLock;
+
+    use super::*;
+    use crate::{prefab::Prefab, relationship::register_relationship};
+
+    fn global_translation(scene: &Scene, entity: katabatic_ecs::entity::Entity) -> Vec3 {
+        scene
+            .world()
+            .read()
+            .get_component::<GlobalTransform>(entity)
+            .unwrap()
+            .translation()
+    }
+
+    #[test]
+    fn test_propagate_transforms() {
+        let world = SharedLock::new(World::new());
+        let mut scene = Scene::new(world.clone());
+        let root = *scene.root();
+
+        let parent = scene.create_node_with(Transform::from_xyz(1.0, 0.0, 0.0));
+        let child =
+            scene.create_node_with(Transform::from_xyz(0.0, 1.0, 0.0).with_scale(Vec3::splat(2.0)));
+        let grandchild = scene.create_node_with(Transform::from_xyz(0.0, 0.0, 1.0));
+        let other = scene.create_node_with(Transform::from_xyz(5.0, 0.0, 0.0));
+        // a plain node without a transform passes its parent's transform through
+        let group = scene.create_node();
+        scene.add_child(root, parent).unwrap();
+        scene.add_child(parent, child).unwrap();
+        scene.add_child(child, group).unwrap();
+        scene.add_child(group, grandchild).unwrap();
+        scene.add_child(root, other).unwrap();
+
+        scene.propagate_transforms();
+        assert_eq!(
+            global_translation(&scene, parent.entity),
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            global_translation(&scene, child.entity),
+            Vec3::new(1.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            global_translation(&scene, grandchild.entity),
+            Vec3::new(1.0, 1.0, 2.0)
+        );
+        assert_eq!(
+            global_translation(&scene, other.entity),
+            Vec3::new(5.0, 0.0, 0.0)
+        );
+        assert!(!world.read().has_component::<GlobalTransform>(group.entity));
+
+        // only the changed subtree is recomputed
+        let before = world.read().change_tick();
+        world
+            .read()
+            .get_component_mut::<Transform>(child.entity)
+            .unwrap()
+            .translation
+            .y = 3.0;
+        scene.propagate_transforms();
+        let world = world.read();
+        let changed = |entity| {
+            world
+                .component_ticks::<GlobalTransform>(entity)
+                .unwrap()
+                .changed
+                .get()
+                > before.get()
+        };
+        assert!(changed(child.entity) && changed(grandchild.entity));
+        assert!(!changed(parent.entity) && !changed(other.entity));
+        drop(world);
+        assert_eq!(
+            global_translation(&scene, grandchild.entity),
+            Vec3::new(1.0, 3.0, 2.0)
+        );
+    }
+
+    #[test]
+    fn test_reparent() {
+        let world = SharedLock::new(World::new());
+        let mut scene = Scene::new(world.clone());
+        let root = *scene.root();
+        let a = scene.create_node_with(Transform::from_xyz(1.0, 0.0, 0.0));
+        let b = scene.create_node_with(Transform::from_xyz(2.0, 0.0, 0.0));
+        let child = scene.create_node_with(Transform::IDENTITY);
+        scene.add_child(root, a).unwrap();
+        scene.add_child(root, b).unwrap();
+        scene.add_child(a, child).unwrap();
+        scene.propagate_transforms();
+        assert_eq!(
+            global_translation(&scene, child.entity),
+            Vec3::new(1.0, 0.0, 0.0)
+        );
+
+        scene.remove_relationship(a, child).unwrap();
+        scene.add_child(b, child).unwrap();
+        scene.propagate_transforms();
+        assert_eq!(
+            global_translation(&scene, child.entity),
+            Vec3::new(2.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_orphans() {
+        let world = SharedLock::new(World::new());
+        let mut scene = Scene::new(world.clone());
+        let root = *scene.root();
+        let a = scene.create_node_with(Transform::from_xyz(1.0, 0.0, 0.0));
+        let b = scene.create_node_with(Transform::from_xyz(2.0, 0.0, 0.0));
+        let child_a = scene.create_node_with(Transform::from_xyz(0.0, 1.0, 0.0));
+        let child_b = scene.create_node_with(Transform::from_xyz(0.0, 0.0, 1.0));
+        let grandchild = scene.create_node_with(Transform::IDENTITY);
+        scene.add_child(root, a).unwrap();
+        scene.add_child(root, b).unwrap();
+        scene.add_child(a, child_a).unwrap();
+        scene.add_child(b, child_b).unwrap();
+        scene.add_child(child_b, grandchild).unwrap();
+        scene.propagate_transforms();
+        assert_eq!(
+            global_translation(&scene, child_a.entity),
+            Vec3::new(1.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            global_translation(&scene, grandchild.entity),
+            Vec3::new(2.0, 0.0, 1.0)
+        );
+
+        // cut off from the root, they're transformed from the origin
+        scene.remove_relationship(a, child_a).unwrap();
+        scene.remove_node(b).unwrap();
+        scene.propagate_transforms();
+        assert_eq!(
+            global_translation(&scene, child_a.entity),
+            Vec3::new(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            global_translation(&scene, grandchild.entity),
+            Vec3::new(0.0, 0.0, 1.0)
+        );
+
+        // and still follow their own changes
+        world
+            .read()
+            .get_component_mut::<Transform>(child_b.entity)
+            .unwrap()
+            .translation
+            .x = 3.0;
+        scene.propagate_transforms();
+        assert_eq!(
+            global_translation(&scene, grandchild.entity),
+            Vec3::new(3.0, 0.0, 1.0)
+        );
+    }
+
+    #[test]
+    fn test_save_load_transforms() {
+        let new_world = || {
+            let mut registry = TypeRegistry::new();
+            registry.register::<Transform>();
+            register_relationship::<ChildOf>(&mut registry);
+            let mut world = World::new();
+            world.insert_resource(registry);
+            SharedLock::new(world)
+        };
+        let transform = Transform::from_xyz(1.0, 2.0, 3.0)
+            .with_rotation(Quat::from_rotation_y(1.0))
+            .with_scale(Vec3::splat(2.0));
+
+        let mut scene = Scene::new(new_world());
+        let root = *scene.root();
+        let child = scene.create_node_with(transform);
+        scene.add_child(root, child).unwrap();
+        let text = scene.save().unwrap();
+
+        let world = new_world();
+        let mut loaded = Scene::load(world.clone(), &text).unwrap();
+        let child = loaded.children_of(*loaded.root()).next().unwrap();
+        assert_eq!(
+            *world
+                .read()
+                .get_component::<Transform>(child.entity)
+                .unwrap(),
+            transform
+        );
+        loaded.propagate_transforms();
+        assert_eq!(
+            global_translation(&loaded, child.entity),
+            Vec3::new(1.0, 2.0, 3.0)
+        );
+
+        // prefab instances keep their transforms too
+        let prefab = Prefab::from_scene(&scene).unwrap();
+        let root = *loaded.root();
+        let id = loaded.instantiate(&prefab, root).unwrap();
+        let instance = loaded.instance(id).unwrap();
+        let instanced = instance.nodes().find(|&node| node != instance.root());
+        assert_eq!(
+            *world
+                .read()
+                .get_component::<Transform>(instanced.unwrap().entity)
+                .unwrap(),
+            transform
+        );
+    }
+}
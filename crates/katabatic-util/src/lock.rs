@@ -33,33 +33,55 @@ impl<T> Lock<T> {
         ReadWrite::new(self)
     }
 
-    pub fn map_read<U, F>(&self, f: F) -> MapRead<'_, U>
+    pub fn map_read<U: ?Sized, F>(&self, f: F) -> MapRead<'_, U>
     where
         F: FnOnce(&T) -> &U,
     {
         MapRead::new(self, f)
     }
 
-    pub fn map_write<U, F>(&self, f: F) -> MapWrite<'_, U>
+    pub fn map_write<U: ?Sized, F>(&self, f: F) -> MapWrite<'_, U>
     where
         F: FnOnce(&mut T) -> &mut U,
     {
         MapWrite::new(self, f)
     }
 
-    pub fn try_map_read<U, F>(&self, f: F) -> Option<MapRead<'_, U>>
+    pub fn try_map_read<U: ?Sized, F>(&self, f: F) -> Option<MapRead<'_, U>>
     where
         F: FnOnce(&T) -> &U,
     {
         MapRead::try_new(self, f)
     }
 
-    pub fn try_map_write<U, F>(&self, f: F) -> Option<MapWrite<'_, U>>
+    pub fn try_map_write<U: ?Sized, F>(&self, f: F) -> Option<MapWrite<'_, U>>
     where
         F: FnOnce(&mut T) -> &mut U,
     {
         MapWrite::try_new(self, f)
     }
+
+    pub fn filter_map_read<U: ?Sized, F>(&self, f: F) -> Option<MapRead<'_, U>>
+    where
+        F: FnOnce(&T) -> Option<&U>,
+    {
+        MapRead::filter_new(self, f)
+    }
+
+    pub fn filter_map_write<U: ?Sized, F>(&self, f: F) -> Option<MapWrite<'_, U>>
+    where
+        F: FnOnce(&mut T) -> Option<&mut U>,
+    {
+        MapWrite::filter_new(self, f)
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
 }
 
 impl<T: Clone> Clone for Lock<T> {
@@ -94,7 +116,7 @@ impl<'a, T> Read<'a, T> {
         self.0
     }
 
-    pub fn map_read<U, F>(self, f: F) -> MapRead<'a, U>
+    pub fn map_read<U: ?Sized, F>(self, f: F) -> MapRead<'a, U>
     where
         F: FnOnce(&T) -> &U,
     {
@@ -115,7 +137,7 @@ impl<'a, T> Write<'a, T> {
         self.0
     }
 
-    pub fn map_write<U, F>(self, f: F) -> MapWrite<'a, U>
+    pub fn map_write<U: ?Sized, F>(self, f: F) -> MapWrite<'a, U>
     where
         F: FnOnce(&mut T) -> &mut U,
     {
@@ -168,9 +190,9 @@ impl<'a, T> DerefMut for Write<'a, T> {
 }
 
 #[derive(Debug)]
-pub struct MapRead<'a, T>(MappedRwLockReadGuard<'a, T>);
+pub struct MapRead<'a, T: ?Sized>(MappedRwLockReadGuard<'a, T>);
 
-impl<'a, T> MapRead<'a, T> {
+impl<'a, T: ?Sized> MapRead<'a, T> {
     pub fn new<U, F>(lock: &'a Lock<U>, f: F) -> Self
     where
         F: FnOnce(&U) -> &T,
@@ -188,12 +210,19 @@ impl<'a, T> MapRead<'a, T> {
             .map(Self)
     }
 
+    pub fn filter_new<U, F>(lock: &'a Lock<U>, f: F) -> Option<Self>
+    where
+        F: FnOnce(&U) -> Option<&T>,
+    {
+        RwLockReadGuard::try_map(lock.0.read(), f).ok().map(Self)
+    }
+
     pub fn into_inner(self) -> MappedRwLockReadGuard<'a, T> {
         self.0
     }
 }
 
-impl<'a, T> Deref for MapRead<'a, T> {
+impl<'a, T: ?Sized> Deref for MapRead<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -201,16 +230,16 @@ impl<'a, T> Deref for MapRead<'a, T> {
     }
 }
 
-impl<'a, T: PartialEq> PartialEq for MapRead<'a, T> {
+impl<'a, T: ?Sized + PartialEq> PartialEq for MapRead<'a, T> {
     fn eq(&self, other: &Self) -> bool {
         *self.0 == *other.0
     }
 }
 
 #[derive(Debug)]
-pub struct MapWrite<'a, T>(MappedRwLockWriteGuard<'a, T>);
+pub struct MapWrite<'a, T: ?Sized>(MappedRwLockWriteGuard<'a, T>);
 
-impl<'a, T> MapWrite<'a, T> {
+impl<'a, T: ?Sized> MapWrite<'a, T> {
     pub fn new<U, F>(lock: &'a Lock<U>, f: F) -> Self
     where
         F: FnOnce(&mut U) -> &mut T,
@@ -228,12 +257,19 @@ impl<'a, T> MapWrite<'a, T> {
             .map(Self)
     }
 
+    pub fn filter_new<U, F>(lock: &'a Lock<U>, f: F) -> Option<Self>
+    where
+        F: FnOnce(&mut U) -> Option<&mut T>,
+    {
+        RwLockWriteGuard::try_map(lock.0.write(), f).ok().map(Self)
+    }
+
     pub fn into_inner(self) -> MappedRwLockWriteGuard<'a, T> {
         self.0
     }
 }
 
-impl<'a, T> Deref for MapWrite<'a, T> {
+impl<'a, T: ?Sized> Deref for MapWrite<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -241,13 +277,13 @@ impl<'a, T> Deref for MapWrite<'a, T> {
     }
 }
 
-impl<'a, T> DerefMut for MapWrite<'a, T> {
+impl<'a, T: ?Sized> DerefMut for MapWrite<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
 
-impl<'a, T: PartialEq> PartialEq for MapWrite<'a, T> {
+impl<'a, T: ?Sized + PartialEq> PartialEq for MapWrite<'a, T> {
     fn eq(&self, other: &Self) -> bool {
         *self.0 == *other.0
     }
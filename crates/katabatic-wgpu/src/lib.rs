@@ -1,74 +1,51 @@
-use std::sync::Arc;
-
-use katabatic_core::{app::App, plugin::Plugin, runner::Hook};
-use katabatic_util::error::KResult;
+use katabatic_core::{
+    app::App,
+    plugin::{Dependency, Plugin},
+    runner::Hook,
+};
+use katabatic_util::{error::KResult, kerror};
 use katabatic_winit::WinitPlugin;
 use winit::window::Window;
 
-pub(crate) struct WgpuPluginInner {
-    pub(crate) surface: Arc<wgpu::Surface>,
-    pub(crate) device: Arc<wgpu::Device>,
-    pub(crate) queue: Arc<wgpu::Queue>,
-}
-
+/// Sets up rendering to the window opened by [`WinitPlugin`], which it depends on.
+///
+/// The [`wgpu::Surface`], [`wgpu::Device`] and [`wgpu::Queue`] are published as resources.
 #[derive(Default)]
-pub struct WgpuPlugin {
-    pub(crate) inner: Option<WgpuPluginInner>,
-}
+pub struct WgpuPlugin;
 
 impl WgpuPlugin {
     pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn surface(&self) -> &Arc<wgpu::Surface> {
-        &self
-            .inner
-            .as_ref()
-            .expect("WgpuPlugin::surface(): Plugin not initialized")
-            .surface
-    }
-
-    pub fn device(&self) -> &Arc<wgpu::Device> {
-        &self
-            .inner
-            .as_ref()
-            .expect("WgpuPlugin::device(): Plugin not initialized")
-            .device
-    }
-
-    pub fn queue(&self) -> &Arc<wgpu::Queue> {
-        &self
-            .inner
-            .as_ref()
-            .expect("WgpuPlugin::queue(): Plugin not initialized")
-            .queue
+        Self
     }
 }
 
 impl Plugin for WgpuPlugin {
-    fn build(&mut self, app: &mut App) -> KResult<()> {
-        let winit_plugin = app
-            .get_plugin::<WinitPlugin>()
-            .expect("WgpuPlugin::build(): Winit plugin not present");
-        let window_id = winit_plugin
-            .window_id()
-            .expect("WgpuPlugin::build(): Winit plugin not initialized");
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![Dependency::on::<WinitPlugin>()]
+    }
 
+    fn build(&mut self, app: &mut App) -> KResult<()> {
         let world = app.world().read();
 
-        let window = world.get_component::<Window>(window_id.entity).unwrap();
+        let window = world
+            .resource::<Window>()
+            .ok_or_else(|| kerror!("WgpuPlugin::build(): Winit window not present"))?;
 
         let instance = wgpu::Instance::default();
 
-        let surface = unsafe { instance.create_surface(&*window) }.unwrap();
+        let surface = unsafe { instance.create_surface(&*window) }.map_err(|e| {
+            kerror!(format!(
+                "WgpuPlugin::build(): Error creating surface: {}",
+                e
+            ))
+        })?;
 
         let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::HighPerformance,
             force_fallback_adapter: false,
             compatible_surface: Some(&surface),
         }))
-        .expect("WgpuPlugin::build(): Error requesting adapter");
+        .ok_or_else(|| kerror!("WgpuPlugin::build(): Error requesting adapter"))?;
 
         let (device, queue) = pollster::block_on(adapter.request_device(
             &wgpu::DeviceDescriptor {
@@ -78,7 +55,12 @@ impl Plugin for WgpuPlugin {
             },
             None,
         ))
-        .expect("WgpuPlugin::build(): Error requesting device");
+        .map_err(|e| {
+            kerror!(format!(
+                "WgpuPlugin::build(): Error requesting device: {}",
+                e
+            ))
+        })?;
 
         let surface_caps = surface.get_capabilities(&adapter);
 
@@ -93,15 +75,15 @@ impl Plugin for WgpuPlugin {
         };
         surface.configure(&device, &config);
 
-        self.inner = Some(WgpuPluginInner {
-            surface: Arc::new(surface),
-            device: Arc::new(device),
-            queue: Arc::new(queue),
-        });
-
         drop(window);
         drop(world);
 
+        let mut world = app.world().write();
+        world.insert_resource(surface);
+        world.insert_resource(device);
+        world.insert_resource(queue);
+        drop(world);
+
         app.add_hook(WgpuRenderHook);
 
         Ok(())
@@ -112,15 +94,24 @@ pub struct WgpuRenderHook;
 
 impl Hook for WgpuRenderHook {
     fn render(&self, app: &App) -> KResult<()> {
-        let wgpu_plugin = app
-            .get_plugin::<WgpuPlugin>()
-            .expect("WgpuRenderHook::render(): Wgpu plugin not present");
-
-        let surface = wgpu_plugin.surface();
-        let device = wgpu_plugin.device();
-        let queue = wgpu_plugin.queue();
+        let world = app.world().read();
 
-        let frame = surface.get_current_texture().unwrap();
+        let surface = world
+            .resource::<wgpu::Surface>()
+            .ok_or_else(|| kerror!("WgpuRenderHook::render(): Wgpu plugin not initialized"))?;
+        let device = world
+            .resource::<wgpu::Device>()
+            .ok_or_else(|| kerror!("WgpuRenderHook::render(): Wgpu plugin not initialized"))?;
+        let queue = world
+            .resource::<wgpu::Queue>()
+            .ok_or_else(|| kerror!("WgpuRenderHook::render(): Wgpu plugin not initialized"))?;
+
+        let frame = surface.get_current_texture().map_err(|e| {
+            kerror!(format!(
+                "WgpuRenderHook::render(): Error getting the next frame: {}",
+                e
+            ))
+        })?;
 
         let view = frame
             .texture
@@ -1,39 +1,61 @@
-use std::cell::Cell;
-
-use katabatic_core::{app::App, plugin::Plugin, runner::Runner};
-use katabatic_scene::node::Node;
-use katabatic_util::error::KResult;
+use katabatic_core::{
+    app::App,
+    event::EventReader,
+    input::Input,
+    plugin::{Dependency, Plugin},
+    runner::Runner,
+    schedule::CoreStage,
+    system::ResMut,
+};
+use katabatic_util::{error::KResult, kerror};
 use winit::{
-    event::{Event, WindowEvent},
+    dpi::PhysicalSize,
+    event::{ElementState, Event, StartCause, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopBuilder},
+    platform::run_return::EventLoopExtRunReturn,
     window::Window,
 };
 
-pub struct WinitPlugin {
-    event_loop_id: Cell<Option<Node>>,
-    window_id: Cell<Option<Node>>,
+/// Sent when the window's inner size changes, in physical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowResized {
+    pub width: u32,
+    pub height: u32,
 }
 
-impl Default for WinitPlugin {
-    fn default() -> Self {
+impl From<PhysicalSize<u32>> for WindowResized {
+    fn from(size: PhysicalSize<u32>) -> Self {
         Self {
-            event_loop_id: Cell::new(None),
-            window_id: Cell::new(None),
+            width: size.width,
+            height: size.height,
         }
     }
 }
 
-impl WinitPlugin {
-    pub fn new() -> Self {
-        Self::default()
-    }
+/// Sent when the user asks to close the window. The app exits right after, so only cleanup
+/// systems get to see it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCloseRequested;
+
+/// Sent when a key is pressed or released while the window has focus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardInput {
+    pub scan_code: u32,
+    pub key_code: Option<VirtualKeyCode>,
+    pub state: ElementState,
+}
 
-    pub fn event_loop_id(&self) -> Option<Node> {
-        self.event_loop_id.get()
-    }
+/// Opens a window. The [`Window`] is published as a resource, and the [`EventLoop`] as a
+/// non-`Send` resource that [`WinitRunner`] takes over.
+///
+/// Window events are published as [`WindowResized`], [`WindowCloseRequested`] and
+/// [`KeyboardInput`] events.
+#[derive(Default)]
+pub struct WinitPlugin;
 
-    pub fn window_id(&self) -> Option<Node> {
-        self.window_id.get()
+impl WinitPlugin {
+    pub fn new() -> Self {
+        Self
     }
 }
 
@@ -41,58 +63,138 @@ impl Plugin for WinitPlugin {
     fn build(&mut self, app: &mut App) -> KResult<()> {
         let event_loop = EventLoopBuilder::new().build();
 
-        let window = Window::new(&event_loop).expect("WinitPlugin::build(): Error creating window");
-
-        let event_loop_id = app.root_scene().write().create_node_with(event_loop);
+        let window = Window::new(&event_loop).map_err(|e| {
+            kerror!(format!(
+                "WinitPlugin::build(): Error creating window: {}",
+                e
+            ))
+        })?;
+
+        let mut world = app.world().write();
+        world.insert_resource(window);
+        world.insert_non_send_resource(event_loop);
+        drop(world);
+
+        app.add_event::<WindowResized>()?;
+        app.add_event::<WindowCloseRequested>()?;
+        app.add_event::<KeyboardInput>()?;
+        app.set_runner(WinitRunner);
 
-        self.event_loop_id.set(Some(event_loop_id));
+        Ok(())
+    }
+}
 
-        let window_id = app.root_scene().write().create_node_with(window);
+/// Tracks the keyboard in an [`Input<VirtualKeyCode>`] resource, updated from [`KeyboardInput`]
+/// events in [`CoreStage::PreUpdate`].
+#[derive(Default)]
+pub struct InputPlugin;
 
-        self.window_id.set(Some(window_id));
+impl Plugin for InputPlugin {
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![Dependency::on::<WinitPlugin>()]
+    }
 
-        app.set_runner(WinitRunner);
+    fn build(&mut self, app: &mut App) -> KResult<()> {
+        app.world()
+            .write()
+            .insert_resource(Input::<VirtualKeyCode>::new());
+        app.add_system(CoreStage::PreUpdate, keyboard_input)
+    }
+}
 
-        Ok(())
+fn keyboard_input(
+    mut input: ResMut<Input<VirtualKeyCode>>,
+    mut events: EventReader<KeyboardInput>,
+) {
+    input.clear();
+    for event in events.read() {
+        if let Some(key) = event.key_code {
+            match event.state {
+                ElementState::Pressed => input.press(key),
+                ElementState::Released => input.release(key),
+            }
+        }
     }
 }
 
+/// Runs the app's update hooks once all of the window's events for a frame are handled, then
+/// requests a redraw and runs the render hooks when it comes. The event loop never waits, so
+/// frames follow one another as fast as the window can present them.
+///
+/// The loop ends when the window is closed, when an [`AppExit`](katabatic_core::app::AppExit)
+/// is sent or on the first fatal error, which is returned once the app is cleaned up.
+///
+/// How often the simulation steps doesn't depend on that; see
+/// [`FixedTime`](katabatic_core::time::FixedTime).
 #[derive(Default)]
 pub struct WinitRunner;
 
 impl Runner for WinitRunner {
-    fn run(&mut self, app: App) -> KResult<()> {
-        let plugin = app
-            .get_plugin::<WinitPlugin>()
-            .expect("WinitRunner::run(): WinitPlugin not present in App");
-
-        let event_loop_id = plugin
-            .event_loop_id()
-            .expect("WinitRunner::run(): Winit event loop not initialized");
-
-        let event_loop = app
+    fn run(&mut self, mut app: App) -> KResult<()> {
+        let mut event_loop = app
             .world()
             .write()
-            .remove_component::<EventLoop<()>>(event_loop_id.entity)
-            .unwrap();
-
-        app.run_init_hooks()?;
-
-        event_loop.run(move |event, _window, control_flow| match event {
-            Event::WindowEvent { event, .. } => {
-                if event == WindowEvent::CloseRequested {
+            .remove_non_send_resource::<EventLoop<()>>()
+            .ok_or_else(|| kerror!("WinitRunner::run(): Winit event loop not initialized"))?;
+
+        let mut result = app.startup();
+        if result.is_ok() {
+            event_loop.run_return(|event, _window, control_flow| {
+                let handled = match event {
+                    Event::NewEvents(StartCause::Init) => {
+                        *control_flow = ControlFlow::Poll;
+                        Ok(())
+                    }
+                    Event::WindowEvent {
+                        event: WindowEvent::CloseRequested,
+                        ..
+                    } => {
+                        *control_flow = ControlFlow::Exit;
+                        app.send_event(WindowCloseRequested)
+                    }
+                    Event::WindowEvent {
+                        event: WindowEvent::Resized(size),
+                        ..
+                    } => app.send_event(WindowResized::from(size)),
+                    Event::WindowEvent {
+                        event: WindowEvent::ScaleFactorChanged { new_inner_size, .. },
+                        ..
+                    } => app.send_event(WindowResized::from(*new_inner_size)),
+                    Event::WindowEvent {
+                        event: WindowEvent::KeyboardInput { input, .. },
+                        ..
+                    } => app.send_event(KeyboardInput {
+                        scan_code: input.scancode,
+                        key_code: input.virtual_keycode,
+                        state: input.state,
+                    }),
+                    // frames only poll the plugins until they're all ready
+                    Event::MainEventsCleared if !app.is_started() => app.startup(),
+                    Event::MainEventsCleared => app.run_update_hooks().map(|()| {
+                        if let Some(window) = app.world().read().resource::<Window>() {
+                            window.request_redraw();
+                        }
+                    }),
+                    Event::RedrawRequested(_) if !app.is_started() => Ok(()),
+                    Event::RedrawRequested(_) => app.run_render_hooks(),
+                    _ => Ok(()),
+                };
+
+                // errors that reach this far were already found fatal by the app's error handler
+                if let Err(error) = handled {
+                    // winit can still deliver events after the loop is told to exit; keep the
+                    // first error
+                    if result.is_ok() {
+                        result = Err(error);
+                    }
+                    *control_flow = ControlFlow::Exit;
+                } else if app.exit_requested() {
                     *control_flow = ControlFlow::Exit;
                 }
-            }
-            Event::DeviceEvent { event: _, .. } => {}
-            Event::RedrawRequested(_) => {
-                app.run_update_hooks().unwrap();
-                app.run_render_hooks().unwrap();
-            }
-            Event::LoopDestroyed => {
-                app.run_cleanup_hooks().unwrap();
-            }
-            _ => {}
-        });
+            });
+        }
+
+        let cleanup = app.cleanup();
+        result.and(cleanup)
     }
 }
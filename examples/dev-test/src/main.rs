@@ -1,11 +1,8 @@
 use std::error::Error;
 
-use katabatic::{core::app::App, wgpu::WgpuPlugin, winit::WinitPlugin};
+use katabatic::{core::app::App, plugins::DefaultPlugins};
 
 fn main() -> Result<(), Box<dyn Error>> {
-    App::new()
-        .add_plugin(WinitPlugin::new())?
-        .add_plugin(WgpuPlugin::new())?
-        .run()?;
+    App::new().add_plugins(DefaultPlugins)?.run()?;
     Ok(())
 }
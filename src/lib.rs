@@ -1,4 +1,7 @@
+pub mod plugins;
+
 pub use katabatic_core as core;
+pub use katabatic_ecs as ecs;
 pub use katabatic_scene as scene;
 pub use katabatic_util as util;
 pub use katabatic_wgpu as wgpu;
@@ -0,0 +1,28 @@
+//! The plugin groups most apps start from.
+
+use katabatic_core::{
+    logging::LogPlugin,
+    plugin::{PluginGroup, PluginGroupBuilder},
+    time::TimePlugin,
+    transform::TransformPlugin,
+};
+use katabatic_wgpu::WgpuPlugin;
+use katabatic_winit::{InputPlugin, WinitPlugin};
+
+pub use katabatic_core::plugin::MinimalPlugins;
+
+/// The plugins for an app with a window: logging, time, transform propagation, a window with
+/// keyboard input, and rendering to it.
+pub struct DefaultPlugins;
+
+impl PluginGroup for DefaultPlugins {
+    fn build(self) -> PluginGroupBuilder {
+        PluginGroupBuilder::start::<Self>()
+            .add(LogPlugin::default())
+            .add(TimePlugin)
+            .add(TransformPlugin)
+            .add(WinitPlugin::new())
+            .add(InputPlugin)
+            .add(WgpuPlugin::new())
+    }
+}